@@ -1,17 +1,162 @@
-#![allow(unexpected_cfgs)]
+#![allow(unexpected_cfgs, deprecated)]
 
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Token, TokenAccount, Mint, Transfer, CloseAccount};
+use anchor_lang::solana_program::compute_units::sol_remaining_compute_units;
+use anchor_lang::solana_program::ed25519_program;
+use anchor_lang::solana_program::program::{set_return_data, MAX_RETURN_DATA};
+use anchor_lang::solana_program::program_option::COption;
+use anchor_lang::solana_program::sysvar::instructions::{
+    load_current_index_checked, load_instruction_at_checked,
+};
+use anchor_lang::system_program;
+use anchor_spl::associated_token::{get_associated_token_address, AssociatedToken};
+use anchor_spl::memo::{self, BuildMemo, Memo};
+use anchor_spl::token::{
+    self, Token, TokenAccount, Mint, Transfer, CloseAccount, InitializeAccount3, Approve, Revoke,
+};
+use anchor_spl::token_2022::spl_token_2022::{
+    self,
+    extension::{transfer_fee::TransferFeeConfig, BaseStateWithExtensions, StateWithExtensions},
+};
+use anchor_spl::token_interface::{self, TokenInterface, TransferChecked};
+use std::collections::BTreeSet;
 
 declare_id!("Fqww93pxMsRRk2V83TpPk2GSwKc64cS8ktpXp7TpHi9");
 
+/// Longest an offer may stay open when it carries an expiry (90 days)
+pub const MAX_OFFER_LIFETIME_SECS: i64 = 90 * 24 * 60 * 60;
+
+/// Age after which anyone may return an offer's escrow to its maker (1 year)
+pub const MAX_ESCROW_AGE_SECS: i64 = 365 * 24 * 60 * 60;
+
+/// Highest offer category; 0 means uncategorized
+pub const MAX_OFFER_CATEGORY: u8 = 15;
+
+/// Fixed-point scale of `price_per_unit` in `create_offer_by_price`
+pub const PRICE_SCALE: u64 = 1_000_000;
+
+/// Oracle price age accepted when the config doesn't set one
+pub const DEFAULT_MAX_ORACLE_STALENESS_SECS: i64 = 60;
+
+/// AMM pool age, in slots, accepted when the config doesn't set one
+pub const DEFAULT_MAX_POOL_STALENESS_SLOTS: u64 = 150;
+
+/// Owner of Pyth price accounts
+pub const PYTH_ORACLE_PROGRAM_ID: Pubkey = pubkey!("FsJ3A3u2vn5cTVofAjvy6y5kwABJAqYWpe4975bi2epH");
+
+/// Longest a taker may hold a reservation on an offer (15 minutes)
+pub const MAX_RESERVATION_SECS: i64 = 15 * 60;
+
+/// How long a `commit_accept` holds an offer when the config doesn't set it
+pub const DEFAULT_ACCEPT_COMMIT_TTL_SECS: i64 = 60;
+
+/// Longest a maker may commit to not cancelling an offer (30 days)
+pub const MAX_CANCEL_LOCK_SECS: i64 = 30 * 24 * 60 * 60;
+
+/// Lamports a taker locks in their `ReservationDeposit` on top of its rent;
+/// returned when they settle in time, otherwise claimable by the owner
+pub const RESERVATION_DEPOSIT_LAMPORTS: u64 = 5_000_000;
+
+/// Basis-point denominator for protocol fees and rebates
+pub const BPS_DENOMINATOR: u16 = 10_000;
+
+/// Length of the period `extension_fee_lamports_per_day` is charged per
+pub const SECS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Time after expiry that only the maker may cancel, when the config doesn't set one
+pub const DEFAULT_EXPIRY_GRACE_SECS: i64 = 24 * 60 * 60;
+
+/// Keeper tip per second an expired offer sits unclaimed after the grace period
+pub const KEEPER_TIP_LAMPORTS_PER_SEC: u64 = 10_000;
+
+/// Most a keeper is tipped for one `cancel_expired_offer`
+pub const MAX_KEEPER_TIP_LAMPORTS: u64 = 1_000_000;
+
+/// Most offer addresses `get_user_offers` derives per call
+pub const MAX_OFFER_QUERY: u64 = 10;
+
+/// Most distinct tokens a sell bundle may escrow
+pub const MAX_BUNDLE_ITEMS: usize = 4;
+
+/// Most (mint, amount) quotes `create_offer_multi_wanted` may list
+pub const MAX_WANTED_QUOTES: usize = 4;
+
+/// Most volume tiers `Config` may hold
+pub const MAX_FEE_TIERS: usize = 4;
+
+/// Seed prefixes of the listings numbered by `UserProfile::offer_count`
+pub const COUNTER_SEEDED_PREFIXES: [&[u8]; 4] =
+    [b"offer", b"bundle", b"delegated_offer", b"sol_offer"];
+
+/// Layout version of newly written `Offer` accounts; bump it whenever the
+/// `Offer` layout changes, see `migrate_offer`
+pub const OFFER_VERSION: u8 = 2;
+
+/// Longest memo `accept_offer` will attach to a swap, in bytes
+pub const MAX_MEMO_LEN: usize = 64;
+
+/// Most takers an offer may block
+pub const MAX_BLOCKED_TAKERS: usize = 4;
+
+/// Most takers an offer's fill queue may hold
+pub const MAX_FILL_QUEUE_LEN: u8 = 8;
+
+/// Most distinct takers an offer may cap its fills at
+pub const MAX_ACCEPTORS: u8 = 8;
+
+/// Most counter-offers pending on an offer at once
+pub const MAX_COUNTER_OFFERS: usize = 4;
+
+/// Most oracle feeds an index offer may weigh together
+pub const MAX_INDEX_COMPONENTS: usize = 4;
+
+/// How long a taker served from a fill queue holds the offer (2 minutes)
+pub const FILL_QUEUE_TURN_SECS: i64 = 2 * 60;
+
+/// Longest a taker may wait in a fill queue before being skipped (1 day)
+pub const MAX_FILL_QUEUE_WAIT_SECS: i64 = 24 * 60 * 60;
+
+/// Lamports a taker locks in their `QueueTicket` while in a fill queue, on
+/// top of its rent; both are returned by `leave_fill_queue`
+pub const FILL_QUEUE_DEPOSIT_LAMPORTS: u64 = 10_000_000;
+
+/// Most offers `accept_offers_batch` may fill, keeping it within compute limits
+pub const MAX_BATCH_SIZE: usize = 4;
+
+/// Compute units budgeted per `accept_offers_batch` entry, a conservative
+/// estimate of one full accept
+pub const BATCH_ENTRY_COMPUTE_UNITS: u64 = 60_000;
+
+/// Compute units budgeted per sell bundle item: its vault's creation or
+/// close and one token transfer
+pub const BUNDLE_ITEM_COMPUTE_UNITS: u64 = 25_000;
+
+/// Most profiles `initialize_users_batch` may create, keeping the transaction
+/// within its account limit
+pub const MAX_PROFILE_BATCH: usize = 8;
+
+/// Most offers `batch_inspect` may summarize within the return data limit
+pub const MAX_INSPECT_BATCH: usize = (MAX_RETURN_DATA - 4) / OfferSummary::SIZE;
+
+/// Vault seed marking a mint's shared omnibus vault, see `omnibus_vault`
+pub const OMNIBUS_VAULT_SEED: &[u8] = b"omnibus";
+
+/// Most a matched offer's escrow may exceed its counterpart's ask, in bps
+pub const MATCH_TOLERANCE_BPS: u16 = 100;
+
 #[program]
 pub mod p2p_swap {
     use super::*;
 
-    /// Initialize a new user profile to track offer count
+    /// Initialize a new user profile to track offer count. Calling it again
+    /// for an existing profile is a no-op.
     pub fn initialize_user(ctx: Context<InitializeUser>) -> Result<()> {
         let user_profile = &mut ctx.accounts.user_profile;
+        if !user_profile.is_fresh() {
+            msg!("User profile already initialized for {}", user_profile.authority);
+            return Ok(());
+        }
+
         user_profile.authority = ctx.accounts.authority.key();
         user_profile.offer_count = 0;
 
@@ -19,389 +164,11489 @@ pub mod p2p_swap {
         Ok(())
     }
 
-    /// Create a new swap offer by locking tokens in escrow
-    pub fn create_offer(
-        ctx: Context<CreateOffer>,
-        amount_offered: u64,
-        amount_wanted: u64,
+    /// Pre-create user profiles for many users at once, e.g. for a migration
+    /// or airdrop, with the admin paying the rent. `remaining_accounts` holds
+    /// an (authority, user profile) pair per user. Profiles that already
+    /// exist are skipped when `skip_existing` is set and fail the batch
+    /// otherwise.
+    pub fn initialize_users_batch<'info>(
+        ctx: Context<'_, '_, 'info, 'info, InitializeUsersBatch<'info>>,
+        skip_existing: bool,
     ) -> Result<()> {
-        // Validate amounts
-        require!(amount_offered > 0, ErrorCode::InvalidAmount);
-        require!(amount_wanted > 0, ErrorCode::InvalidAmount);
+        let pairs = ctx.remaining_accounts.chunks_exact(2);
+        require!(pairs.remainder().is_empty(), ErrorCode::InvalidUserProfile);
+        require!(pairs.len() > 0, ErrorCode::InvalidAmount);
+        require!(pairs.len() <= MAX_PROFILE_BATCH, ErrorCode::BatchTooLarge);
 
-        let user_profile = &mut ctx.accounts.user_profile;
-        let offer = &mut ctx.accounts.offer;
-        let clock = Clock::get()?;
+        let rent = Rent::get()?;
+        let space = 8 + UserProfile::SIZE;
+        let mut created = 0;
+        for pair in pairs {
+            let (authority, profile_info) = (&pair[0], &pair[1]);
+            let (profile_key, bump) = Pubkey::find_program_address(
+                &[b"user_profile", authority.key.as_ref()],
+                ctx.program_id,
+            );
+            require_keys_eq!(profile_info.key(), profile_key, ErrorCode::InvalidUserProfile);
+
+            if !profile_info.data_is_empty() {
+                require!(skip_existing, ErrorCode::ProfileAlreadyExists);
+                msg!("User profile already initialized for {}", authority.key);
+                continue;
+            }
+
+            let seeds = &[b"user_profile", authority.key.as_ref(), &[bump]];
+            let signer = &[&seeds[..]];
+            let cpi_accounts = system_program::CreateAccount {
+                from: ctx.accounts.admin.to_account_info(),
+                to: profile_info.clone(),
+            };
+            let cpi_program = ctx.accounts.system_program.to_account_info();
+            system_program::create_account(
+                CpiContext::new_with_signer(cpi_program, cpi_accounts, signer),
+                rent.minimum_balance(space),
+                space as u64,
+                ctx.program_id,
+            )?;
 
-        // Initialize UserProfile if this is first time
-        if user_profile.offer_count == 0 && user_profile.authority == Pubkey::default() {
-            user_profile.authority = ctx.accounts.maker.key();
-            msg!("Auto-initialized user profile for {}", user_profile.authority);
+            let user_profile = UserProfile {
+                authority: authority.key(),
+                offer_count: 0,
+                last_offer_ts: 0,
+                last_cancel_ts: 0,
+                open_offers: 0,
+            };
+            user_profile.try_serialize(&mut &mut profile_info.try_borrow_mut_data()?[..])?;
+            created += 1;
         }
 
-        // Get current offer ID and increment counter
-        let offer_id = user_profile.offer_count;
-        user_profile.offer_count = user_profile
-            .offer_count
+        msg!("Initialized {} user profiles", created);
+        Ok(())
+    }
+
+    /// Close the caller's user profile and reclaim its rent. Refused while
+    /// any offer still counts against it, so no offer is left without the
+    /// profile its cancel and accept paths expect.
+    pub fn close_user_profile(ctx: Context<CloseUserProfile>) -> Result<()> {
+        msg!("User profile closed for {}", ctx.accounts.authority.key());
+        Ok(())
+    }
+
+    /// Move the caller's offer counter past an ID whose listing still
+    /// exists. A recreated profile counts from 0 again, while offers handed
+    /// to another owner before the old profile closed keep their addresses;
+    /// the create handlers refuse those IDs with `SlotInUse` until skipped.
+    pub fn skip_offer_id(ctx: Context<SkipOfferId>) -> Result<()> {
+        let user_profile = &mut ctx.accounts.user_profile;
+        let skipped = user_profile.offer_count;
+        let authority = ctx.accounts.authority.key();
+        let listing = ctx.accounts.listing.key();
+        let taken = COUNTER_SEEDED_PREFIXES.iter().any(|prefix| {
+            let seeds: &[&[u8]] = &[prefix, authority.as_ref(), &skipped.to_le_bytes()];
+            Pubkey::find_program_address(seeds, ctx.program_id).0 == listing
+        });
+        require!(taken, ErrorCode::InvalidOfferId);
+        user_profile.offer_count = skipped
             .checked_add(1)
             .ok_or(ErrorCode::CounterOverflow)?;
 
-        // Initialize offer account
-        offer.offer_id = offer_id;
-        offer.maker = ctx.accounts.maker.key();
-        offer.mint_offered = ctx.accounts.mint_offered.key();
-        offer.mint_wanted = ctx.accounts.mint_wanted.key();
-        offer.amount_offered = amount_offered;
-        offer.amount_wanted = amount_wanted;
-        offer.vault_bump = ctx.bumps.vault;
-        offer.bump = ctx.bumps.offer;
-        offer.created_at = clock.unix_timestamp;
-
-        // Transfer tokens from maker to vault
-        let cpi_accounts = Transfer {
-            from: ctx.accounts.maker_token_account.to_account_info(),
-            to: ctx.accounts.vault.to_account_info(),
-            authority: ctx.accounts.maker.to_account_info(),
-        };
-        let cpi_program = ctx.accounts.token_program.to_account_info();
-        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
-        token::transfer(cpi_ctx, amount_offered)?;
+        msg!("Offer ID {} skipped for {}", skipped, user_profile.authority);
+        Ok(())
+    }
 
-        msg!(
-            "Offer {} created: {} {} for {} {}",
-            offer_id,
-            amount_offered,
-            offer.mint_offered,
-            amount_wanted,
-            offer.mint_wanted
-        );
+    /// Create the protocol config; the signer becomes its admin
+    pub fn initialize_config(
+        ctx: Context<InitializeConfig>,
+        params: ConfigParams,
+    ) -> Result<()> {
+        params.validate()?;
 
+        let config = &mut ctx.accounts.config;
+        config.admin = ctx.accounts.admin.key();
+        config.bump = ctx.bumps.config;
+        params.apply(config);
+
+        msg!("Config initialized by {}", config.admin);
         Ok(())
     }
 
-    /// Accept an offer and execute atomic token swap
-    pub fn accept_offer(ctx: Context<AcceptOffer>, _offer_id: u64) -> Result<()> {
-        let offer = &ctx.accounts.offer;
+    /// Replace the protocol config settings (admin only)
+    pub fn update_config(ctx: Context<UpdateConfig>, params: ConfigParams) -> Result<()> {
+        params.validate()?;
+        params.apply(&mut ctx.accounts.config);
 
-        // Validate token mints match the offer
-        require!(
-            ctx.accounts.mint_offered.key() == offer.mint_offered,
-            ErrorCode::InvalidMint
-        );
-        require!(
-            ctx.accounts.mint_wanted.key() == offer.mint_wanted,
-            ErrorCode::InvalidMint
-        );
+        msg!("Config updated");
+        Ok(())
+    }
 
-        // Transfer wanted tokens from taker to maker
-        let cpi_accounts = Transfer {
-            from: ctx.accounts.taker_token_account_offered.to_account_info(),
-            to: ctx.accounts.maker_token_account_wanted.to_account_info(),
-            authority: ctx.accounts.taker.to_account_info(),
-        };
-        let cpi_program = ctx.accounts.token_program.to_account_info();
-        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
-        token::transfer(cpi_ctx, offer.amount_wanted)?;
+    /// Close the protocol config, refunding its rent to the admin, so that it
+    /// can be created afresh with `initialize_config` (admin only)
+    pub fn close_config(ctx: Context<CloseConfig>) -> Result<()> {
+        msg!("Config closed by {}", ctx.accounts.admin.key());
+        Ok(())
+    }
 
-        // Transfer offered tokens from vault to taker using PDA signer
-        let offer_key = offer.key();
-        let mint_key = offer.mint_offered;
-        let seeds = &[
-            b"vault",
-            offer_key.as_ref(),
-            mint_key.as_ref(),
-            &[offer.vault_bump],
-        ];
-        let signer = &[&seeds[..]];
+    /// Declare a protocol-wide emergency (admin only): accepts stop and any
+    /// open offer may be returned to its owner by anyone through
+    /// `emergency_reclaim`
+    pub fn declare_emergency(ctx: Context<UpdateConfig>) -> Result<()> {
+        ctx.accounts.config.emergency = true;
 
-        let cpi_accounts = Transfer {
-            from: ctx.accounts.vault.to_account_info(),
-            to: ctx.accounts.taker_token_account_wanted.to_account_info(),
-            authority: ctx.accounts.vault.to_account_info(),
-        };
-        let cpi_program = ctx.accounts.token_program.to_account_info();
-        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
-        token::transfer(cpi_ctx, offer.amount_offered)?;
+        msg!("Emergency declared by {}", ctx.accounts.admin.key());
+        Ok(())
+    }
 
-        // Close vault token account (refund rent to maker)
-        let cpi_accounts = CloseAccount {
-            account: ctx.accounts.vault.to_account_info(),
-            destination: ctx.accounts.maker.to_account_info(),
-            authority: ctx.accounts.vault.to_account_info(),
-        };
-        let cpi_program = ctx.accounts.token_program.to_account_info();
-        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
-        token::close_account(cpi_ctx)?;
+    /// End a declared emergency, letting accepts resume (admin only)
+    pub fn lift_emergency(ctx: Context<UpdateConfig>) -> Result<()> {
+        ctx.accounts.config.emergency = false;
 
-        msg!(
-            "Offer {} accepted by {}",
-            offer.offer_id,
-            ctx.accounts.taker.key()
-        );
+        msg!("Emergency lifted by {}", ctx.accounts.admin.key());
+        Ok(())
+    }
+
+    /// Nominate a new admin; takes effect once they call `accept_admin`
+    pub fn propose_admin(ctx: Context<UpdateConfig>, new_admin: Pubkey) -> Result<()> {
+        ctx.accounts.config.pending_admin = Some(new_admin);
 
+        msg!("Admin transfer to {} proposed", new_admin);
         Ok(())
     }
 
-    /// Cancel an offer and return tokens to maker
-    pub fn cancel_offer(ctx: Context<CancelOffer>, _offer_id: u64) -> Result<()> {
-        let offer = &ctx.accounts.offer;
+    /// Complete a pending admin transfer (pending admin only)
+    pub fn accept_admin(ctx: Context<AcceptAdmin>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        require!(
+            config.pending_admin == Some(ctx.accounts.new_admin.key()),
+            ErrorCode::Unauthorized
+        );
 
-        // Transfer tokens from vault back to maker using PDA signer
-        let offer_key = offer.key();
-        let mint_key = offer.mint_offered;
-        let seeds = &[
-            b"vault",
-            offer_key.as_ref(),
-            mint_key.as_ref(),
-            &[offer.vault_bump],
-        ];
-        let signer = &[&seeds[..]];
+        config.admin = ctx.accounts.new_admin.key();
+        config.pending_admin = None;
 
-        let cpi_accounts = Transfer {
-            from: ctx.accounts.vault.to_account_info(),
-            to: ctx.accounts.maker_token_account.to_account_info(),
-            authority: ctx.accounts.vault.to_account_info(),
-        };
-        let cpi_program = ctx.accounts.token_program.to_account_info();
-        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
-        token::transfer(cpi_ctx, offer.amount_offered)?;
+        msg!("Admin transferred to {}", config.admin);
+        Ok(())
+    }
 
-        // Close vault token account (refund rent to maker)
-        let cpi_accounts = CloseAccount {
-            account: ctx.accounts.vault.to_account_info(),
-            destination: ctx.accounts.maker.to_account_info(),
-            authority: ctx.accounts.vault.to_account_info(),
-        };
-        let cpi_program = ctx.accounts.token_program.to_account_info();
-        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
-        token::close_account(cpi_ctx)?;
+    /// Approve or revoke a maker for listing while `config.maker_allowlist`
+    /// is on (admin only)
+    pub fn approve_maker(ctx: Context<ApproveMaker>, approved: bool) -> Result<()> {
+        let maker_status = &mut ctx.accounts.maker_status;
+        maker_status.maker = ctx.accounts.maker.key();
+        maker_status.approved = approved;
+        maker_status.bump = ctx.bumps.maker_status;
 
-        msg!("Offer {} cancelled", offer.offer_id);
+        msg!("Maker {} approved: {}", maker_status.maker, approved);
+        Ok(())
+    }
+
+    /// Allow a mint to be offered or wanted while `config.mint_allowlist`
+    /// is on (admin only)
+    pub fn add_allowed_mint(ctx: Context<AddAllowedMint>) -> Result<()> {
+        let allowed_mint = &mut ctx.accounts.allowed_mint;
+        allowed_mint.mint = ctx.accounts.mint.key();
+        allowed_mint.bump = ctx.bumps.allowed_mint;
 
+        msg!("Mint {} allowed", allowed_mint.mint);
         Ok(())
     }
-}
 
-// ============================================================================
-// Account Structures
-// ============================================================================
+    /// Drop a mint from the allowlist, refunding the entry's rent to the
+    /// admin (admin only). Open offers in the mint are left alone.
+    pub fn remove_allowed_mint(ctx: Context<RemoveAllowedMint>) -> Result<()> {
+        msg!("Mint {} no longer allowed", ctx.accounts.allowed_mint.mint);
+        Ok(())
+    }
 
-#[derive(Accounts)]
-pub struct InitializeUser<'info> {
-    #[account(
-        init,
-        payer = authority,
-        space = 8 + UserProfile::SIZE,
-        seeds = [b"user_profile", authority.key().as_ref()],
-        bump
-    )]
-    pub user_profile: Account<'info, UserProfile>,
+    /// Pause or resume trading of a single mint (admin only). Offers in a
+    /// paused mint can neither be created nor accepted, but can still be
+    /// cancelled.
+    pub fn set_mint_paused(ctx: Context<SetMintPaused>, paused: bool) -> Result<()> {
+        let mint_status = &mut ctx.accounts.mint_status;
+        mint_status.mint = ctx.accounts.mint.key();
+        mint_status.paused = paused;
+        mint_status.bump = ctx.bumps.mint_status;
 
-    #[account(mut)]
-    pub authority: Signer<'info>,
+        msg!("Mint {} paused: {}", mint_status.mint, paused);
+        Ok(())
+    }
 
-    pub system_program: Program<'info, System>,
-}
+    /// Create a new swap offer by locking tokens in escrow
+    pub fn create_offer(
+        ctx: Context<CreateOffer>,
+        amount_offered: u64,
+        amount_wanted: u64,
+        options: OfferOptions,
+    ) -> Result<()> {
+        let offer_bump = ctx.bumps.offer;
+        let vault_bump = ctx.bumps.vault;
+        let accounts = ctx.accounts;
+        open_offer(
+            &mut accounts.user_profile,
+            &mut accounts.offer,
+            &mut accounts.vault,
+            &accounts.maker_token_account,
+            &accounts.mint_offered,
+            &accounts.mint_wanted,
+            &accounts.maker.to_account_info(),
+            &accounts.maker.to_account_info(),
+            &[],
+            &accounts.token_program,
+            &accounts.system_program,
+            Some(&accounts.rent),
+            &OfferGates {
+                config: &accounts.config,
+                mint_tvl: &accounts.mint_tvl,
+                mint_tvl_bump: ctx.bumps.mint_tvl,
+                payer: &accounts.maker.to_account_info(),
+                mint_status_offered: &accounts.mint_status_offered,
+                mint_status_wanted: &accounts.mint_status_wanted,
+                maker_status: accounts.maker_status.as_ref(),
+            },
+            amount_offered,
+            amount_wanted,
+            &options,
+            None,
+            offer_bump,
+            vault_bump,
+            false,
+        )?;
+        check_mints_allowed(
+            &accounts.config,
+            accounts.allowed_mint_offered.as_ref(),
+            accounts.allowed_mint_wanted.as_ref(),
+        )?;
+        collect_listing_fee(
+            &accounts.config,
+            &accounts.maker.to_account_info(),
+            accounts.treasury.as_ref(),
+            &accounts.system_program,
+        )?;
 
-#[derive(Accounts)]
-pub struct CreateOffer<'info> {
-    #[account(
-        init_if_needed,
-        payer = maker,
-        space = 8 + UserProfile::SIZE,
-        seeds = [b"user_profile", maker.key().as_ref()],
-        bump
-    )]
-    pub user_profile: Account<'info, UserProfile>,
+        Ok(())
+    }
 
-    #[account(
-        init,
-        payer = maker,
-        space = 8 + Offer::SIZE,
-        seeds = [
-            b"offer",
-            maker.key().as_ref(),
-            &user_profile.offer_count.to_le_bytes(),
-        ],
-        bump
-    )]
-    pub offer: Account<'info, Offer>,
+    /// Create a buyback/rebate offer wanting the offered token itself back at
+    /// a different amount. Same-mint offers are refused everywhere else.
+    pub fn create_rebate_offer(
+        ctx: Context<CreateOffer>,
+        amount_offered: u64,
+        amount_wanted: u64,
+        options: OfferOptions,
+    ) -> Result<()> {
+        require_keys_eq!(
+            ctx.accounts.mint_offered.key(),
+            ctx.accounts.mint_wanted.key(),
+            ErrorCode::InvalidMint
+        );
+        require!(amount_offered != amount_wanted, ErrorCode::InvalidAmount);
 
-    #[account(
-        init,
-        payer = maker,
-        seeds = [
-            b"vault",
-            offer.key().as_ref(),
-            mint_offered.key().as_ref(),
-        ],
-        bump,
-        token::mint = mint_offered,
-        token::authority = vault,
-    )]
-    pub vault: Account<'info, TokenAccount>,
+        let offer_bump = ctx.bumps.offer;
+        let vault_bump = ctx.bumps.vault;
+        let accounts = ctx.accounts;
+        open_offer(
+            &mut accounts.user_profile,
+            &mut accounts.offer,
+            &mut accounts.vault,
+            &accounts.maker_token_account,
+            &accounts.mint_offered,
+            &accounts.mint_wanted,
+            &accounts.maker.to_account_info(),
+            &accounts.maker.to_account_info(),
+            &[],
+            &accounts.token_program,
+            &accounts.system_program,
+            Some(&accounts.rent),
+            &OfferGates {
+                config: &accounts.config,
+                mint_tvl: &accounts.mint_tvl,
+                mint_tvl_bump: ctx.bumps.mint_tvl,
+                payer: &accounts.maker.to_account_info(),
+                mint_status_offered: &accounts.mint_status_offered,
+                mint_status_wanted: &accounts.mint_status_wanted,
+                maker_status: accounts.maker_status.as_ref(),
+            },
+            amount_offered,
+            amount_wanted,
+            &options,
+            None,
+            offer_bump,
+            vault_bump,
+            true,
+        )?;
+        check_mints_allowed(
+            &accounts.config,
+            accounts.allowed_mint_offered.as_ref(),
+            accounts.allowed_mint_wanted.as_ref(),
+        )?;
+        collect_listing_fee(
+            &accounts.config,
+            &accounts.maker.to_account_info(),
+            accounts.treasury.as_ref(),
+            &accounts.system_program,
+        )?;
 
-    #[account(
-        mut,
-        constraint = maker_token_account.mint == mint_offered.key() @ ErrorCode::InvalidMint,
-        constraint = maker_token_account.owner == maker.key() @ ErrorCode::Unauthorized,
-    )]
-    pub maker_token_account: Account<'info, TokenAccount>,
+        Ok(())
+    }
 
-    pub mint_offered: Account<'info, Mint>,
-    pub mint_wanted: Account<'info, Mint>,
+    /// Create a new swap offer priced per unit of the offered token rather
+    /// than by total; `amount_wanted` is derived as
+    /// `amount_offered * price_per_unit / PRICE_SCALE`
+    pub fn create_offer_by_price(
+        ctx: Context<CreateOffer>,
+        amount_offered: u64,
+        price_per_unit: u64,
+        options: OfferOptions,
+    ) -> Result<()> {
+        let amount_wanted = mul_div(amount_offered, price_per_unit, PRICE_SCALE, false)?;
+        require!(amount_wanted > 0, ErrorCode::InvalidAmount);
 
-    #[account(mut)]
-    pub maker: Signer<'info>,
+        let offer_bump = ctx.bumps.offer;
+        let vault_bump = ctx.bumps.vault;
+        let accounts = ctx.accounts;
+        open_offer(
+            &mut accounts.user_profile,
+            &mut accounts.offer,
+            &mut accounts.vault,
+            &accounts.maker_token_account,
+            &accounts.mint_offered,
+            &accounts.mint_wanted,
+            &accounts.maker.to_account_info(),
+            &accounts.maker.to_account_info(),
+            &[],
+            &accounts.token_program,
+            &accounts.system_program,
+            Some(&accounts.rent),
+            &OfferGates {
+                config: &accounts.config,
+                mint_tvl: &accounts.mint_tvl,
+                mint_tvl_bump: ctx.bumps.mint_tvl,
+                payer: &accounts.maker.to_account_info(),
+                mint_status_offered: &accounts.mint_status_offered,
+                mint_status_wanted: &accounts.mint_status_wanted,
+                maker_status: accounts.maker_status.as_ref(),
+            },
+            amount_offered,
+            amount_wanted,
+            &options,
+            None,
+            offer_bump,
+            vault_bump,
+            false,
+        )?;
+        check_mints_allowed(
+            &accounts.config,
+            accounts.allowed_mint_offered.as_ref(),
+            accounts.allowed_mint_wanted.as_ref(),
+        )?;
+        collect_listing_fee(
+            &accounts.config,
+            &accounts.maker.to_account_info(),
+            accounts.treasury.as_ref(),
+            &accounts.system_program,
+        )?;
 
-    pub token_program: Program<'info, Token>,
-    pub system_program: Program<'info, System>,
-    pub rent: Sysvar<'info, Rent>,
-}
+        Ok(())
+    }
 
-#[derive(Accounts)]
-#[instruction(offer_id: u64)]
-pub struct AcceptOffer<'info> {
-    #[account(
-        mut,
-        seeds = [
-            b"offer",
-            maker.key().as_ref(),
-            &offer_id.to_le_bytes(),
-        ],
-        bump = offer.bump,
-        close = maker,
-        has_one = maker @ ErrorCode::Unauthorized,
-    )]
-    pub offer: Account<'info, Offer>,
+    /// Create a new swap offer whose wanted amount floats with a Pyth price
+    /// feed: on every acceptance it is recomputed as the oracle price of the
+    /// offered token times `multiplier_bps`
+    pub fn create_oracle_offer(
+        ctx: Context<CreateOracleOffer>,
+        amount_offered: u64,
+        multiplier_bps: u16,
+        options: OfferOptions,
+    ) -> Result<()> {
+        require!(multiplier_bps > 0, ErrorCode::InvalidAmount);
 
-    #[account(
-        mut,
-        seeds = [
+        let clock = Clock::get()?;
+        let max_staleness = oracle_staleness(&ctx.accounts.config)?;
+        let price = load_pyth_price(
+            &ctx.accounts.price_feed,
+            clock.unix_timestamp,
+            max_staleness,
+        )?;
+        let amount_wanted = oracle_amount_wanted(
+            amount_offered,
+            &price,
+            multiplier_bps,
+            ctx.accounts.mint_offered.decimals,
+            ctx.accounts.mint_wanted.decimals,
+        )?;
+
+        let offer_bump = ctx.bumps.offer;
+        let vault_bump = ctx.bumps.vault;
+        let accounts = ctx.accounts;
+        open_offer(
+            &mut accounts.user_profile,
+            &mut accounts.offer,
+            &mut accounts.vault,
+            &accounts.maker_token_account,
+            &accounts.mint_offered,
+            &accounts.mint_wanted,
+            &accounts.maker.to_account_info(),
+            &accounts.maker.to_account_info(),
+            &[],
+            &accounts.token_program,
+            &accounts.system_program,
+            Some(&accounts.rent),
+            &OfferGates {
+                config: &accounts.config,
+                mint_tvl: &accounts.mint_tvl,
+                mint_tvl_bump: ctx.bumps.mint_tvl,
+                payer: &accounts.maker.to_account_info(),
+                mint_status_offered: &accounts.mint_status_offered,
+                mint_status_wanted: &accounts.mint_status_wanted,
+                maker_status: accounts.maker_status.as_ref(),
+            },
+            amount_offered,
+            amount_wanted,
+            &options,
+            None,
+            offer_bump,
+            vault_bump,
+            false,
+        )?;
+        check_mints_allowed(
+            &accounts.config,
+            accounts.allowed_mint_offered.as_ref(),
+            accounts.allowed_mint_wanted.as_ref(),
+        )?;
+        collect_listing_fee(
+            &accounts.config,
+            &accounts.maker.to_account_info(),
+            accounts.treasury.as_ref(),
+            &accounts.system_program,
+        )?;
+
+        accounts.offer.price_feed = Some(accounts.price_feed.key());
+        accounts.offer.multiplier_bps = multiplier_bps;
+
+        Ok(())
+    }
+
+    /// Create a swap offer priced off a weighted basket of Pyth price feeds:
+    /// the wanted amount is the `weights`-weighted average of what each feed
+    /// prices the escrow at, times `multiplier_bps`. The feeds are passed as
+    /// remaining accounts in the order of `weights`, which must sum to
+    /// 10_000. Such offers are taken through `accept_index_offer`.
+    pub fn create_index_offer<'info>(
+        ctx: Context<'_, '_, '_, 'info, CreateIndexOffer<'info>>,
+        amount_offered: u64,
+        multiplier_bps: u16,
+        options: OfferOptions,
+        weights: Vec<u16>,
+    ) -> Result<()> {
+        require!(multiplier_bps > 0, ErrorCode::InvalidAmount);
+
+        let components = index_components(&weights, ctx.remaining_accounts)?;
+        let clock = Clock::get()?;
+        let max_staleness = oracle_staleness(&ctx.accounts.config)?;
+        let amount_wanted = index_amount_wanted(
+            &components,
+            ctx.remaining_accounts,
+            amount_offered,
+            multiplier_bps,
+            ctx.accounts.mint_offered.decimals,
+            ctx.accounts.mint_wanted.decimals,
+            clock.unix_timestamp,
+            max_staleness,
+        )?;
+
+        let offer_bump = ctx.bumps.offer;
+        let vault_bump = ctx.bumps.vault;
+        let accounts = ctx.accounts;
+        open_offer(
+            &mut accounts.user_profile,
+            &mut accounts.offer,
+            &mut accounts.vault,
+            &accounts.maker_token_account,
+            &accounts.mint_offered,
+            &accounts.mint_wanted,
+            &accounts.maker.to_account_info(),
+            &accounts.maker.to_account_info(),
+            &[],
+            &accounts.token_program,
+            &accounts.system_program,
+            Some(&accounts.rent),
+            &OfferGates {
+                config: &accounts.config,
+                mint_tvl: &accounts.mint_tvl,
+                mint_tvl_bump: ctx.bumps.mint_tvl,
+                payer: &accounts.maker.to_account_info(),
+                mint_status_offered: &accounts.mint_status_offered,
+                mint_status_wanted: &accounts.mint_status_wanted,
+                maker_status: accounts.maker_status.as_ref(),
+            },
+            amount_offered,
+            amount_wanted,
+            &options,
+            None,
+            offer_bump,
+            vault_bump,
+            false,
+        )?;
+        check_mints_allowed(
+            &accounts.config,
+            accounts.allowed_mint_offered.as_ref(),
+            accounts.allowed_mint_wanted.as_ref(),
+        )?;
+        collect_listing_fee(
+            &accounts.config,
+            &accounts.maker.to_account_info(),
+            accounts.treasury.as_ref(),
+            &accounts.system_program,
+        )?;
+
+        accounts.offer.index_components = components;
+        accounts.offer.multiplier_bps = multiplier_bps;
+
+        Ok(())
+    }
+
+    /// Create an offer whose wanted amount tracks the spot price of a
+    /// constant-product AMM pool pairing the two mints, marked up by
+    /// `spread_bps`. The amount is recomputed from the pool's reserves on
+    /// every accept.
+    pub fn create_amm_offer(
+        ctx: Context<CreateAmmOffer>,
+        amount_offered: u64,
+        spread_bps: u16,
+        options: OfferOptions,
+    ) -> Result<()> {
+        let multiplier_bps = BPS_DENOMINATOR
+            .checked_add(spread_bps)
+            .ok_or(ErrorCode::InvalidAmount)?;
+
+        let clock = Clock::get()?;
+        let reserves = load_pool_reserves(
+            &ctx.accounts.amm_pool,
+            &ctx.accounts.config,
+            ctx.accounts.mint_offered.key(),
+            ctx.accounts.mint_wanted.key(),
+            clock.slot,
+        )?;
+        let amount_wanted = pool_amount_wanted(amount_offered, &reserves, multiplier_bps)?;
+
+        let offer_bump = ctx.bumps.offer;
+        let vault_bump = ctx.bumps.vault;
+        let accounts = ctx.accounts;
+        open_offer(
+            &mut accounts.user_profile,
+            &mut accounts.offer,
+            &mut accounts.vault,
+            &accounts.maker_token_account,
+            &accounts.mint_offered,
+            &accounts.mint_wanted,
+            &accounts.maker.to_account_info(),
+            &accounts.maker.to_account_info(),
+            &[],
+            &accounts.token_program,
+            &accounts.system_program,
+            Some(&accounts.rent),
+            &OfferGates {
+                config: &accounts.config,
+                mint_tvl: &accounts.mint_tvl,
+                mint_tvl_bump: ctx.bumps.mint_tvl,
+                payer: &accounts.maker.to_account_info(),
+                mint_status_offered: &accounts.mint_status_offered,
+                mint_status_wanted: &accounts.mint_status_wanted,
+                maker_status: accounts.maker_status.as_ref(),
+            },
+            amount_offered,
+            amount_wanted,
+            &options,
+            None,
+            offer_bump,
+            vault_bump,
+            false,
+        )?;
+        check_mints_allowed(
+            &accounts.config,
+            accounts.allowed_mint_offered.as_ref(),
+            accounts.allowed_mint_wanted.as_ref(),
+        )?;
+        collect_listing_fee(
+            &accounts.config,
+            &accounts.maker.to_account_info(),
+            accounts.treasury.as_ref(),
+            &accounts.system_program,
+        )?;
+
+        accounts.offer.amm_pool = Some(accounts.amm_pool.key());
+        accounts.offer.multiplier_bps = multiplier_bps;
+
+        Ok(())
+    }
+
+    /// Create a new swap offer whose PDA is derived from a caller-supplied
+    /// nonce instead of the profile counter, so clients can know the address
+    /// up front. Reusing a nonce fails on the `init` constraint.
+    pub fn create_offer_with_nonce(
+        ctx: Context<CreateOfferWithNonce>,
+        nonce: [u8; 16],
+        amount_offered: u64,
+        amount_wanted: u64,
+        options: OfferOptions,
+    ) -> Result<()> {
+        let offer_bump = ctx.bumps.offer;
+        let vault_bump = ctx.bumps.vault;
+        let accounts = ctx.accounts;
+        open_offer(
+            &mut accounts.user_profile,
+            &mut accounts.offer,
+            &mut accounts.vault,
+            &accounts.maker_token_account,
+            &accounts.mint_offered,
+            &accounts.mint_wanted,
+            &accounts.maker.to_account_info(),
+            &accounts.maker.to_account_info(),
+            &[],
+            &accounts.token_program,
+            &accounts.system_program,
+            Some(&accounts.rent),
+            &OfferGates {
+                config: &accounts.config,
+                mint_tvl: &accounts.mint_tvl,
+                mint_tvl_bump: ctx.bumps.mint_tvl,
+                payer: &accounts.maker.to_account_info(),
+                mint_status_offered: &accounts.mint_status_offered,
+                mint_status_wanted: &accounts.mint_status_wanted,
+                maker_status: accounts.maker_status.as_ref(),
+            },
+            amount_offered,
+            amount_wanted,
+            &options,
+            Some(nonce),
+            offer_bump,
+            vault_bump,
+            false,
+        )?;
+        check_mints_allowed(
+            &accounts.config,
+            accounts.allowed_mint_offered.as_ref(),
+            accounts.allowed_mint_wanted.as_ref(),
+        )?;
+        collect_listing_fee(
+            &accounts.config,
+            &accounts.maker.to_account_info(),
+            accounts.treasury.as_ref(),
+            &accounts.system_program,
+        )?;
+
+        Ok(())
+    }
+
+    /// Create an offer for a maker that can sign but not pay, such as a
+    /// multisig or DAO treasury PDA signing through a CPI from its
+    /// controlling program. `payer` funds the accounts and any listing fee;
+    /// the escrow still comes from the maker's own token account. The offer
+    /// is cancelled through `cancel_offer`, again signed by the maker via CPI,
+    /// and its rent is refunded to the maker.
+    pub fn create_pda_offer(
+        ctx: Context<CreatePdaOffer>,
+        amount_offered: u64,
+        amount_wanted: u64,
+        options: OfferOptions,
+    ) -> Result<()> {
+        let offer_bump = ctx.bumps.offer;
+        let vault_bump = ctx.bumps.vault;
+        let accounts = ctx.accounts;
+        open_offer(
+            &mut accounts.user_profile,
+            &mut accounts.offer,
+            &mut accounts.vault,
+            &accounts.maker_token_account,
+            &accounts.mint_offered,
+            &accounts.mint_wanted,
+            &accounts.maker.to_account_info(),
+            &accounts.maker.to_account_info(),
+            &[],
+            &accounts.token_program,
+            &accounts.system_program,
+            Some(&accounts.rent),
+            &OfferGates {
+                config: &accounts.config,
+                mint_tvl: &accounts.mint_tvl,
+                mint_tvl_bump: ctx.bumps.mint_tvl,
+                payer: &accounts.payer.to_account_info(),
+                mint_status_offered: &accounts.mint_status_offered,
+                mint_status_wanted: &accounts.mint_status_wanted,
+                maker_status: accounts.maker_status.as_ref(),
+            },
+            amount_offered,
+            amount_wanted,
+            &options,
+            None,
+            offer_bump,
+            vault_bump,
+            false,
+        )?;
+        check_mints_allowed(
+            &accounts.config,
+            accounts.allowed_mint_offered.as_ref(),
+            accounts.allowed_mint_wanted.as_ref(),
+        )?;
+        collect_listing_fee(
+            &accounts.config,
+            &accounts.payer.to_account_info(),
+            accounts.treasury.as_ref(),
+            &accounts.system_program,
+        )?;
+
+        Ok(())
+    }
+
+    /// Create an offer payable in any of up to `MAX_WANTED_QUOTES` mints,
+    /// each with its own amount. The first quote must match `mint_wanted` and
+    /// becomes the offer's primary price. Such offers can only be taken whole.
+    /// While the mint allowlist is on, `remaining_accounts` holds the
+    /// `AllowedMint` PDA of every other quote's mint, in quote order.
+    pub fn create_offer_multi_wanted(
+        ctx: Context<CreateOfferMultiWanted>,
+        quotes: Vec<WantedQuote>,
+        amount_offered: u64,
+        options: OfferOptions,
+    ) -> Result<()> {
+        require!(
+            !quotes.is_empty() && quotes.len() <= MAX_WANTED_QUOTES,
+            ErrorCode::InvalidQuotes
+        );
+        require_keys_eq!(
+            quotes[0].mint,
+            ctx.accounts.mint_wanted.key(),
+            ErrorCode::InvalidQuotes
+        );
+        let mut mints = BTreeSet::new();
+        for quote in quotes.iter() {
+            require!(
+                quote.amount > 0 && mints.insert(quote.mint),
+                ErrorCode::InvalidQuotes
+            );
+            require_keys_neq!(
+                quote.mint,
+                ctx.accounts.mint_offered.key(),
+                ErrorCode::SameMint
+            );
+        }
+        require!(!options.allow_partial, ErrorCode::PartialFillNotAllowed);
+        check_listed_mints_allowed(
+            &ctx.accounts.config,
+            quotes[1..].iter().map(|quote| &quote.mint),
+            ctx.remaining_accounts,
+            ctx.program_id,
+        )?;
+
+        let offer_bump = ctx.bumps.offer;
+        let vault_bump = ctx.bumps.vault;
+        let accounts = ctx.accounts;
+        open_offer(
+            &mut accounts.user_profile,
+            &mut accounts.offer,
+            &mut accounts.vault,
+            &accounts.maker_token_account,
+            &accounts.mint_offered,
+            &accounts.mint_wanted,
+            &accounts.maker.to_account_info(),
+            &accounts.maker.to_account_info(),
+            &[],
+            &accounts.token_program,
+            &accounts.system_program,
+            Some(&accounts.rent),
+            &OfferGates {
+                config: &accounts.config,
+                mint_tvl: &accounts.mint_tvl,
+                mint_tvl_bump: ctx.bumps.mint_tvl,
+                payer: &accounts.maker.to_account_info(),
+                mint_status_offered: &accounts.mint_status_offered,
+                mint_status_wanted: &accounts.mint_status_wanted,
+                maker_status: accounts.maker_status.as_ref(),
+            },
+            amount_offered,
+            quotes[0].amount,
+            &options,
+            None,
+            offer_bump,
+            vault_bump,
+            false,
+        )?;
+        check_mints_allowed(
+            &accounts.config,
+            accounts.allowed_mint_offered.as_ref(),
+            accounts.allowed_mint_wanted.as_ref(),
+        )?;
+        collect_listing_fee(
+            &accounts.config,
+            &accounts.maker.to_account_info(),
+            accounts.treasury.as_ref(),
+            &accounts.system_program,
+        )?;
+
+        accounts.offer.alt_wanted = quotes[1..].to_vec();
+
+        Ok(())
+    }
+
+    /// Post a request for quote: the requester escrows `amount_paid` of the
+    /// token they pay with and asks for `amount_wanted` of another. Any
+    /// maker can then deliver the wanted tokens through `fill_request`.
+    pub fn create_request(
+        ctx: Context<CreateOffer>,
+        amount_paid: u64,
+        amount_wanted: u64,
+        options: OfferOptions,
+    ) -> Result<()> {
+        let offer_bump = ctx.bumps.offer;
+        let vault_bump = ctx.bumps.vault;
+        let accounts = ctx.accounts;
+        open_offer(
+            &mut accounts.user_profile,
+            &mut accounts.offer,
+            &mut accounts.vault,
+            &accounts.maker_token_account,
+            &accounts.mint_offered,
+            &accounts.mint_wanted,
+            &accounts.maker.to_account_info(),
+            &accounts.maker.to_account_info(),
+            &[],
+            &accounts.token_program,
+            &accounts.system_program,
+            Some(&accounts.rent),
+            &OfferGates {
+                config: &accounts.config,
+                mint_tvl: &accounts.mint_tvl,
+                mint_tvl_bump: ctx.bumps.mint_tvl,
+                payer: &accounts.maker.to_account_info(),
+                mint_status_offered: &accounts.mint_status_offered,
+                mint_status_wanted: &accounts.mint_status_wanted,
+                maker_status: accounts.maker_status.as_ref(),
+            },
+            amount_paid,
+            amount_wanted,
+            &options,
+            None,
+            offer_bump,
+            vault_bump,
+            false,
+        )?;
+        check_mints_allowed(
+            &accounts.config,
+            accounts.allowed_mint_offered.as_ref(),
+            accounts.allowed_mint_wanted.as_ref(),
+        )?;
+        collect_listing_fee(
+            &accounts.config,
+            &accounts.maker.to_account_info(),
+            accounts.treasury.as_ref(),
+            &accounts.system_program,
+        )?;
+
+        accounts.offer.request_type = RequestType::Request;
+        msg!("Offer {} is a request for quote", accounts.offer.offer_id);
+
+        Ok(())
+    }
+
+    /// Fill a request for quote whole: the signing maker delivers the wanted
+    /// tokens to the requester and claims the escrowed payment. The
+    /// `AcceptOffer` roles are swapped, with the filling maker as `taker`.
+    pub fn fill_request(ctx: Context<AcceptOffer>, _offer_id: u64) -> Result<()> {
+        require!(
+            ctx.accounts.offer.request_type == RequestType::Request,
+            ErrorCode::NotARequest
+        );
+
+        let amount_in = ctx
+            .accounts
+            .offer
+            .quote_for(&ctx.accounts.mint_wanted.key())
+            .ok_or(ErrorCode::MintNotAccepted)?;
+        let amount_out = ctx.accounts.offer.amount_offered;
+        fill_offer(ctx.accounts, amount_in, amount_out, false, false, false)
+    }
+
+    /// Accept an offer and execute atomic token swap
+    pub fn accept_offer(
+        ctx: Context<AcceptOffer>,
+        _offer_id: u64,
+        memo: Option<String>,
+    ) -> Result<()> {
+        // The memo goes first: any CPI after the fill would clear its
+        // return data
+        if let Some(memo) = &memo {
+            require!(memo.len() <= MAX_MEMO_LEN, ErrorCode::MemoTooLong);
+            attach_memo(ctx.accounts, memo)?;
+        }
+
+        reprice_from_oracle(ctx.accounts)?;
+        let amount_in = ctx
+            .accounts
+            .offer
+            .quote_for(&ctx.accounts.mint_wanted.key())
+            .ok_or(ErrorCode::MintNotAccepted)?;
+        let amount_out = ctx.accounts.offer.amount_offered;
+        fill_offer(ctx.accounts, amount_in, amount_out, false, false, false)
+    }
+
+    /// Accept an index offer whole, repricing it from its component feeds,
+    /// passed as remaining accounts in the order the offer lists them
+    pub fn accept_index_offer(ctx: Context<AcceptOffer>, _offer_id: u64) -> Result<()> {
+        reprice_from_index(ctx.accounts, ctx.remaining_accounts)?;
+        let amount_in = ctx.accounts.offer.amount_wanted;
+        let amount_out = ctx.accounts.offer.amount_offered;
+        fill_offer(ctx.accounts, amount_in, amount_out, false, false, false)
+    }
+
+    /// Accept an offer whole by presenting a `TakerAuthorization` the offer's
+    /// owner signed off-chain, verified through an Ed25519 program
+    /// instruction placed right before this one. This is the only way to take
+    /// offers created with `requires_authorization`.
+    pub fn accept_offer_authorized(
+        ctx: Context<AcceptOffer>,
+        _offer_id: u64,
+        authorization: TakerAuthorization,
+    ) -> Result<()> {
+        let offer = &ctx.accounts.offer;
+        let clock = Clock::get()?;
+
+        require_keys_eq!(authorization.offer, offer.key(), ErrorCode::InvalidAuthorization);
+        require_keys_eq!(
+            authorization.taker,
+            ctx.accounts.taker.key(),
+            ErrorCode::InvalidAuthorization
+        );
+        require!(
+            authorization.valid_from <= clock.unix_timestamp
+                && clock.unix_timestamp < authorization.valid_until,
+            ErrorCode::AuthorizationExpired
+        );
+        let instructions = ctx
+            .accounts
+            .instructions
+            .as_ref()
+            .ok_or(ErrorCode::InvalidSignature)?;
+        verify_ed25519_signature(
+            instructions,
+            &offer.current_owner,
+            &authorization.try_to_vec()?,
+        )?;
+
+        reprice_from_oracle(ctx.accounts)?;
+        let amount_in = ctx
+            .accounts
+            .offer
+            .quote_for(&ctx.accounts.mint_wanted.key())
+            .ok_or(ErrorCode::MintNotAccepted)?;
+        let amount_out = ctx.accounts.offer.amount_offered;
+        fill_offer(ctx.accounts, amount_in, amount_out, true, false, false)
+    }
+
+    /// Accept an offer whole against a `PriceAttestation` its owner signed
+    /// off-chain, verified through an Ed25519 program instruction placed
+    /// right before this one. The attestation must quote the offer's current
+    /// `amount_wanted` and be at most `attestation_max_age_slots` old; this is
+    /// the only way to take offers that set it.
+    pub fn accept_offer_attested(
+        ctx: Context<AcceptOffer>,
+        _offer_id: u64,
+        attestation: PriceAttestation,
+    ) -> Result<()> {
+        // The attested ask is the one the fill is priced at
+        reprice_from_oracle(ctx.accounts)?;
+        let offer = &ctx.accounts.offer;
+        let clock = Clock::get()?;
+
+        require_keys_eq!(attestation.offer, offer.key(), ErrorCode::InvalidAttestation);
+        require!(
+            attestation.amount_wanted == offer.amount_wanted,
+            ErrorCode::InvalidAttestation
+        );
+        require!(
+            attestation.slot <= clock.slot
+                && clock.slot - attestation.slot <= offer.attestation_max_age_slots,
+            ErrorCode::StaleAttestation
+        );
+        let instructions = ctx
+            .accounts
+            .instructions
+            .as_ref()
+            .ok_or(ErrorCode::InvalidSignature)?;
+        verify_ed25519_signature(
+            instructions,
+            &offer.current_owner,
+            &attestation.try_to_vec()?,
+        )?;
+
+        let amount_in = ctx
+            .accounts
+            .offer
+            .quote_for(&ctx.accounts.mint_wanted.key())
+            .ok_or(ErrorCode::MintNotAccepted)?;
+        let amount_out = ctx.accounts.offer.amount_offered;
+        fill_offer(ctx.accounts, amount_in, amount_out, false, true, false)
+    }
+
+    /// Accept an offer whole, but only if exactly `expected_amount` is still
+    /// offered, so a partial fill landing first reverts the take instead of
+    /// leaving the taker with less than planned
+    pub fn accept_offer_fok(
+        ctx: Context<AcceptOffer>,
+        _offer_id: u64,
+        expected_amount: u64,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.offer.amount_offered == expected_amount,
+            ErrorCode::FillOrKillFailed
+        );
+
+        reprice_from_oracle(ctx.accounts)?;
+        let amount_in = ctx
+            .accounts
+            .offer
+            .quote_for(&ctx.accounts.mint_wanted.key())
+            .ok_or(ErrorCode::MintNotAccepted)?;
+        fill_offer(ctx.accounts, amount_in, expected_amount, false, false, false)
+    }
+
+    /// Fill part of a partially-fillable offer by paying `amount_in` of the
+    /// wanted token; the offered amount received is rounded down
+    pub fn accept_offer_partial(
+        ctx: Context<AcceptOffer>,
+        _offer_id: u64,
+        amount_in: u64,
+    ) -> Result<()> {
+        reprice_from_oracle(ctx.accounts)?;
+        let offer = &ctx.accounts.offer;
+        require!(offer.allow_partial, ErrorCode::PartialFillNotAllowed);
+        require!(
+            amount_in > 0 && amount_in <= offer.amount_wanted,
+            ErrorCode::InvalidAmount
+        );
+
+        let amount_out = mul_div(amount_in, offer.amount_offered, offer.amount_wanted, false)?;
+        require!(amount_out > 0, ErrorCode::InvalidAmount);
+
+        fill_offer(ctx.accounts, amount_in, amount_out, false, false, false)
+    }
+
+    /// Fill part of a partially-fillable offer by receiving exactly
+    /// `amount_out` of the offered token; the price paid is rounded per the
+    /// offer's `rounding`, up unless the maker chose otherwise
+    pub fn accept_offer_exact_out(
+        ctx: Context<AcceptOffer>,
+        _offer_id: u64,
+        amount_out: u64,
+    ) -> Result<()> {
+        reprice_from_oracle(ctx.accounts)?;
+        let offer = &ctx.accounts.offer;
+        require!(offer.allow_partial, ErrorCode::PartialFillNotAllowed);
+        require!(
+            amount_out > 0 && amount_out <= offer.amount_offered,
+            ErrorCode::InvalidAmount
+        );
+
+        let amount_in = mul_div_rounded(
+            amount_out,
+            offer.amount_wanted,
+            offer.amount_offered,
+            offer.rounding,
+        )?;
+        // Rounding down must not make a fill free, nor rounding up let a
+        // partial fill pay off the whole wanted side
+        require!(amount_in > 0, ErrorCode::InvalidAmount);
+        require!(
+            amount_in < offer.amount_wanted || amount_out == offer.amount_offered,
+            ErrorCode::InvalidAmount
+        );
+
+        fill_offer(ctx.accounts, amount_in, amount_out, false, false, false)
+    }
+
+    /// Accept an offer whole, budgeting up to `amount_sent` of the wanted
+    /// token. Only the quoted price and the taker fee leave the taker's
+    /// account; whatever of the budget is left over is never pulled.
+    pub fn accept_offer_with_change(
+        ctx: Context<AcceptOffer>,
+        _offer_id: u64,
+        amount_sent: u64,
+    ) -> Result<()> {
+        reprice_from_oracle(ctx.accounts)?;
+        let amount_in = ctx
+            .accounts
+            .offer
+            .quote_for(&ctx.accounts.mint_wanted.key())
+            .ok_or(ErrorCode::MintNotAccepted)?;
+
+        // In fee-token mode the taker fee is charged in another mint
+        let taker_fee = match load_config(&ctx.accounts.config)? {
+            Some(config) if config.fee_mint == Pubkey::default() => taker_fee(&config, amount_in)?,
+            _ => 0,
+        };
+        require!(
+            amount_sent >= amount_in.checked_add(taker_fee).ok_or(ErrorCode::MathOverflow)?,
+            ErrorCode::InsufficientBalance
+        );
+
+        let amount_out = ctx.accounts.offer.amount_offered;
+        fill_offer(ctx.accounts, amount_in, amount_out, false, false, false)
+    }
+
+    /// Accept an offer on behalf of `beneficiary`. The taker still pays the
+    /// price, but the vault releases into `taker_receive_account`, which must
+    /// be the beneficiary's associated token account for the offered mint.
+    pub fn accept_offer_for(
+        ctx: Context<AcceptOffer>,
+        _offer_id: u64,
+        beneficiary: Pubkey,
+    ) -> Result<()> {
+        let beneficiary_account = ctx
+            .accounts
+            .taker_receive_account
+            .as_ref()
+            .ok_or(ErrorCode::InvalidBeneficiary)?;
+        require_keys_eq!(
+            beneficiary_account.key(),
+            get_associated_token_address(&beneficiary, &ctx.accounts.offer.mint_offered),
+            ErrorCode::InvalidBeneficiary
+        );
+
+        reprice_from_oracle(ctx.accounts)?;
+        let amount_in = ctx
+            .accounts
+            .offer
+            .quote_for(&ctx.accounts.mint_wanted.key())
+            .ok_or(ErrorCode::MintNotAccepted)?;
+
+        let offer_id = ctx.accounts.offer.offer_id;
+        let amount_out = ctx.accounts.offer.amount_offered;
+        fill_offer(ctx.accounts, amount_in, amount_out, false, false, false)?;
+
+        msg!("Offer {} taken on behalf of {}", offer_id, beneficiary);
+
+        Ok(())
+    }
+
+    /// Accept an offer with a vesting cliff whole. The maker is paid at once,
+    /// while the offered tokens go to a vesting vault the taker can empty
+    /// through `claim_vested` once `vesting_cliff_secs` have passed. The
+    /// `vesting_claim` and `vesting_vault` PDAs are created here at the
+    /// taker's expense; a taker holds one claim per offer at a time.
+    pub fn accept_with_vesting(ctx: Context<AcceptOffer>, _offer_id: u64) -> Result<()> {
+        let cliff_secs = ctx.accounts.offer.vesting_cliff_secs;
+        require!(cliff_secs > 0, ErrorCode::VestingMismatch);
+
+        reprice_from_oracle(ctx.accounts)?;
+        let amount_in = ctx
+            .accounts
+            .offer
+            .quote_for(&ctx.accounts.mint_wanted.key())
+            .ok_or(ErrorCode::MintNotAccepted)?;
+        let amount_out = ctx.accounts.offer.amount_offered;
+        let (claim_bump, vault_bump) = open_vesting_escrow(ctx.accounts, ctx.program_id)?;
+        fill_offer(ctx.accounts, amount_in, amount_out, false, false, true)?;
+
+        let accounts = ctx.accounts;
+        let unlock_ts = checked_add_ts(Clock::get()?.unix_timestamp, cliff_secs)?;
+        let claim = VestingClaim {
+            offer: accounts.offer.key(),
+            taker: accounts.taker.key(),
+            mint: accounts.mint_offered.key(),
+            amount: amount_out,
+            unlock_ts,
+            bump: claim_bump,
+            vault_bump,
+        };
+        let claim_info = accounts
+            .vesting_claim
+            .as_ref()
+            .ok_or(ErrorCode::MissingVestingAccount)?;
+        claim.try_serialize(&mut &mut claim_info.try_borrow_mut_data()?[..])?;
+
+        msg!(
+            "{} of offer {} vesting for {} until {}",
+            amount_out,
+            claim.offer,
+            claim.taker,
+            unlock_ts
+        );
+
+        Ok(())
+    }
+
+    /// Release a vesting claim's tokens to the taker once its cliff has
+    /// passed, closing the claim and its vault
+    pub fn claim_vested(ctx: Context<ClaimVested>) -> Result<()> {
+        let claim = &ctx.accounts.vesting_claim;
+        require!(
+            Clock::get()?.unix_timestamp >= claim.unlock_ts,
+            ErrorCode::CliffNotReached
+        );
+
+        let claim_key = claim.key();
+        let seeds = &[b"vesting_vault", claim_key.as_ref(), &[claim.vault_bump]];
+        let signer = &[&seeds[..]];
+
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.vesting_vault.to_account_info(),
+            to: ctx.accounts.taker_token_account.to_account_info(),
+            authority: ctx.accounts.vesting_vault.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        token::transfer(
+            CpiContext::new_with_signer(cpi_program, cpi_accounts, signer),
+            ctx.accounts.vesting_vault.amount,
+        )?;
+
+        let cpi_accounts = CloseAccount {
+            account: ctx.accounts.vesting_vault.to_account_info(),
+            destination: ctx.accounts.taker.to_account_info(),
+            authority: ctx.accounts.vesting_vault.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        token::close_account(CpiContext::new_with_signer(cpi_program, cpi_accounts, signer))?;
+
+        msg!("Vested {} of offer {} claimed by {}", claim.amount, claim.offer, claim.taker);
+
+        Ok(())
+    }
+
+    /// Cancel an offer and return tokens to maker
+    pub fn cancel_offer(ctx: Context<CancelOffer>, _offer_id: u64) -> Result<()> {
+        let offer = &ctx.accounts.offer;
+        let clock = Clock::get()?;
+
+        require!(!offer.is_sealed(&clock), ErrorCode::OfferSealed);
+        require!(!offer.is_cancel_locked(clock.unix_timestamp), ErrorCode::CancelLocked);
+        validate_bumps(offer)?;
+        validate_vault(&ctx.accounts.vault, offer)?;
+
+        // Expired offers are always recoverable and don't count as cancels
+        if !offer.is_expired(&clock) {
+            let user_profile = &mut ctx.accounts.user_profile;
+            if let Some(config) = load_config(&ctx.accounts.config)? {
+                require!(
+                    clock.unix_timestamp.saturating_sub(user_profile.last_cancel_ts)
+                        >= config.min_cancel_interval_secs,
+                    ErrorCode::CancelCooldown
+                );
+            }
+            user_profile.last_cancel_ts = clock.unix_timestamp;
+        }
+
+        // The maker may send the escrow to any account of the offered mint
+        let destination = match &ctx.accounts.return_token_account {
+            Some(return_token_account) => {
+                require_keys_eq!(
+                    return_token_account.mint,
+                    offer.mint_offered,
+                    ErrorCode::InvalidMint
+                );
+                return_token_account
+            }
+            None => &ctx.accounts.maker_token_account,
+        };
+
+        let amount_returned = refund_vault(
+            offer,
+            &ctx.accounts.vault,
+            destination,
+            &ctx.accounts.maker.to_account_info(),
+            &ctx.accounts.token_program,
+            &ctx.accounts.mint_tvl,
+        )?;
+
+        ctx.accounts.user_profile.release_offer_slot()?;
+
+        emit!(OfferCancelled {
+            offer: offer.key(),
+            maker: offer.maker,
+            offer_id: offer.offer_id,
+            amount_returned,
+        });
+
+        msg!("Offer {} cancelled", offer.offer_id);
+
+        Ok(())
+    }
+
+    /// Cancel an offer and immediately relist its escrow at a new price as a
+    /// fresh counter-derived offer, all in one transaction
+    pub fn relist_offer(
+        ctx: Context<RelistOffer>,
+        _offer_id: u64,
+        new_amount_wanted: u64,
+        new_expiry_ts: Option<i64>,
+    ) -> Result<()> {
+        let new_offer_bump = ctx.bumps.new_offer;
+        let new_vault_bump = ctx.bumps.new_vault;
+        let accounts = ctx.accounts;
+        let old_offer = &accounts.offer;
+        let clock = Clock::get()?;
+
+        require!(!old_offer.is_sealed(&clock), ErrorCode::OfferSealed);
+        require!(
+            !old_offer.is_cancel_locked(clock.unix_timestamp),
+            ErrorCode::CancelLocked
+        );
+        // A maker may not reprice an offer out from under a reserving taker
+        require!(
+            !old_offer.is_held(clock.unix_timestamp),
+            ErrorCode::OfferReserved
+        );
+        validate_vault(&accounts.vault, old_offer)?;
+
+        let amount_returned = refund_vault(
+            old_offer,
+            &accounts.vault,
+            &accounts.maker_token_account,
+            &accounts.maker.to_account_info(),
+            &accounts.token_program,
+            &accounts.mint_tvl,
+        )?;
+
+        accounts.user_profile.release_offer_slot()?;
+
+        emit!(OfferCancelled {
+            offer: old_offer.key(),
+            maker: old_offer.maker,
+            offer_id: old_offer.offer_id,
+            amount_returned,
+        });
+
+        let amount_offered = old_offer.amount_offered;
+        let options = OfferOptions {
+            expiry_ts: new_expiry_ts,
+            allow_partial: old_offer.allow_partial,
+            category: old_offer.category,
+            maker_receive_account: old_offer.maker_receive_account,
+            expected_price: None,
+            tolerance_bps: 0,
+            active_from_ts: old_offer.active_from_ts,
+            referrer: old_offer.referrer,
+            referrer_bps: old_offer.referrer_bps,
+            max_fills: old_offer.max_fills,
+            maker_derived_vault: old_offer.maker_derived_vault,
+            dust_threshold: old_offer.dust_threshold,
+            royalty_recipient: old_offer.royalty_recipient,
+            royalty_bps: old_offer.royalty_bps,
+            blocked_takers: old_offer.blocked_takers.clone(),
+            sealed: old_offer.sealed,
+            expiry_slot: None,
+            // The relisted escrow always moves into a vault of its own
+            omnibus_vault: false,
+            min_taker_swaps: old_offer.min_taker_swaps,
+            requires_authorization: old_offer.requires_authorization,
+            // Takers queued on the old offer have to line up again
+            fill_queue_len: old_offer.fill_queue_capacity,
+            rounding: old_offer.rounding,
+            vesting_cliff_secs: old_offer.vesting_cliff_secs,
+            fill_window: old_offer.fill_window,
+            dust_in_whole_tokens: old_offer.dust_in_whole_tokens,
+            metadata_hash: old_offer.metadata_hash,
+            // Relisting is only possible once the old lock has run out
+            no_cancel_until: None,
+            auto_relist_amount_wanted: old_offer.auto_relist_amount_wanted,
+            // Refunded with the old offer's rent and escrowed afresh
+            taker_tip_lamports: old_offer.taker_tip_lamports,
+            lot_size: old_offer.lot_size,
+            // Takers of the old offer count afresh against the cap
+            max_acceptors: old_offer.max_acceptors,
+            attestation_max_age_slots: old_offer.attestation_max_age_slots,
+            min_acceptable_wanted: old_offer.min_acceptable_wanted,
+            gate_mint: old_offer.gate_mint,
+            // Refunded with the escrow and escrowed afresh
+            taker_rebate_bps: old_offer.taker_rebate_bps,
+        };
+        let old_offer_id = old_offer.offer_id;
+        let same_mint = old_offer.mint_offered == old_offer.mint_wanted;
+
+        open_offer(
+            &mut accounts.user_profile,
+            &mut accounts.new_offer,
+            &mut accounts.new_vault,
+            &accounts.maker_token_account,
+            &accounts.mint_offered,
+            &accounts.mint_wanted,
+            &accounts.maker.to_account_info(),
+            &accounts.maker.to_account_info(),
+            &[],
+            &accounts.token_program,
+            &accounts.system_program,
+            Some(&accounts.rent),
+            &OfferGates {
+                config: &accounts.config,
+                mint_tvl: &accounts.mint_tvl,
+                mint_tvl_bump: ctx.bumps.mint_tvl,
+                payer: &accounts.maker.to_account_info(),
+                mint_status_offered: &accounts.mint_status_offered,
+                mint_status_wanted: &accounts.mint_status_wanted,
+                maker_status: accounts.maker_status.as_ref(),
+            },
+            amount_offered,
+            new_amount_wanted,
+            &options,
+            None,
+            new_offer_bump,
+            new_vault_bump,
+            same_mint,
+        )?;
+        check_mints_allowed(
+            &accounts.config,
+            accounts.allowed_mint_offered.as_ref(),
+            accounts.allowed_mint_wanted.as_ref(),
+        )?;
+        collect_listing_fee(
+            &accounts.config,
+            &accounts.maker.to_account_info(),
+            accounts.treasury.as_ref(),
+            &accounts.system_program,
+        )?;
+
+        msg!(
+            "Offer {} relisted as offer {}",
+            old_offer_id,
+            accounts.new_offer.offer_id
+        );
+
+        Ok(())
+    }
+
+    /// Cancel an offer and reopen it on the same terms under `new_maker`'s
+    /// profile, moving the escrow straight from the old vault into the new
+    /// one. Both makers sign; the new maker pays the new accounts' rent and
+    /// any taker tip, while the old offer's rent and tip go back to the old
+    /// maker.
+    pub fn move_offer_to_maker(ctx: Context<MoveOfferToMaker>, _offer_id: u64) -> Result<()> {
+        let new_offer_bump = ctx.bumps.new_offer;
+        let new_vault_bump = ctx.bumps.new_vault;
+        let accounts = ctx.accounts;
+        let old_offer = &accounts.offer;
+        let clock = Clock::get()?;
+
+        require_keys_neq!(
+            accounts.maker.key(),
+            accounts.new_maker.key(),
+            ErrorCode::SameMaker
+        );
+        require!(!old_offer.is_sealed(&clock), ErrorCode::OfferSealed);
+        require!(
+            !old_offer.is_cancel_locked(clock.unix_timestamp),
+            ErrorCode::CancelLocked
+        );
+        require!(
+            !old_offer.is_held(clock.unix_timestamp),
+            ErrorCode::OfferReserved
+        );
+        validate_vault(&accounts.vault, old_offer)?;
+        // The rebate pool is the old maker's money, not part of the terms
+        require!(
+            old_offer.taker_rebate_escrowed == 0,
+            ErrorCode::TakerRebateEscrowed
+        );
+
+        accounts.user_profile.release_offer_slot()?;
+        // The new offer counts the escrow afresh
+        if old_offer.tvl_tracked {
+            release_mint_tvl(&accounts.mint_tvl, old_offer.amount_offered)?;
+        }
+
+        // The escrow moves on rather than back to the maker
+        emit!(OfferCancelled {
+            offer: old_offer.key(),
+            maker: old_offer.maker,
+            offer_id: old_offer.offer_id,
+            amount_returned: 0,
+        });
+
+        let options = OfferOptions {
+            expiry_ts: old_offer.expiry_ts,
+            allow_partial: old_offer.allow_partial,
+            category: old_offer.category,
+            // The old maker's receive account is no business of the new one
+            maker_receive_account: None,
+            expected_price: None,
+            tolerance_bps: 0,
+            active_from_ts: old_offer.active_from_ts,
+            referrer: old_offer.referrer,
+            referrer_bps: old_offer.referrer_bps,
+            max_fills: old_offer.max_fills,
+            maker_derived_vault: false,
+            dust_threshold: old_offer.dust_threshold,
+            royalty_recipient: old_offer.royalty_recipient,
+            royalty_bps: old_offer.royalty_bps,
+            blocked_takers: old_offer.blocked_takers.clone(),
+            sealed: old_offer.sealed,
+            expiry_slot: old_offer.expiry_slot,
+            omnibus_vault: false,
+            min_taker_swaps: old_offer.min_taker_swaps,
+            requires_authorization: old_offer.requires_authorization,
+            fill_queue_len: old_offer.fill_queue_capacity,
+            rounding: old_offer.rounding,
+            vesting_cliff_secs: old_offer.vesting_cliff_secs,
+            fill_window: old_offer.fill_window,
+            dust_in_whole_tokens: old_offer.dust_in_whole_tokens,
+            metadata_hash: old_offer.metadata_hash,
+            no_cancel_until: None,
+            auto_relist_amount_wanted: old_offer.auto_relist_amount_wanted,
+            taker_tip_lamports: old_offer.taker_tip_lamports,
+            lot_size: old_offer.lot_size,
+            // Takers of the old offer count afresh against the cap
+            max_acceptors: old_offer.max_acceptors,
+            attestation_max_age_slots: old_offer.attestation_max_age_slots,
+            min_acceptable_wanted: old_offer.min_acceptable_wanted,
+            gate_mint: old_offer.gate_mint,
+            taker_rebate_bps: 0,
+        };
+        let old_offer_id = old_offer.offer_id;
+        let same_mint = old_offer.mint_offered == old_offer.mint_wanted;
+
+        // The old vault signs for the escrow it hands over
+        let offer_key = old_offer.key();
+        let vault_key_seed = old_offer.vault_key_seed(offer_key);
+        let vault_id_seed = old_offer.vault_id_seed();
+        let mint_key = old_offer.mint_offered;
+        let vault_bump = [old_offer.vault_bump];
+        let vault_seeds: &[&[u8]] = &[
             b"vault",
-            offer.key().as_ref(),
-            mint_offered.key().as_ref(),
-        ],
-        bump = offer.vault_bump,
-    )]
+            vault_key_seed.as_ref(),
+            vault_id_seed.as_slice(),
+            mint_key.as_ref(),
+            &vault_bump,
+        ];
+
+        open_offer(
+            &mut accounts.new_user_profile,
+            &mut accounts.new_offer,
+            &mut accounts.new_vault,
+            &accounts.vault,
+            &accounts.mint_offered,
+            &accounts.mint_wanted,
+            &accounts.new_maker.to_account_info(),
+            &accounts.vault.to_account_info(),
+            &[vault_seeds],
+            &accounts.token_program,
+            &accounts.system_program,
+            None,
+            &OfferGates {
+                config: &accounts.config,
+                mint_tvl: &accounts.mint_tvl,
+                mint_tvl_bump: ctx.bumps.mint_tvl,
+                payer: &accounts.new_maker.to_account_info(),
+                mint_status_offered: &accounts.mint_status_offered,
+                mint_status_wanted: &accounts.mint_status_wanted,
+                maker_status: accounts.new_maker_status.as_ref(),
+            },
+            old_offer.amount_offered,
+            old_offer.amount_wanted,
+            &options,
+            None,
+            new_offer_bump,
+            new_vault_bump,
+            same_mint,
+        )?;
+
+        // A shared vault still holds other offers' escrow
+        if !old_offer.omnibus_vault {
+            let cpi_accounts = CloseAccount {
+                account: accounts.vault.to_account_info(),
+                destination: accounts.maker.to_account_info(),
+                authority: accounts.vault.to_account_info(),
+            };
+            let cpi_program = accounts.token_program.to_account_info();
+            token::close_account(CpiContext::new_with_signer(
+                cpi_program,
+                cpi_accounts,
+                &[vault_seeds],
+            ))?;
+        }
+
+        msg!(
+            "Offer {} moved to {} as offer {}",
+            old_offer_id,
+            accounts.new_maker.key(),
+            accounts.new_offer.offer_id
+        );
+
+        Ok(())
+    }
+
+    /// Withdraw part of the escrow back to the maker, keeping the offer open
+    pub fn decrease_offer(
+        ctx: Context<DecreaseOffer>,
+        _offer_id: u64,
+        reduce_by: u64,
+    ) -> Result<()> {
+        require!(reduce_by > 0, ErrorCode::InvalidAmount);
+        require!(
+            reduce_by < ctx.accounts.offer.amount_offered,
+            ErrorCode::CannotDrainOffer
+        );
+        let clock = Clock::get()?;
+        require!(!ctx.accounts.offer.is_sealed(&clock), ErrorCode::OfferSealed);
+        require!(
+            !ctx.accounts.offer.is_cancel_locked(clock.unix_timestamp),
+            ErrorCode::CancelLocked
+        );
+        require!(
+            !ctx.accounts.offer.is_held(clock.unix_timestamp),
+            ErrorCode::OfferReserved
+        );
+
+        // Transfer tokens from vault back to maker using PDA signer
+        let offer_key = ctx.accounts.offer.key();
+        let vault_key_seed = ctx.accounts.offer.vault_key_seed(offer_key);
+        let vault_id_seed = ctx.accounts.offer.vault_id_seed();
+        let mint_key = ctx.accounts.offer.mint_offered;
+        let seeds = &[
+            b"vault",
+            vault_key_seed.as_ref(),
+            vault_id_seed.as_slice(),
+            mint_key.as_ref(),
+            &[ctx.accounts.offer.vault_bump],
+        ];
+        let signer = &[&seeds[..]];
+
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.vault.to_account_info(),
+            to: ctx.accounts.maker_token_account.to_account_info(),
+            authority: ctx.accounts.vault.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        token::transfer(cpi_ctx, reduce_by)?;
+
+        let offer = &mut ctx.accounts.offer;
+        offer.amount_offered = offer
+            .amount_offered
+            .checked_sub(reduce_by)
+            .ok_or(ErrorCode::InvalidAmount)?;
+        if offer.tvl_tracked {
+            release_mint_tvl(&ctx.accounts.mint_tvl, reduce_by)?;
+        }
+
+        msg!(
+            "Offer {} decreased by {}, {} remaining",
+            offer.offer_id,
+            reduce_by,
+            offer.amount_offered
+        );
+
+        Ok(())
+    }
+
+    /// Cancel `amount` of an offer, refunding it to the maker and leaving the
+    /// rest open at the same price: `amount_wanted` and the alternative
+    /// quotes shrink in proportion, rounded up in the maker's favor.
+    pub fn partial_cancel(ctx: Context<DecreaseOffer>, _offer_id: u64, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        let offer = &ctx.accounts.offer;
+        require!(amount < offer.amount_offered, ErrorCode::CannotDrainOffer);
+        let clock = Clock::get()?;
+        require!(!offer.is_sealed(&clock), ErrorCode::OfferSealed);
+        require!(!offer.is_cancel_locked(clock.unix_timestamp), ErrorCode::CancelLocked);
+        require!(!offer.is_held(clock.unix_timestamp), ErrorCode::OfferReserved);
+        let old_amount_offered = offer.amount_offered;
+        let new_amount_offered = old_amount_offered - amount;
+        let new_amount_wanted =
+            mul_div(offer.amount_wanted, new_amount_offered, old_amount_offered, true)?;
+        let alt_wanted = offer
+            .alt_wanted
+            .iter()
+            .map(|quote| {
+                Ok(WantedQuote {
+                    mint: quote.mint,
+                    amount: mul_div(quote.amount, new_amount_offered, old_amount_offered, true)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // Transfer tokens from vault back to maker using PDA signer
+        let offer_key = offer.key();
+        let vault_key_seed = offer.vault_key_seed(offer_key);
+        let vault_id_seed = offer.vault_id_seed();
+        let mint_key = offer.mint_offered;
+        let seeds = &[
+            b"vault",
+            vault_key_seed.as_ref(),
+            vault_id_seed.as_slice(),
+            mint_key.as_ref(),
+            &[offer.vault_bump],
+        ];
+        let signer = &[&seeds[..]];
+
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.vault.to_account_info(),
+            to: ctx.accounts.maker_token_account.to_account_info(),
+            authority: ctx.accounts.vault.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        token::transfer(cpi_ctx, amount)?;
+
+        let offer = &mut ctx.accounts.offer;
+        offer.amount_offered = new_amount_offered;
+        if offer.tvl_tracked {
+            release_mint_tvl(&ctx.accounts.mint_tvl, amount)?;
+        }
+        offer.amount_wanted = new_amount_wanted;
+        offer.alt_wanted = alt_wanted;
+
+        msg!(
+            "Offer {} partially cancelled by {}: {} for {}",
+            offer.offer_id,
+            amount,
+            new_amount_offered,
+            new_amount_wanted
+        );
+
+        Ok(())
+    }
+
+    /// Scale both sides of an offer by `numerator / denominator`, keeping its
+    /// price: the escrow is topped up from or refunded to the maker's token
+    /// account by the difference. Alternative quotes scale along.
+    pub fn scale_offer(
+        ctx: Context<ScaleOffer>,
+        _offer_id: u64,
+        numerator: u64,
+        denominator: u64,
+    ) -> Result<()> {
+        require!(numerator > 0 && denominator > 0, ErrorCode::InvalidAmount);
+
+        let offer = &ctx.accounts.offer;
+        let clock = Clock::get()?;
+        require!(!offer.is_sealed(&clock), ErrorCode::OfferSealed);
+        require!(!offer.is_held(clock.unix_timestamp), ErrorCode::OfferReserved);
+        let old_amount_offered = offer.amount_offered;
+        let new_amount_offered = mul_div(old_amount_offered, numerator, denominator, false)?;
+        let new_amount_wanted = mul_div(offer.amount_wanted, numerator, denominator, false)?;
+        require!(
+            new_amount_offered > 0 && new_amount_wanted > 0,
+            ErrorCode::InvalidAmount
+        );
+        let alt_wanted = offer
+            .alt_wanted
+            .iter()
+            .map(|quote| {
+                let amount = mul_div(quote.amount, numerator, denominator, false)?;
+                require!(amount > 0, ErrorCode::InvalidAmount);
+                Ok(WantedQuote {
+                    mint: quote.mint,
+                    amount,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        validate_vault(&ctx.accounts.vault, offer)?;
+
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        if new_amount_offered > old_amount_offered {
+            let top_up = new_amount_offered - old_amount_offered;
+            require!(
+                ctx.accounts.maker_token_account.amount >= top_up,
+                ErrorCode::InsufficientBalance
+            );
+
+            let cpi_accounts = Transfer {
+                from: ctx.accounts.maker_token_account.to_account_info(),
+                to: ctx.accounts.vault.to_account_info(),
+                authority: ctx.accounts.maker.to_account_info(),
+            };
+            token::transfer(CpiContext::new(cpi_program, cpi_accounts), top_up)?;
+            if offer.tvl_tracked {
+                lock_mint_tvl(&ctx.accounts.mint_tvl, &ctx.accounts.config, top_up)?;
+            }
+        } else if new_amount_offered < old_amount_offered {
+            // Scaling down pulls escrow back out, as a partial cancel would
+            require!(
+                !offer.is_cancel_locked(clock.unix_timestamp),
+                ErrorCode::CancelLocked
+            );
+            let refund = old_amount_offered - new_amount_offered;
+
+            // Transfer tokens from vault back to maker using PDA signer
+            let offer_key = offer.key();
+            let vault_key_seed = offer.vault_key_seed(offer_key);
+            let vault_id_seed = offer.vault_id_seed();
+            let mint_key = offer.mint_offered;
+            let seeds = &[
+                b"vault",
+                vault_key_seed.as_ref(),
+                vault_id_seed.as_slice(),
+                mint_key.as_ref(),
+                &[offer.vault_bump],
+            ];
+            let signer = &[&seeds[..]];
+
+            let cpi_accounts = Transfer {
+                from: ctx.accounts.vault.to_account_info(),
+                to: ctx.accounts.maker_token_account.to_account_info(),
+                authority: ctx.accounts.vault.to_account_info(),
+            };
+            token::transfer(
+                CpiContext::new_with_signer(cpi_program, cpi_accounts, signer),
+                refund,
+            )?;
+            if offer.tvl_tracked {
+                release_mint_tvl(&ctx.accounts.mint_tvl, refund)?;
+            }
+        }
+
+        let offer = &mut ctx.accounts.offer;
+        offer.amount_offered = new_amount_offered;
+        offer.amount_wanted = new_amount_wanted;
+        offer.alt_wanted = alt_wanted;
+
+        msg!(
+            "Offer {} scaled by {}/{}: {} for {}",
+            offer.offer_id,
+            numerator,
+            denominator,
+            new_amount_offered,
+            new_amount_wanted
+        );
+
+        Ok(())
+    }
+
+    /// Switch the token an offer wants, e.g. from one stablecoin to another,
+    /// asking `new_amount_wanted` of it; the escrow is left untouched.
+    /// Pending counter-offers and any oracle or pool pricing, which were in
+    /// the old mint, are dropped.
+    pub fn change_wanted_mint(
+        ctx: Context<ChangeWantedMint>,
+        _offer_id: u64,
+        new_mint_wanted: Pubkey,
+        new_amount_wanted: u64,
+    ) -> Result<()> {
+        require!(new_amount_wanted > 0, ErrorCode::InvalidAmount);
+
+        let offer = &mut ctx.accounts.offer;
+        let clock = Clock::get()?;
+        require!(!offer.is_sealed(&clock), ErrorCode::OfferSealed);
+        // Wanting a token no taker holds would withdraw the offer as surely
+        // as cancelling it
+        require!(!offer.is_cancel_locked(clock.unix_timestamp), ErrorCode::CancelLocked);
+        require!(!offer.is_held(clock.unix_timestamp), ErrorCode::OfferReserved);
+        require!(new_mint_wanted != offer.mint_offered, ErrorCode::SameMint);
+        check_mints_unpaused(&ctx.accounts.mint_status_offered, &ctx.accounts.mint_status_wanted)?;
+        check_mints_allowed(
+            &ctx.accounts.config,
+            ctx.accounts.allowed_mint_offered.as_ref(),
+            ctx.accounts.allowed_mint_wanted.as_ref(),
+        )?;
+
+        let old_mint_wanted = offer.mint_wanted;
+        offer.mint_wanted = new_mint_wanted;
+        offer.amount_wanted = new_amount_wanted;
+        offer.decimals_wanted = ctx.accounts.mint_wanted.decimals;
+        offer.alt_wanted.retain(|quote| quote.mint != new_mint_wanted);
+        offer.counter_offers.clear();
+        offer.price_feed = None;
+        offer.amm_pool = None;
+        offer.index_components.clear();
+
+        emit!(OfferWantedMintChanged {
+            offer: offer.key(),
+            maker: offer.maker,
+            offer_id: offer.offer_id,
+            old_mint_wanted,
+            new_mint_wanted,
+            new_amount_wanted,
+        });
+
+        msg!(
+            "Offer {} now wants {} of {}",
+            offer.offer_id,
+            new_amount_wanted,
+            new_mint_wanted
+        );
+
+        Ok(())
+    }
+
+    /// Push an offer's expiry further into the future. The expiry set at
+    /// creation is free; each extension costs the config's
+    /// `extension_fee_lamports_per_day`, pro rata, paid to the treasury.
+    pub fn extend_expiry(
+        ctx: Context<ExtendExpiry>,
+        _offer_id: u64,
+        new_expiry_ts: i64,
+    ) -> Result<()> {
+        let offer = &mut ctx.accounts.offer;
+        let clock = Clock::get()?;
+
+        let old_expiry_ts = offer.expiry_ts.ok_or(ErrorCode::InvalidExpiry)?;
+        require!(new_expiry_ts > old_expiry_ts, ErrorCode::InvalidExpiry);
+        validate_expiry(new_expiry_ts, clock.unix_timestamp)?;
+
+        let (fee, treasury) = match load_config(&ctx.accounts.config)? {
+            Some(config) => (
+                extension_fee(&config, new_expiry_ts - old_expiry_ts)?,
+                config.treasury,
+            ),
+            None => (0, Pubkey::default()),
+        };
+        if fee > 0 {
+            require!(
+                ctx.accounts.maker.lamports() >= fee,
+                ErrorCode::InsufficientBalance
+            );
+            let treasury_account = ctx
+                .accounts
+                .treasury
+                .as_ref()
+                .ok_or(ErrorCode::MissingTreasuryAccount)?;
+            require_keys_eq!(treasury_account.key(), treasury, ErrorCode::Unauthorized);
+
+            let cpi_accounts = system_program::Transfer {
+                from: ctx.accounts.maker.to_account_info(),
+                to: treasury_account.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.system_program.to_account_info();
+            system_program::transfer(CpiContext::new(cpi_program, cpi_accounts), fee)?;
+        }
+
+        offer.expiry_ts = Some(new_expiry_ts);
+
+        emit!(OfferExtended {
+            offer: offer.key(),
+            maker: offer.maker,
+            offer_id: offer.offer_id,
+            old_expiry_ts,
+            new_expiry_ts,
+            fee,
+        });
+
+        msg!("Offer {} extended until {}", offer.offer_id, new_expiry_ts);
+
+        Ok(())
+    }
+
+    /// Hand an open offer over to `new_owner`, who from then on controls it
+    /// and receives its proceeds and refunds. The offer keeps its address,
+    /// which is derived from the original maker.
+    pub fn transfer_offer_ownership(
+        ctx: Context<TransferOfferOwnership>,
+        _offer_id: u64,
+        new_owner: Pubkey,
+    ) -> Result<()> {
+        let offer = &mut ctx.accounts.offer;
+        let clock = Clock::get()?;
+
+        require!(!offer.is_expired(&clock), ErrorCode::OfferExpired);
+        require!(new_owner != Pubkey::default(), ErrorCode::Unauthorized);
+
+        // The offer's open-offer slot moves to the new owner
+        let max_open_offers = load_config(&ctx.accounts.config)?
+            .map_or(0, |config| config.max_offers_per_user);
+        ctx.accounts.owner_profile.release_offer_slot()?;
+        ctx.accounts
+            .new_owner_profile
+            .claim_offer_slot(max_open_offers)?;
+
+        let old_owner = offer.current_owner;
+        offer.current_owner = new_owner;
+
+        emit!(OfferOwnershipTransferred {
+            offer: offer.key(),
+            maker: offer.maker,
+            offer_id: offer.offer_id,
+            old_owner,
+            new_owner,
+        });
+
+        msg!(
+            "Offer {} transferred from {} to {}",
+            offer.offer_id,
+            old_owner,
+            new_owner
+        );
+
+        Ok(())
+    }
+
+    /// Suspend acceptance of an offer without cancelling it; the escrow and
+    /// offer account stay in place and the owner can still cancel
+    pub fn pause_offer(ctx: Context<SetOfferPaused>, _offer_id: u64) -> Result<()> {
+        let clock = Clock::get()?;
+        require!(!ctx.accounts.offer.is_sealed(&clock), ErrorCode::OfferSealed);
+        set_offer_paused(&mut ctx.accounts.offer, true)
+    }
+
+    /// Let takers accept a paused offer again
+    pub fn resume_offer(ctx: Context<SetOfferPaused>, _offer_id: u64) -> Result<()> {
+        set_offer_paused(&mut ctx.accounts.offer, false)
+    }
+
+    /// Mark an offer expired right away, so it can no longer be accepted,
+    /// while leaving the escrow in place: a later `cancel_offer` or, past the
+    /// grace period, `cancel_expired_offer` returns it
+    pub fn expire_offer(ctx: Context<SetOfferPaused>, _offer_id: u64) -> Result<()> {
+        let offer = &mut ctx.accounts.offer;
+        let clock = Clock::get()?;
+        require!(!offer.is_expired(&clock), ErrorCode::OfferExpired);
+        require!(!offer.is_sealed(&clock), ErrorCode::OfferSealed);
+        require!(!offer.is_cancel_locked(clock.unix_timestamp), ErrorCode::CancelLocked);
+        require!(!offer.is_held(clock.unix_timestamp), ErrorCode::OfferReserved);
+
+        offer.expiry_ts = Some(clock.unix_timestamp);
+
+        msg!("Offer {} force-expired", offer.offer_id);
+        Ok(())
+    }
+
+    /// Reserve an offer for the signing taker for `duration_secs`, during
+    /// which only they can accept it. The taker locks
+    /// `RESERVATION_DEPOSIT_LAMPORTS` in a `ReservationDeposit`, returned if
+    /// they accept before the reservation ends and forfeited to the owner
+    /// otherwise.
+    pub fn reserve_offer(
+        ctx: Context<ReserveOffer>,
+        _offer_id: u64,
+        duration_secs: i64,
+    ) -> Result<()> {
+        require!(
+            duration_secs > 0 && duration_secs <= MAX_RESERVATION_SECS,
+            ErrorCode::InvalidReservation
+        );
+
+        let offer = &mut ctx.accounts.offer;
+        let taker = ctx.accounts.taker.key();
+        let clock = Clock::get()?;
+
+        require!(!offer.is_expired(&clock), ErrorCode::OfferExpired);
+        require!(!offer.is_blocked(&taker), ErrorCode::TakerBlocked);
+        // Offers with a fill queue are only reserved in queue order
+        require!(offer.fill_queue_capacity == 0, ErrorCode::InvalidReservation);
+        require!(
+            !offer.is_reserved(clock.unix_timestamp) || offer.reserved_by == taker,
+            ErrorCode::OfferReserved
+        );
+
+        offer.reserved_by = taker;
+        offer.reserved_until = checked_add_ts(clock.unix_timestamp, duration_secs)?;
+
+        let deposit = &mut ctx.accounts.deposit;
+        if deposit.taker == Pubkey::default() {
+            system_program::transfer(
+                CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    system_program::Transfer {
+                        from: ctx.accounts.taker.to_account_info(),
+                        to: deposit.to_account_info(),
+                    },
+                ),
+                RESERVATION_DEPOSIT_LAMPORTS,
+            )?;
+            deposit.offer = offer.key();
+            deposit.taker = taker;
+            deposit.bump = ctx.bumps.deposit;
+        } else {
+            // A missed deadline can't be reset by reserving again
+            require!(
+                clock.unix_timestamp < deposit.settlement_deadline,
+                ErrorCode::InvalidReservation
+            );
+        }
+        deposit.owner = offer.current_owner;
+        deposit.settlement_deadline = offer.reserved_until;
+
+        msg!(
+            "Offer {} reserved by {} until {}",
+            offer.offer_id,
+            taker,
+            offer.reserved_until
+        );
+
+        Ok(())
+    }
+
+    /// Suggest a different `amount_wanted` to the maker; replaces any pending
+    /// counter-offer
+    pub fn propose_counter(
+        ctx: Context<ProposeCounter>,
+        _offer_id: u64,
+        new_amount_wanted: u64,
+    ) -> Result<()> {
+        require!(new_amount_wanted > 0, ErrorCode::InvalidAmount);
+
+        let offer = &mut ctx.accounts.offer;
+        let taker = ctx.accounts.taker.key();
+        let clock = Clock::get()?;
+
+        require!(!offer.is_expired(&clock), ErrorCode::OfferExpired);
+        require!(!offer.is_blocked(&taker), ErrorCode::TakerBlocked);
+        require!(offer.locked_taker.is_none(), ErrorCode::NoActiveCounter);
+
+        let counter = CounterOffer {
+            taker,
+            amount_wanted: new_amount_wanted,
+        };
+        // Takers may only raise their own counter, so the maker never settles
+        // below a price they saw; a full list drops its weakest counter
+        if let Some(pending) = offer.counter_offers.iter_mut().find(|c| c.taker == taker) {
+            require!(
+                new_amount_wanted > pending.amount_wanted,
+                ErrorCode::CounterTooLow
+            );
+            *pending = counter;
+        } else if offer.counter_offers.len() < MAX_COUNTER_OFFERS {
+            offer.counter_offers.push(counter);
+        } else {
+            let weakest = offer
+                .counter_offers
+                .iter_mut()
+                .min_by_key(|c| c.amount_wanted)
+                .ok_or(ErrorCode::CounterTooLow)?;
+            require!(
+                new_amount_wanted > weakest.amount_wanted,
+                ErrorCode::CounterTooLow
+            );
+            *weakest = counter;
+        }
+
+        msg!(
+            "Counter-offer of {} proposed on offer {} by {}",
+            new_amount_wanted,
+            offer.offer_id,
+            taker
+        );
+
+        Ok(())
+    }
+
+    /// Take the pending counter-offer: reprice the offer to it and lock the
+    /// offer to the proposing taker. `amount_wanted` must match the counter,
+    /// so a last-second re-proposal can't change what the maker agrees to.
+    pub fn accept_counter(
+        ctx: Context<AcceptCounter>,
+        _offer_id: u64,
+        amount_wanted: u64,
+    ) -> Result<()> {
+        let offer = &mut ctx.accounts.offer;
+        require!(
+            !offer.is_reserved(Clock::get()?.unix_timestamp),
+            ErrorCode::OfferReserved
+        );
+
+        let counter = offer
+            .counter_offers
+            .iter()
+            .find(|counter| counter.amount_wanted == amount_wanted)
+            .copied()
+            .ok_or(ErrorCode::NoActiveCounter)?;
+
+        offer.settle_counter(counter);
+
+        Ok(())
+    }
+
+    /// Take the highest pending counter-offer, the earliest on a tie, and
+    /// drop the rest; the offer is repriced and locked as in `accept_counter`
+    pub fn accept_best_counter(ctx: Context<AcceptCounter>, _offer_id: u64) -> Result<()> {
+        let offer = &mut ctx.accounts.offer;
+        require!(
+            !offer.is_reserved(Clock::get()?.unix_timestamp),
+            ErrorCode::OfferReserved
+        );
+
+        let best = offer
+            .counter_offers
+            .iter()
+            .copied()
+            .reduce(|best, counter| {
+                if counter.amount_wanted > best.amount_wanted {
+                    counter
+                } else {
+                    best
+                }
+            })
+            .ok_or(ErrorCode::NoActiveCounter)?;
+
+        offer.settle_counter(best);
+
+        Ok(())
+    }
+
+    /// End a reservation early (reserving taker or maker). On an offer with
+    /// a fill queue the next taker in line is served right away.
+    pub fn release_reservation(ctx: Context<ReleaseReservation>, _offer_id: u64) -> Result<()> {
+        let offer = &mut ctx.accounts.offer;
+        let signer = ctx.accounts.signer.key();
+        require!(
+            signer == offer.reserved_by || signer == offer.current_owner,
+            ErrorCode::Unauthorized
+        );
+
+        offer.reserved_by = Pubkey::default();
+        offer.reserved_until = 0;
+        offer.serve_fill_queue(Clock::get()?.unix_timestamp)?;
+
+        msg!("Offer {} reservation released", offer.offer_id);
+
+        Ok(())
+    }
+
+    /// First step of accepting an offer above `config.high_value_threshold`:
+    /// hold the offer for the signing taker until they `finalize_accept` it,
+    /// for `config.accept_commit_ttl_secs`. Other takers can't fill it
+    /// meanwhile, and the owner may still cancel.
+    pub fn commit_accept(ctx: Context<CommitAccept>, _offer_id: u64) -> Result<()> {
+        let ttl = accept_commit_ttl(&ctx.accounts.config)?;
+        let offer = &mut ctx.accounts.offer;
+        let taker = ctx.accounts.taker.key();
+        let clock = Clock::get()?;
+
+        require!(!offer.is_expired(&clock), ErrorCode::OfferExpired);
+        require!(!offer.is_blocked(&taker), ErrorCode::TakerBlocked);
+        require!(
+            !offer.is_reserved(clock.unix_timestamp) || offer.reserved_by == taker,
+            ErrorCode::OfferReserved
+        );
+        // A live commit can't be extended by committing again
+        require!(!offer.is_committed(clock.unix_timestamp), ErrorCode::OfferReserved);
+
+        offer.committed_taker = Some(taker);
+        offer.commit_expires_at = checked_add_ts(clock.unix_timestamp, ttl)?;
+
+        msg!(
+            "Offer {} committed to {} until {}",
+            offer.offer_id,
+            taker,
+            offer.commit_expires_at
+        );
+
+        Ok(())
+    }
+
+    /// Second step of a two-step accept: fill the offer whole for the taker
+    /// holding a live commit on it
+    pub fn finalize_accept(ctx: Context<AcceptOffer>, _offer_id: u64) -> Result<()> {
+        ctx.accounts
+            .offer
+            .check_commit(&ctx.accounts.taker.key(), Clock::get()?.unix_timestamp)?;
+
+        reprice_from_oracle(ctx.accounts)?;
+        let amount_in = ctx
+            .accounts
+            .offer
+            .quote_for(&ctx.accounts.mint_wanted.key())
+            .ok_or(ErrorCode::MintNotAccepted)?;
+        let amount_out = ctx.accounts.offer.amount_offered;
+        fill_offer(ctx.accounts, amount_in, amount_out, false, false, false)
+    }
+
+    /// Collect the deposit of a taker who reserved one of the owner's offers
+    /// but didn't accept it before their settlement deadline
+    pub fn claim_forfeited_deposit(ctx: Context<ClaimForfeitedDeposit>) -> Result<()> {
+        let deposit = &ctx.accounts.deposit;
+        require!(
+            Clock::get()?.unix_timestamp >= deposit.settlement_deadline,
+            ErrorCode::DepositNotForfeited
+        );
+
+        msg!(
+            "Reservation deposit of {} on {} forfeited to {}",
+            deposit.taker,
+            deposit.offer,
+            deposit.owner
+        );
+
+        Ok(())
+    }
+
+    /// Line up for an offer with a fill queue, waiting at most `wait_secs`
+    /// to be served. Served takers hold the offer for
+    /// `FILL_QUEUE_TURN_SECS`; the taker locks `FILL_QUEUE_DEPOSIT_LAMPORTS`
+    /// in a `QueueTicket` until they leave the queue.
+    pub fn join_fill_queue(
+        ctx: Context<JoinFillQueue>,
+        _offer_id: u64,
+        wait_secs: i64,
+    ) -> Result<()> {
+        require!(
+            wait_secs > 0 && wait_secs <= MAX_FILL_QUEUE_WAIT_SECS,
+            ErrorCode::InvalidReservation
+        );
+
+        let offer = &mut ctx.accounts.offer;
+        let taker = ctx.accounts.taker.key();
+        let clock = Clock::get()?;
+
+        require!(!offer.is_expired(&clock), ErrorCode::OfferExpired);
+        require!(!offer.is_blocked(&taker), ErrorCode::TakerBlocked);
+        require!(offer.fill_queue_capacity > 0, ErrorCode::FillQueueDisabled);
+        require!(
+            offer.fill_queue.len() < usize::from(offer.fill_queue_capacity),
+            ErrorCode::FillQueueFull
+        );
+
+        system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                system_program::Transfer {
+                    from: ctx.accounts.taker.to_account_info(),
+                    to: ctx.accounts.ticket.to_account_info(),
+                },
+            ),
+            FILL_QUEUE_DEPOSIT_LAMPORTS,
+        )?;
+
+        let ticket = &mut ctx.accounts.ticket;
+        ticket.offer = offer.key();
+        ticket.taker = taker;
+        ticket.bump = ctx.bumps.ticket;
+
+        let wait_until = checked_add_ts(clock.unix_timestamp, wait_secs)?;
+        offer.fill_queue.push(QueuedTaker { taker, wait_until });
+        offer.serve_fill_queue(clock.unix_timestamp)?;
+
+        msg!(
+            "{} joined the fill queue of offer {} ({} waiting)",
+            taker,
+            offer.offer_id,
+            offer.fill_queue.len()
+        );
+
+        Ok(())
+    }
+
+    /// Serve the next taker in an offer's fill queue once the current turn
+    /// has run out, skipping takers whose wait is over (anyone may call)
+    pub fn serve_fill_queue(ctx: Context<ServeFillQueue>, _offer_id: u64) -> Result<()> {
+        let offer = &mut ctx.accounts.offer;
+        let now = Clock::get()?.unix_timestamp;
+        require!(!offer.is_reserved(now), ErrorCode::OfferReserved);
+
+        offer.serve_fill_queue(now)?;
+
+        msg!(
+            "Offer {} fill queue served {}",
+            offer.offer_id,
+            offer.reserved_by
+        );
+
+        Ok(())
+    }
+
+    /// Leave an offer's fill queue, giving up any turn being held, and get
+    /// the ticket's deposit and rent back. Works after the offer is gone too.
+    pub fn leave_fill_queue(ctx: Context<LeaveFillQueue>) -> Result<()> {
+        let offer_info = &ctx.accounts.offer;
+        let taker = ctx.accounts.taker.key();
+
+        if *offer_info.owner == crate::ID && !offer_info.data_is_empty() {
+            let mut data = offer_info.try_borrow_mut_data()?;
+            let mut offer = Offer::try_deserialize(&mut &data[..])?;
+            offer.fill_queue.retain(|queued| queued.taker != taker);
+            if offer.reserved_by == taker {
+                offer.reserved_by = Pubkey::default();
+                offer.reserved_until = 0;
+                offer.serve_fill_queue(Clock::get()?.unix_timestamp)?;
+            }
+            offer.try_serialize(&mut &mut data[..])?;
+        }
+
+        msg!("{} left the fill queue of {}", taker, offer_info.key());
+
+        Ok(())
+    }
+
+    /// Return the tokens in a vault whose offer no longer exists to the
+    /// offer's maker and close the vault (admin only). `offer_seed` is the
+    /// offer's ID seed, proving the vault belongs to `maker`.
+    pub fn sweep_orphan_vault(
+        ctx: Context<SweepOrphanVault>,
+        _offer_seed: Vec<u8>,
+    ) -> Result<()> {
+        let vault = &ctx.accounts.vault;
+        let amount = vault.amount;
+
+        let offer_key = ctx.accounts.offer.key();
+        let mint_key = ctx.accounts.mint.key();
+        let seeds = &[
+            b"vault",
+            offer_key.as_ref(),
+            mint_key.as_ref(),
+            &[ctx.bumps.vault],
+        ];
+        let signer = &[&seeds[..]];
+
+        let cpi_accounts = Transfer {
+            from: vault.to_account_info(),
+            to: ctx.accounts.maker_token_account.to_account_info(),
+            authority: vault.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        token::transfer(cpi_ctx, amount)?;
+
+        // Close vault token account (refund rent to maker)
+        let cpi_accounts = CloseAccount {
+            account: vault.to_account_info(),
+            destination: ctx.accounts.maker.to_account_info(),
+            authority: vault.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        token::close_account(cpi_ctx)?;
+
+        msg!(
+            "Swept orphan vault {} ({} tokens) back to {}",
+            vault.key(),
+            amount,
+            ctx.accounts.maker.key()
+        );
+
+        Ok(())
+    }
+
+    /// Send tokens transferred straight to an offer's vault by mistake, all
+    /// it holds above what the offer escrows, to `destination`. Callable by
+    /// the offer's owner or the protocol admin; the escrow stays in place.
+    pub fn rescue_excess(ctx: Context<RescueExcess>, _offer_id: u64) -> Result<()> {
+        let accounts = ctx.accounts;
+        let offer = &accounts.offer;
+        let authority = accounts.authority.key();
+        let is_admin =
+            load_config(&accounts.config)?.is_some_and(|config| config.admin == authority);
+        require!(
+            authority == offer.current_owner || is_admin,
+            ErrorCode::Unauthorized
+        );
+        // A shared vault's balance can't be told apart between its offers
+        require!(!offer.omnibus_vault, ErrorCode::SharedVault);
+
+        let excess = accounts.vault.amount.saturating_sub(offer.amount_escrowed());
+        require!(excess > 0, ErrorCode::NoExcessFunds);
+
+        release_escrow(
+            offer,
+            &accounts.vault,
+            &accounts.destination,
+            &accounts.authority.to_account_info(),
+            &accounts.token_program,
+            excess,
+            false,
+        )?;
+
+        msg!(
+            "Rescued {} excess tokens from vault {} to {}",
+            excess,
+            accounts.vault.key(),
+            accounts.destination.key()
+        );
+
+        Ok(())
+    }
+
+    /// Withdraw `amount` of accumulated protocol fees from a token account
+    /// held by the program's treasury PDA to `destination` (admin only).
+    /// Fees only accumulate there once `config.treasury` is set to the PDA.
+    pub fn withdraw_fees(ctx: Context<WithdrawFees>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        require!(
+            amount <= ctx.accounts.fee_account.amount,
+            ErrorCode::InsufficientBalance
+        );
+
+        let seeds = &[b"treasury".as_ref(), &[ctx.bumps.treasury]];
+        let signer = &[&seeds[..]];
+
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.fee_account.to_account_info(),
+            to: ctx.accounts.destination.to_account_info(),
+            authority: ctx.accounts.treasury.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        token::transfer(cpi_ctx, amount)?;
+
+        let global_stats = &mut ctx.accounts.global_stats;
+        global_stats.bump = ctx.bumps.global_stats;
+        global_stats.fees_withdrawn = global_stats
+            .fees_withdrawn
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        global_stats.withdrawals = global_stats
+            .withdrawals
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        emit!(FeesWithdrawn {
+            mint: ctx.accounts.fee_account.mint,
+            amount,
+            destination: ctx.accounts.destination.key(),
+            admin: ctx.accounts.admin.key(),
+            total_withdrawn: global_stats.fees_withdrawn,
+        });
+
+        msg!(
+            "Withdrew {} fees of mint {} to {}",
+            amount,
+            ctx.accounts.fee_account.mint,
+            ctx.accounts.destination.key()
+        );
+
+        Ok(())
+    }
+
+    /// Accept several offers in full, all or nothing. `remaining_accounts`
+    /// holds the `AcceptOffer` accounts of each entry of `offer_ids`, in the
+    /// same order; any failing accept reverts the whole batch. A single
+    /// `BatchAccepted` event sums up the fills, with each fill's own
+    /// `OfferAccepted` emitted too only if `emit_fill_events`.
+    pub fn accept_offers_batch<'info>(
+        ctx: Context<'_, '_, 'info, 'info, AcceptOffersBatch<'info>>,
+        offer_ids: Vec<u64>,
+        emit_fill_events: bool,
+    ) -> Result<()> {
+        require!(!offer_ids.is_empty(), ErrorCode::InvalidAmount);
+        require!(offer_ids.len() <= MAX_BATCH_SIZE, ErrorCode::BatchTooLarge);
+        check_compute_budget(offer_ids.len(), BATCH_ENTRY_COMPUTE_UNITS)?;
+
+        let mut remaining = ctx.remaining_accounts;
+        let mut total_amount_in: u64 = 0;
+        let mut total_amount_out: u64 = 0;
+        for offer_id in offer_ids.iter() {
+            // Runs every `AcceptOffer` constraint: offer and vault PDAs, mints, owners
+            let mut accounts = AcceptOffer::try_accounts(
+                ctx.program_id,
+                &mut remaining,
+                &offer_id.to_le_bytes(),
+                &mut AcceptOfferBumps::default(),
+                &mut BTreeSet::new(),
+            )?;
+            require_keys_eq!(
+                accounts.taker.key(),
+                ctx.accounts.taker.key(),
+                ErrorCode::Unauthorized
+            );
+
+            reprice_from_oracle(&mut accounts)?;
+            let amount_in = accounts
+                .offer
+                .quote_for(&accounts.mint_wanted.key())
+                .ok_or(ErrorCode::MintNotAccepted)?;
+            let amount_out = accounts.offer.amount_offered;
+            settle_fill(
+                &mut accounts,
+                amount_in,
+                amount_out,
+                false,
+                false,
+                false,
+                emit_fill_events,
+            )?;
+            accounts.exit(ctx.program_id)?;
+
+            total_amount_in = total_amount_in
+                .checked_add(amount_in)
+                .ok_or(ErrorCode::MathOverflow)?;
+            total_amount_out = total_amount_out
+                .checked_add(amount_out)
+                .ok_or(ErrorCode::MathOverflow)?;
+        }
+
+        emit!(BatchAccepted {
+            taker: ctx.accounts.taker.key(),
+            offers_filled: offer_ids.len() as u8,
+            total_amount_in,
+            total_amount_out,
+        });
+
+        msg!(
+            "Batch of {} offers accepted by {}",
+            offer_ids.len(),
+            ctx.accounts.taker.key()
+        );
+
+        Ok(())
+    }
+
+    /// Accept an auto-relist offer in full and escrow the maker's proceeds
+    /// into a new offer, `new_offer_id`, wanting `auto_relist_amount_wanted`
+    /// of the original offered mint. `remaining_accounts` holds the
+    /// `AcceptOffer` accounts, as for `accept_offers_batch`. The maker must
+    /// have approved the `delegate` PDA over their wanted-mint token account,
+    /// which the proceeds are moved out of; the taker pays the new offer's
+    /// rent.
+    pub fn accept_and_relist<'info>(
+        ctx: Context<'_, '_, 'info, 'info, AcceptAndRelist<'info>>,
+        offer_id: u64,
+        new_offer_id: u64,
+    ) -> Result<()> {
+        let mut remaining = ctx.remaining_accounts;
+        let mut accept = AcceptOffer::try_accounts(
+            ctx.program_id,
+            &mut remaining,
+            &offer_id.to_le_bytes(),
+            &mut AcceptOfferBumps::default(),
+            &mut BTreeSet::new(),
+        )?;
+        let accounts = ctx.accounts;
+        require_keys_eq!(
+            accept.taker.key(),
+            accounts.taker.key(),
+            ErrorCode::Unauthorized
+        );
+        require_keys_eq!(
+            accept.maker.key(),
+            accounts.maker.key(),
+            ErrorCode::Unauthorized
+        );
+        // The proceeds leave through the maker's own approval only
+        require_keys_eq!(
+            accept.maker_token_account_wanted.owner,
+            accounts.maker.key(),
+            ErrorCode::Unauthorized
+        );
+        require!(
+            new_offer_id == accept.user_profile.offer_count,
+            ErrorCode::InvalidOfferId
+        );
+        require_keys_eq!(
+            accounts.mint_offered.key(),
+            accept.offer.mint_wanted,
+            ErrorCode::InvalidMint
+        );
+        require_keys_eq!(
+            accounts.mint_wanted.key(),
+            accept.offer.mint_offered,
+            ErrorCode::InvalidMint
+        );
+        let relist_amount_wanted = accept
+            .offer
+            .auto_relist_amount_wanted
+            .take()
+            .ok_or(ErrorCode::NotAutoRelist)?;
+        let options = OfferOptions {
+            category: accept.offer.category,
+            ..OfferOptions::default()
+        };
+
+        reprice_from_oracle(&mut accept)?;
+        let amount_in = accept
+            .offer
+            .quote_for(&accept.mint_wanted.key())
+            .ok_or(ErrorCode::MintNotAccepted)?;
+        let amount_out = accept.offer.amount_offered;
+        let balance_before = accept.maker_token_account_wanted.amount;
+        fill_offer(&mut accept, amount_in, amount_out, false, false, false)?;
+
+        // Whatever reached the maker after fees and cuts is relisted
+        accept.maker_token_account_wanted.reload()?;
+        let proceeds = accept
+            .maker_token_account_wanted
+            .amount
+            .checked_sub(balance_before)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        // The proceeds are escrowed again through the legacy token program
+        let proceeds_info = ctx
+            .remaining_accounts
+            .iter()
+            .find(|info| info.key() == accept.maker_token_account_wanted.key())
+            .ok_or(ErrorCode::MakerAccountUnavailable)?;
+        let proceeds_account = Account::<TokenAccount>::try_from(proceeds_info)?;
+        let delegate_seeds: &[&[u8]] = &[b"delegate", &[ctx.bumps.delegate]];
+        open_offer(
+            &mut accept.user_profile,
+            &mut accounts.new_offer,
+            &mut accounts.new_vault,
+            &proceeds_account,
+            &accounts.mint_offered,
+            &accounts.mint_wanted,
+            &accounts.maker.to_account_info(),
+            &accounts.delegate.to_account_info(),
+            &[delegate_seeds],
+            &accept.token_program,
+            &accounts.system_program,
+            None,
+            &OfferGates {
+                config: &accept.config,
+                mint_tvl: &accounts.new_mint_tvl,
+                mint_tvl_bump: ctx.bumps.new_mint_tvl,
+                payer: &accounts.taker.to_account_info(),
+                // The relisted offer trades the accepted one's mints the other way
+                mint_status_offered: &accept.mint_status_wanted,
+                mint_status_wanted: &accept.mint_status_offered,
+                maker_status: accounts.maker_status.as_ref(),
+            },
+            proceeds,
+            relist_amount_wanted,
+            &options,
+            None,
+            ctx.bumps.new_offer,
+            ctx.bumps.new_vault,
+            false,
+        )?;
+        accept.exit(ctx.program_id)?;
+
+        msg!(
+            "Offer {} accepted and relisted as offer {}",
+            offer_id,
+            new_offer_id
+        );
+
+        Ok(())
+    }
+
+    /// Accept an offer whole and credit the taker's `LoyaltyAccount` one point
+    /// per base unit of protocol fee the fill paid. `remaining_accounts`
+    /// holds the offer's `AcceptOffer` accounts. Points are only a counter;
+    /// no tokens move for them.
+    pub fn accept_with_loyalty<'info>(
+        ctx: Context<'_, '_, 'info, 'info, AcceptWithLoyalty<'info>>,
+        offer_id: u64,
+    ) -> Result<()> {
+        let mut remaining = ctx.remaining_accounts;
+        let mut accept = AcceptOffer::try_accounts(
+            ctx.program_id,
+            &mut remaining,
+            &offer_id.to_le_bytes(),
+            &mut AcceptOfferBumps::default(),
+            &mut BTreeSet::new(),
+        )?;
+        let accounts = ctx.accounts;
+        require_keys_eq!(
+            accept.taker.key(),
+            accounts.taker.key(),
+            ErrorCode::Unauthorized
+        );
+
+        reprice_from_oracle(&mut accept)?;
+        let amount_in = accept
+            .offer
+            .quote_for(&accept.mint_wanted.key())
+            .ok_or(ErrorCode::MintNotAccepted)?;
+        let amount_out = accept.offer.amount_offered;
+        let fill_result =
+            settle_fill(&mut accept, amount_in, amount_out, false, false, false, true)?;
+        accept.exit(ctx.program_id)?;
+
+        let loyalty_account = &mut accounts.loyalty_account;
+        loyalty_account.taker = accounts.taker.key();
+        loyalty_account.bump = ctx.bumps.loyalty_account;
+        loyalty_account.points = loyalty_account
+            .points
+            .checked_add(fill_result.fee)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        msg!(
+            "Offer {} accepted by {}: {} loyalty points, {} total",
+            offer_id,
+            accounts.taker.key(),
+            fill_result.fee,
+            loyalty_account.points
+        );
+
+        Ok(())
+    }
+
+    /// Return a taker's loyalty points as a Borsh `u64` in return data, for
+    /// frontends to read through simulation
+    pub fn loyalty_points(ctx: Context<LoyaltyPoints>) -> Result<()> {
+        set_return_data(&ctx.accounts.loyalty_account.points.to_le_bytes());
+        Ok(())
+    }
+
+    /// Cancel an expired offer on the maker's behalf once the grace period
+    /// after expiry, reserved for the maker, has passed. The escrow and all
+    /// rent return to the maker, less the caller's tip out of the offer's
+    /// rent: `KEEPER_TIP_LAMPORTS_PER_SEC` for every second since the grace
+    /// period ended, up to `MAX_KEEPER_TIP_LAMPORTS`.
+    pub fn cancel_expired_offer(ctx: Context<CancelExpiredOffer>, _offer_id: u64) -> Result<()> {
+        let offer = &ctx.accounts.offer;
+        let clock = Clock::get()?;
+
+        let expiry_ts = offer.expiry_ts.ok_or(ErrorCode::InvalidExpiry)?;
+        require!(offer.is_expired(&clock), ErrorCode::NotExpiredYet);
+        let grace_ends = checked_add_ts(expiry_ts, expiry_grace(&ctx.accounts.config)?)?;
+        require!(clock.unix_timestamp > grace_ends, ErrorCode::GracePeriodActive);
+
+        validate_vault(&ctx.accounts.vault, offer)?;
+
+        let amount_returned = refund_vault(
+            offer,
+            &ctx.accounts.vault,
+            &ctx.accounts.maker_token_account,
+            &ctx.accounts.maker.to_account_info(),
+            &ctx.accounts.token_program,
+            &ctx.accounts.mint_tvl,
+        )?;
+
+        ctx.accounts.user_profile.release_offer_slot()?;
+
+        emit!(OfferCancelled {
+            offer: offer.key(),
+            maker: offer.maker,
+            offer_id: offer.offer_id,
+            amount_returned,
+        });
+
+        // The longer an offer lingers, the more its cleanup pays
+        let unclaimed_secs = (clock.unix_timestamp - grace_ends) as u64;
+        let offer_info = offer.to_account_info();
+        let tip = unclaimed_secs
+            .saturating_mul(KEEPER_TIP_LAMPORTS_PER_SEC)
+            .min(MAX_KEEPER_TIP_LAMPORTS)
+            .min(offer_info.lamports());
+        **offer_info.try_borrow_mut_lamports()? -= tip;
+        **ctx.accounts.keeper.try_borrow_mut_lamports()? += tip;
+
+        msg!(
+            "Expired offer {} cancelled by {} for a {} lamport tip",
+            offer.offer_id,
+            ctx.accounts.keeper.key(),
+            tip
+        );
+
+        Ok(())
+    }
+
+    /// Close an offer whose remainder fell below its dust threshold,
+    /// returning the dust and all rent to the maker. Anyone may call it.
+    pub fn cleanup_dust_offer(ctx: Context<CleanupDustOffer>, _offer_id: u64) -> Result<()> {
+        let offer = &ctx.accounts.offer;
+        require!(offer.is_dust(), ErrorCode::OfferNotDust);
+
+        validate_vault(&ctx.accounts.vault, offer)?;
+
+        let amount_returned = refund_vault(
+            offer,
+            &ctx.accounts.vault,
+            &ctx.accounts.maker_token_account,
+            &ctx.accounts.maker.to_account_info(),
+            &ctx.accounts.token_program,
+            &ctx.accounts.mint_tvl,
+        )?;
+
+        ctx.accounts.user_profile.release_offer_slot()?;
+
+        emit!(OfferCancelled {
+            offer: offer.key(),
+            maker: offer.maker,
+            offer_id: offer.offer_id,
+            amount_returned,
+        });
+
+        msg!(
+            "Dust offer {} cleaned up by {}",
+            offer.offer_id,
+            ctx.accounts.keeper.key()
+        );
+
+        Ok(())
+    }
+
+    /// Return the escrow of an offer whose maker can no longer be paid, as
+    /// their wanted-mint token account has been frozen by the mint's freeze
+    /// authority. The escrow and all rent go back to the maker. Anyone may
+    /// call it.
+    pub fn cancel_if_frozen(ctx: Context<CancelIfFrozen>, _offer_id: u64) -> Result<()> {
+        let offer = &ctx.accounts.offer;
+        require!(
+            ctx.accounts.maker_token_account_wanted.is_frozen(),
+            ErrorCode::AccountNotFrozen
+        );
+
+        validate_vault(&ctx.accounts.vault, offer)?;
+
+        let amount_returned = refund_vault(
+            offer,
+            &ctx.accounts.vault,
+            &ctx.accounts.maker_token_account,
+            &ctx.accounts.maker.to_account_info(),
+            &ctx.accounts.token_program,
+            &ctx.accounts.mint_tvl,
+        )?;
+
+        ctx.accounts.user_profile.release_offer_slot()?;
+
+        emit!(OfferCancelled {
+            offer: offer.key(),
+            maker: offer.maker,
+            offer_id: offer.offer_id,
+            amount_returned,
+        });
+
+        msg!(
+            "Offer {} with a frozen maker account cancelled by {}",
+            offer.offer_id,
+            ctx.accounts.caller.key()
+        );
+
+        Ok(())
+    }
+
+    /// Settle two mirrored offers against each other without a taker: each
+    /// vault's escrow goes to the other offer's owner. When one offer is
+    /// larger, only the overlap is settled and the larger one keeps its
+    /// remainder, see `match_amounts`. Callable by anyone, e.g. a keeper
+    /// watching for crossing offers.
+    pub fn match_offers(ctx: Context<MatchOffers>) -> Result<()> {
+        let clock = Clock::get()?;
+        let offer_a = &ctx.accounts.offer_a;
+        let offer_b = &ctx.accounts.offer_b;
+
+        validate_vault(&ctx.accounts.vault_a, offer_a)?;
+        validate_vault(&ctx.accounts.vault_b, offer_b)?;
+        check_matchable(offer_a, offer_b, &clock)?;
+        check_matchable(offer_b, offer_a, &clock)?;
+        check_mints_unpaused(&ctx.accounts.mint_status_a, &ctx.accounts.mint_status_b)?;
+        check_no_emergency(&ctx.accounts.config)?;
+        require_keys_neq!(
+            ctx.accounts.maker_a_token_account.key(),
+            ctx.accounts.maker_b_token_account.key(),
+            ErrorCode::DuplicateAccount
+        );
+        let (amount_a, amount_b) = match_amounts(offer_a, offer_b)?;
+
+        release_escrow(
+            offer_a,
+            &ctx.accounts.vault_a,
+            &ctx.accounts.maker_b_token_account,
+            &ctx.accounts.maker_a.to_account_info(),
+            &ctx.accounts.token_program,
+            amount_a,
+            amount_a == offer_a.amount_offered,
+        )?;
+        release_escrow(
+            offer_b,
+            &ctx.accounts.vault_b,
+            &ctx.accounts.maker_a_token_account,
+            &ctx.accounts.maker_b.to_account_info(),
+            &ctx.accounts.token_program,
+            amount_b,
+            amount_b == offer_b.amount_offered,
+        )?;
+        if offer_a.tvl_tracked {
+            release_mint_tvl(&ctx.accounts.mint_tvl_a, amount_a)?;
+        }
+        if offer_b.tvl_tracked {
+            release_mint_tvl(&ctx.accounts.mint_tvl_b, amount_b)?;
+        }
+
+        let owner_a = offer_a.current_owner;
+        let owner_b = offer_b.current_owner;
+        record_match(&mut ctx.accounts.offer_a, amount_a, amount_b, owner_b, clock.unix_timestamp)?;
+        record_match(&mut ctx.accounts.offer_b, amount_b, amount_a, owner_a, clock.unix_timestamp)?;
+
+        emit!(OffersMatched {
+            offer_a: ctx.accounts.offer_a.key(),
+            offer_b: ctx.accounts.offer_b.key(),
+            amount_a,
+            amount_b,
+            keeper: ctx.accounts.keeper.key(),
+            remaining_a: ctx.accounts.offer_a.amount_offered,
+            remaining_b: ctx.accounts.offer_b.amount_offered,
+        });
+
+        msg!(
+            "Offers {} and {} matched by {}",
+            ctx.accounts.offer_a.key(),
+            ctx.accounts.offer_b.key(),
+            ctx.accounts.keeper.key()
+        );
+
+        if ctx.accounts.offer_a.amount_offered == 0 {
+            ctx.accounts.maker_a_profile.release_offer_slot()?;
+            ctx.accounts
+                .offer_a
+                .close(ctx.accounts.maker_a.to_account_info())?;
+        }
+        if ctx.accounts.offer_b.amount_offered == 0 {
+            ctx.accounts.maker_b_profile.release_offer_slot()?;
+            ctx.accounts
+                .offer_b
+                .close(ctx.accounts.maker_b.to_account_info())?;
+        }
+
+        Ok(())
+    }
+
+    /// Recovery escape hatch: cancel an offer without the maker's signature,
+    /// returning the escrow and all rent to the maker's recorded address, for
+    /// makers who lost access to their wallet (admin only)
+    pub fn force_reclaim(ctx: Context<ForceReclaim>, _offer_id: u64) -> Result<()> {
+        let offer = &ctx.accounts.offer;
+
+        validate_vault(&ctx.accounts.vault, offer)?;
+
+        let amount_returned = refund_vault(
+            offer,
+            &ctx.accounts.vault,
+            &ctx.accounts.maker_token_account,
+            &ctx.accounts.maker.to_account_info(),
+            &ctx.accounts.token_program,
+            &ctx.accounts.mint_tvl,
+        )?;
+
+        ctx.accounts.user_profile.release_offer_slot()?;
+
+        emit!(OfferCancelled {
+            offer: offer.key(),
+            maker: offer.maker,
+            offer_id: offer.offer_id,
+            amount_returned,
+        });
+
+        msg!(
+            "Offer {} reclaimed for its maker by admin {}",
+            offer.offer_id,
+            ctx.accounts.admin.key()
+        );
+
+        Ok(())
+    }
+
+    /// Last-resort safety valve: once an offer is older than
+    /// `MAX_ESCROW_AGE_SECS`, anyone may return its escrow and all rent to
+    /// the owner, whatever its expiry or seal, so funds are never stuck
+    pub fn emergency_withdraw(ctx: Context<EmergencyWithdraw>, _offer_id: u64) -> Result<()> {
+        let offer = &ctx.accounts.offer;
+        let clock = Clock::get()?;
+
+        require!(
+            clock.unix_timestamp.saturating_sub(offer.created_at) > MAX_ESCROW_AGE_SECS,
+            ErrorCode::EscrowNotStale
+        );
+        validate_vault(&ctx.accounts.vault, offer)?;
+
+        let amount_returned = refund_vault(
+            offer,
+            &ctx.accounts.vault,
+            &ctx.accounts.maker_token_account,
+            &ctx.accounts.maker.to_account_info(),
+            &ctx.accounts.token_program,
+            &ctx.accounts.mint_tvl,
+        )?;
+
+        ctx.accounts.user_profile.release_offer_slot()?;
+
+        emit!(OfferCancelled {
+            offer: offer.key(),
+            maker: offer.maker,
+            offer_id: offer.offer_id,
+            amount_returned,
+        });
+
+        msg!(
+            "Offer {} created at {} returned to its maker by {}",
+            offer.offer_id,
+            offer.created_at,
+            ctx.accounts.caller.key()
+        );
+
+        Ok(())
+    }
+
+    /// While an emergency is declared, let anyone return any open offer's
+    /// escrow and all rent to its owner, whatever its expiry or seal. Funds
+    /// never go to the caller. SOL offers and sell bundles have their own
+    /// variants below; delegated offers escrow nothing, as their makers keep
+    /// the tokens and can revoke the approval themselves.
+    pub fn emergency_reclaim(ctx: Context<EmergencyReclaim>, _offer_id: u64) -> Result<()> {
+        require!(ctx.accounts.config.emergency, ErrorCode::NoEmergency);
+
+        let offer = &ctx.accounts.offer;
+        validate_vault(&ctx.accounts.vault, offer)?;
+
+        let amount_returned = refund_vault(
+            offer,
+            &ctx.accounts.vault,
+            &ctx.accounts.maker_token_account,
+            &ctx.accounts.maker.to_account_info(),
+            &ctx.accounts.token_program,
+            &ctx.accounts.mint_tvl,
+        )?;
+
+        ctx.accounts.user_profile.release_offer_slot()?;
+
+        emit!(OfferCancelled {
+            offer: offer.key(),
+            maker: offer.maker,
+            offer_id: offer.offer_id,
+            amount_returned,
+        });
+
+        msg!(
+            "Offer {} returned to its owner in an emergency by {}",
+            offer.offer_id,
+            ctx.accounts.caller.key()
+        );
+
+        Ok(())
+    }
+
+    /// `emergency_reclaim` for SOL offers: the escrowed lamports and the rent
+    /// go back to the maker
+    pub fn emergency_reclaim_sol_offer(
+        ctx: Context<EmergencyReclaimSolOffer>,
+        _offer_id: u64,
+    ) -> Result<()> {
+        require!(ctx.accounts.config.emergency, ErrorCode::NoEmergency);
+
+        release_mint_tvl(&ctx.accounts.mint_tvl, ctx.accounts.sol_offer.lamports_offered)?;
+        ctx.accounts.user_profile.release_offer_slot()?;
+
+        msg!(
+            "SOL offer {} returned to its maker in an emergency by {}",
+            ctx.accounts.sol_offer.offer_id,
+            ctx.accounts.caller.key()
+        );
+
+        Ok(())
+    }
+
+    /// `emergency_reclaim` for sell bundles. `remaining_accounts` holds the
+    /// same groups as `cancel_sell_bundle`; each destination must belong to
+    /// the maker.
+    pub fn emergency_reclaim_bundle<'info>(
+        ctx: Context<'_, '_, 'info, 'info, EmergencyReclaimBundle<'info>>,
+        _offer_id: u64,
+    ) -> Result<()> {
+        require!(ctx.accounts.config.emergency, ErrorCode::NoEmergency);
+
+        let bundle = &ctx.accounts.bundle;
+        require!(
+            ctx.remaining_accounts.len() == bundle.items.len() * 3,
+            ErrorCode::InvalidBundle
+        );
+        for group in ctx.remaining_accounts.chunks(3) {
+            let destination = Account::<TokenAccount>::try_from(&group[1])?;
+            require_keys_eq!(destination.owner, bundle.maker, ErrorCode::Unauthorized);
+        }
+        release_bundle(
+            bundle,
+            ctx.remaining_accounts,
+            &ctx.accounts.maker.to_account_info(),
+            &ctx.accounts.token_program,
+            ctx.program_id,
+            false,
+        )?;
+        ctx.accounts.user_profile.release_offer_slot()?;
+
+        msg!(
+            "Sell bundle {} returned to its maker in an emergency by {}",
+            bundle.offer_id,
+            ctx.accounts.caller.key()
+        );
+
+        Ok(())
+    }
+
+    /// Cancel an offer whose maker's profile no longer exists, e.g. one
+    /// created before open offers were counted, so `close_user_profile` let
+    /// the profile go. Offer PDAs derive from the maker key alone, so the
+    /// offer stays addressable; the escrow and all rent go back to the maker.
+    pub fn orphan_offer_reclaim(ctx: Context<OrphanOfferReclaim>, _offer_id: u64) -> Result<()> {
+        let offer = &ctx.accounts.offer;
+        let clock = Clock::get()?;
+
+        require!(!offer.is_sealed(&clock), ErrorCode::OfferSealed);
+        validate_vault(&ctx.accounts.vault, offer)?;
+
+        let amount_returned = refund_vault(
+            offer,
+            &ctx.accounts.vault,
+            &ctx.accounts.maker_token_account,
+            &ctx.accounts.maker.to_account_info(),
+            &ctx.accounts.token_program,
+            &ctx.accounts.mint_tvl,
+        )?;
+
+        emit!(OfferCancelled {
+            offer: offer.key(),
+            maker: offer.maker,
+            offer_id: offer.offer_id,
+            amount_returned,
+        });
+
+        msg!("Orphaned offer {} reclaimed", offer.offer_id);
+
+        Ok(())
+    }
+
+    /// Create an offer submitted and paid for by a relayer on behalf of a
+    /// maker who signed `terms` off-chain. The transaction must carry an
+    /// Ed25519 program verification of that signature just before this
+    /// instruction, and the maker must have approved the program's delegate
+    /// PDA to move the offered tokens.
+    pub fn create_offer_relayed(
+        ctx: Context<CreateOfferRelayed>,
+        terms: RelayedOfferTerms,
+    ) -> Result<()> {
+        let accounts = ctx.accounts;
+
+        // Binding the signature to the maker's next offer ID stops replays
+        require!(
+            terms.offer_id == accounts.user_profile.offer_count,
+            ErrorCode::InvalidOfferId
+        );
+        require_keys_eq!(
+            terms.mint_offered,
+            accounts.mint_offered.key(),
+            ErrorCode::InvalidMint
+        );
+        require_keys_eq!(
+            terms.mint_wanted,
+            accounts.mint_wanted.key(),
+            ErrorCode::InvalidMint
+        );
+        verify_ed25519_signature(
+            &accounts.instructions,
+            &accounts.maker.key(),
+            &terms.try_to_vec()?,
+        )?;
+
+        let delegate_seeds: &[&[u8]] = &[b"delegate", &[ctx.bumps.delegate]];
+        open_offer(
+            &mut accounts.user_profile,
+            &mut accounts.offer,
+            &mut accounts.vault,
+            &accounts.maker_token_account,
+            &accounts.mint_offered,
+            &accounts.mint_wanted,
+            &accounts.maker.to_account_info(),
+            &accounts.delegate.to_account_info(),
+            &[delegate_seeds],
+            &accounts.token_program,
+            &accounts.system_program,
+            Some(&accounts.rent),
+            &OfferGates {
+                config: &accounts.config,
+                mint_tvl: &accounts.mint_tvl,
+                mint_tvl_bump: ctx.bumps.mint_tvl,
+                payer: &accounts.relayer.to_account_info(),
+                mint_status_offered: &accounts.mint_status_offered,
+                mint_status_wanted: &accounts.mint_status_wanted,
+                maker_status: accounts.maker_status.as_ref(),
+            },
+            terms.amount_offered,
+            terms.amount_wanted,
+            &terms.options,
+            None,
+            ctx.bumps.offer,
+            ctx.bumps.vault,
+            false,
+        )?;
+        check_mints_allowed(
+            &accounts.config,
+            accounts.allowed_mint_offered.as_ref(),
+            accounts.allowed_mint_wanted.as_ref(),
+        )?;
+        collect_listing_fee(
+            &accounts.config,
+            &accounts.relayer.to_account_info(),
+            accounts.treasury.as_ref(),
+            &accounts.system_program,
+        )?;
+
+        msg!(
+            "Offer {} relayed by {}",
+            terms.offer_id,
+            accounts.relayer.key()
+        );
+
+        Ok(())
+    }
+
+    /// Grow an offer written under an older, smaller `Offer` layout to the
+    /// current size and stamp it with `OFFER_VERSION`. Fields added since
+    /// then read as zero. The maker pays the extra rent. Offers already at
+    /// `OFFER_VERSION` are refused.
+    pub fn migrate_offer(ctx: Context<MigrateOffer>) -> Result<()> {
+        let offer_info = ctx.accounts.offer.to_account_info();
+        let maker = &ctx.accounts.maker;
+
+        // Every layout starts with the discriminator, offer ID and maker
+        {
+            let data = offer_info.try_borrow_data()?;
+            require!(
+                data.len() >= 8 + 8 + 32 && data.starts_with(Offer::DISCRIMINATOR),
+                ErrorCode::InvalidOfferId
+            );
+            require!(data[16..48] == maker.key().to_bytes(), ErrorCode::Unauthorized);
+        }
+
+        let new_len = 8 + Offer::SIZE;
+        if offer_info.data_len() < new_len {
+            let shortfall = Rent::get()?
+                .minimum_balance(new_len)
+                .saturating_sub(offer_info.lamports());
+            if shortfall > 0 {
+                let cpi_accounts = system_program::Transfer {
+                    from: maker.to_account_info(),
+                    to: offer_info.clone(),
+                };
+                let cpi_program = ctx.accounts.system_program.to_account_info();
+                system_program::transfer(CpiContext::new(cpi_program, cpi_accounts), shortfall)?;
+            }
+            offer_info.realloc(new_len, true)?;
+        }
+
+        let mut data = offer_info.try_borrow_mut_data()?;
+        let mut offer = Offer::try_deserialize(&mut &data[..])?;
+        let expected_key = Pubkey::create_program_address(
+            &[b"offer", maker.key().as_ref(), &offer.id_seed(), &[offer.bump]],
+            ctx.program_id,
+        )
+        .map_err(|_| error!(ErrorCode::InvalidOfferId))?;
+        require_keys_eq!(expected_key, offer_info.key(), ErrorCode::InvalidOfferId);
+
+        let old_version = offer.version;
+        require!(old_version < OFFER_VERSION, ErrorCode::OfferUpToDate);
+        offer.version = OFFER_VERSION;
+        // Offers from before ownership transfers are still owned by their maker
+        if offer.current_owner == Pubkey::default() {
+            offer.current_owner = offer.maker;
+        }
+        offer.try_serialize(&mut &mut data[..])?;
+
+        msg!(
+            "Offer {} migrated from version {} to {}",
+            offer.offer_id,
+            old_version,
+            OFFER_VERSION
+        );
+
+        Ok(())
+    }
+
+    /// Emit the counter-based offer addresses of a user for IDs in
+    /// `[start_id, end_id)`, clamped to the IDs issued so far and to
+    /// `MAX_OFFER_QUERY` entries, so clients can check their seed derivation
+    pub fn get_user_offers(ctx: Context<GetUserOffers>, start_id: u64, end_id: u64) -> Result<()> {
+        let user_profile = &ctx.accounts.user_profile;
+        let end_id = end_id
+            .min(user_profile.offer_count)
+            .min(start_id.saturating_add(MAX_OFFER_QUERY));
+
+        let offers = (start_id..end_id)
+            .map(|offer_id| {
+                Pubkey::find_program_address(
+                    &[
+                        b"offer",
+                        user_profile.authority.as_ref(),
+                        &offer_id.to_le_bytes(),
+                    ],
+                    ctx.program_id,
+                )
+                .0
+            })
+            .collect();
+
+        emit!(UserOffersQueried {
+            user: user_profile.authority,
+            start_id,
+            offers,
+        });
+
+        Ok(())
+    }
+
+    /// How many more offers a user may open, under the config's
+    /// `max_offers_per_user` and the limits of their profile's counters,
+    /// returned as a Borsh-encoded `OfferCapacity` in return data
+    pub fn get_offer_capacity(ctx: Context<GetOfferCapacity>) -> Result<()> {
+        let user_profile = &ctx.accounts.user_profile;
+        let max_open_offers =
+            load_config(&ctx.accounts.config)?.map_or(0, |config| config.max_offers_per_user);
+
+        let capacity = OfferCapacity {
+            open_offers: user_profile.open_offers,
+            remaining_open_offers: user_profile.remaining_offer_slots(max_open_offers),
+            remaining_offer_ids: u64::MAX - user_profile.offer_count,
+        };
+        set_return_data(&capacity.try_to_vec()?);
+
+        Ok(())
+    }
+
+    /// Escrow a basket of tokens, one vault per offered mint, in exchange
+    /// for a single wanted token. `remaining_accounts` holds, per entry of
+    /// `amounts`: the offered mint, the maker's token account of it, the
+    /// vault PDA `[b"vault", bundle, mint]` to create and the mint's
+    /// `MintTvl` PDA, which counts the item like any offer's escrow; then
+    /// each item mint's `MintStatus` PDA, in the same order, followed by
+    /// their `AllowedMint` PDAs while the mint allowlist is on. The bundle
+    /// takes one open offer slot and pays one listing fee; each item must
+    /// clear the config's dust minimum.
+    pub fn create_sell_bundle<'info>(
+        ctx: Context<'_, '_, 'info, 'info, CreateSellBundle<'info>>,
+        amounts: Vec<u64>,
+        amount_wanted: u64,
+        expiry_ts: Option<i64>,
+    ) -> Result<()> {
+        require!(!amounts.is_empty(), ErrorCode::InvalidAmount);
+        require!(amounts.len() <= MAX_BUNDLE_ITEMS, ErrorCode::BundleTooLarge);
+        check_compute_budget(amounts.len(), BUNDLE_ITEM_COMPUTE_UNITS)?;
+        let remaining_len = ctx.remaining_accounts.len();
+        require!(
+            remaining_len == amounts.len() * 5 || remaining_len == amounts.len() * 6,
+            ErrorCode::InvalidBundle
+        );
+        require!(amount_wanted > 0, ErrorCode::InvalidAmount);
+
+        let clock = Clock::get()?;
+        if let Some(expiry_ts) = expiry_ts {
+            validate_expiry(expiry_ts, clock.unix_timestamp)?;
+        }
+        let (groups, item_gates) = ctx.remaining_accounts.split_at(amounts.len() * 4);
+        let (mint_statuses, allowed_mints) = item_gates.split_at(amounts.len());
+        check_mint_unpaused(&ctx.accounts.mint_status_wanted)?;
+        check_bundle_unpaused(
+            groups.chunks(4).map(|group| group[0].key),
+            mint_statuses,
+            ctx.program_id,
+        )?;
+        check_mint_allowed(&ctx.accounts.config, ctx.accounts.allowed_mint_wanted.as_ref())?;
+        check_listed_mints_allowed(
+            &ctx.accounts.config,
+            groups.chunks(4).map(|group| group[0].key),
+            allowed_mints,
+            ctx.program_id,
+        )?;
+
+        let accounts = ctx.accounts;
+        let maker = &accounts.maker;
+        // Every item must clear the dust minimum on its own
+        let (offer_id, _) = claim_listing(
+            &mut accounts.user_profile,
+            maker.key(),
+            &accounts.config,
+            accounts.maker_status.as_ref(),
+            amounts.iter().copied().min().unwrap_or_default(),
+            amount_wanted,
+            clock.unix_timestamp,
+        )?;
+        collect_listing_fee(
+            &accounts.config,
+            &maker.to_account_info(),
+            accounts.treasury.as_ref(),
+            &accounts.system_program,
+        )?;
+
+        let bundle_key = accounts.bundle.key();
+        let rent = Rent::get()?;
+        let mut items = Vec::with_capacity(amounts.len());
+        for (group, &amount) in groups.chunks(4).zip(amounts.iter()) {
+            require!(amount > 0, ErrorCode::InvalidAmount);
+            let mint = Account::<Mint>::try_from(&group[0])?;
+            let maker_token_account = Account::<TokenAccount>::try_from(&group[1])?;
+            let vault = &group[2];
+            require_keys_eq!(maker_token_account.mint, mint.key(), ErrorCode::InvalidMint);
+            require_keys_eq!(maker_token_account.owner, maker.key(), ErrorCode::Unauthorized);
+
+            let (vault_key, vault_bump) = Pubkey::find_program_address(
+                &[b"vault", bundle_key.as_ref(), mint.key().as_ref()],
+                ctx.program_id,
+            );
+            require_keys_eq!(vault.key(), vault_key, ErrorCode::InvalidBundle);
+
+            // Create the vault as a token account that is its own authority
+            let mint_key = mint.key();
+            let seeds = &[
+                b"vault",
+                bundle_key.as_ref(),
+                mint_key.as_ref(),
+                &[vault_bump],
+            ];
+            let signer = &[&seeds[..]];
+            let cpi_accounts = system_program::CreateAccount {
+                from: maker.to_account_info(),
+                to: vault.clone(),
+            };
+            let cpi_program = accounts.system_program.to_account_info();
+            system_program::create_account(
+                CpiContext::new_with_signer(cpi_program, cpi_accounts, signer),
+                rent.minimum_balance(TokenAccount::LEN),
+                TokenAccount::LEN as u64,
+                &accounts.token_program.key(),
+            )?;
+            let cpi_accounts = InitializeAccount3 {
+                account: vault.clone(),
+                mint: mint.to_account_info(),
+                authority: vault.clone(),
+            };
+            let cpi_program = accounts.token_program.to_account_info();
+            token::initialize_account3(CpiContext::new(cpi_program, cpi_accounts))?;
+
+            let cpi_accounts = Transfer {
+                from: maker_token_account.to_account_info(),
+                to: vault.clone(),
+                authority: maker.to_account_info(),
+            };
+            let cpi_program = accounts.token_program.to_account_info();
+            token::transfer(CpiContext::new(cpi_program, cpi_accounts), amount)?;
+
+            // Each vault is freshly created, so it must hold exactly its item
+            require!(
+                Account::<TokenAccount>::try_from(vault)?.amount == amount,
+                ErrorCode::VaultBalanceMismatch
+            );
+
+            let mint_tvl = &group[3];
+            let (mint_tvl_key, mint_tvl_bump) =
+                Pubkey::find_program_address(&[b"mint_tvl", mint_key.as_ref()], ctx.program_id);
+            require_keys_eq!(mint_tvl.key(), mint_tvl_key, ErrorCode::InvalidBundle);
+            init_mint_tvl(
+                mint_tvl,
+                mint_tvl_bump,
+                mint_key,
+                &maker.to_account_info(),
+                &accounts.system_program,
+            )?;
+            lock_mint_tvl(mint_tvl, &accounts.config, amount)?;
+
+            items.push(BundleItem {
+                mint: mint_key,
+                amount,
+                vault_bump,
+            });
+        }
+
+        let bundle = &mut accounts.bundle;
+        bundle.offer_id = offer_id;
+        bundle.maker = maker.key();
+        bundle.mint_wanted = accounts.mint_wanted.key();
+        bundle.amount_wanted = amount_wanted;
+        bundle.bump = ctx.bumps.bundle;
+        bundle.created_at = clock.unix_timestamp;
+        bundle.expiry_ts = expiry_ts;
+        bundle.items = items;
+
+        msg!(
+            "Sell bundle {} created: {} tokens for {} {}",
+            offer_id,
+            bundle.items.len(),
+            amount_wanted,
+            bundle.mint_wanted
+        );
+
+        Ok(())
+    }
+
+    /// Pay a sell bundle's wanted amount and receive every escrowed token.
+    /// `remaining_accounts` holds, per bundle item in order: its vault, the
+    /// taker's token account of that mint and the mint's `MintTvl`; then
+    /// each item mint's `MintStatus` PDA, in the same order.
+    pub fn accept_sell_bundle<'info>(
+        ctx: Context<'_, '_, 'info, 'info, AcceptSellBundle<'info>>,
+        _offer_id: u64,
+    ) -> Result<()> {
+        let bundle = &ctx.accounts.bundle;
+        let clock = Clock::get()?;
+        require!(
+            bundle.expiry_ts.is_none_or(|expiry_ts| clock.unix_timestamp < expiry_ts),
+            ErrorCode::OfferExpired
+        );
+        require!(
+            ctx.remaining_accounts.len() == bundle.items.len() * 4,
+            ErrorCode::InvalidBundle
+        );
+        let (groups, mint_statuses) = ctx.remaining_accounts.split_at(bundle.items.len() * 3);
+        check_mint_unpaused(&ctx.accounts.mint_status_wanted)?;
+        check_no_emergency(&ctx.accounts.config)?;
+        check_bundle_unpaused(
+            bundle.items.iter().map(|item| &item.mint),
+            mint_statuses,
+            ctx.program_id,
+        )?;
+
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.taker_token_account_offered.to_account_info(),
+            to: ctx.accounts.maker_token_account_wanted.to_account_info(),
+            authority: ctx.accounts.taker.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        token::transfer(CpiContext::new(cpi_program, cpi_accounts), bundle.amount_wanted)?;
+
+        release_bundle(
+            bundle,
+            groups,
+            &ctx.accounts.maker.to_account_info(),
+            &ctx.accounts.token_program,
+            ctx.program_id,
+            true,
+        )?;
+        ctx.accounts.user_profile.release_offer_slot()?;
+
+        msg!(
+            "Sell bundle {} accepted by {}",
+            bundle.offer_id,
+            ctx.accounts.taker.key()
+        );
+
+        Ok(())
+    }
+
+    /// Cancel a sell bundle and return every escrowed token to the maker.
+    /// `remaining_accounts` holds, per bundle item in order: its vault, the
+    /// maker's token account of that mint and the mint's `MintTvl`.
+    pub fn cancel_sell_bundle<'info>(
+        ctx: Context<'_, '_, 'info, 'info, CancelSellBundle<'info>>,
+        _offer_id: u64,
+    ) -> Result<()> {
+        let bundle = &ctx.accounts.bundle;
+
+        release_bundle(
+            bundle,
+            ctx.remaining_accounts,
+            &ctx.accounts.maker.to_account_info(),
+            &ctx.accounts.token_program,
+            ctx.program_id,
+            false,
+        )?;
+        ctx.accounts.user_profile.release_offer_slot()?;
+
+        msg!("Sell bundle {} cancelled", bundle.offer_id);
+
+        Ok(())
+    }
+
+    /// List tokens without escrowing them: the maker approves the program's
+    /// delegate PDA for `amount_offered` on their own token account, and
+    /// `accept_delegated_offer` pulls from it at fill time. No vault is
+    /// created or closed. Approvals of several delegated offers on the same
+    /// token account add up, as SPL Token keeps a single delegate per account.
+    ///
+    /// The maker keeps custody, so they can revoke the approval, switch it to
+    /// another delegate or spend the tokens; the offer then fails to fill with
+    /// `DelegateRevoked` until it is cancelled. The listed amount still counts
+    /// toward the offered mint's `MintTvl` and its cap, and the offer goes
+    /// through the same listing checks, cap and fee as an escrowed one.
+    pub fn create_delegated_offer(
+        ctx: Context<CreateDelegatedOffer>,
+        amount_offered: u64,
+        amount_wanted: u64,
+        expiry_ts: Option<i64>,
+    ) -> Result<()> {
+        require!(amount_offered > 0, ErrorCode::InvalidAmount);
+        require!(amount_wanted > 0, ErrorCode::InvalidAmount);
+        require!(
+            ctx.accounts.maker_token_account.amount >= amount_offered,
+            ErrorCode::InsufficientBalance
+        );
+
+        let clock = Clock::get()?;
+        if let Some(expiry_ts) = expiry_ts {
+            validate_expiry(expiry_ts, clock.unix_timestamp)?;
+        }
+        check_mints_unpaused(&ctx.accounts.mint_status_offered, &ctx.accounts.mint_status_wanted)?;
+        check_mints_allowed(
+            &ctx.accounts.config,
+            ctx.accounts.allowed_mint_offered.as_ref(),
+            ctx.accounts.allowed_mint_wanted.as_ref(),
+        )?;
+
+        let accounts = ctx.accounts;
+        let maker = &accounts.maker;
+        let (offer_id, _) = claim_listing(
+            &mut accounts.user_profile,
+            maker.key(),
+            &accounts.config,
+            accounts.maker_status.as_ref(),
+            amount_offered,
+            amount_wanted,
+            clock.unix_timestamp,
+        )?;
+        collect_listing_fee(
+            &accounts.config,
+            &maker.to_account_info(),
+            accounts.treasury.as_ref(),
+            &accounts.system_program,
+        )?;
+
+        init_mint_tvl(
+            &accounts.mint_tvl,
+            ctx.bumps.mint_tvl,
+            accounts.maker_token_account.mint,
+            &maker.to_account_info(),
+            &accounts.system_program,
+        )?;
+        lock_mint_tvl(&accounts.mint_tvl, &accounts.config, amount_offered)?;
+
+        let allowance = delegated_allowance(&accounts.maker_token_account, &accounts.delegate.key())
+            .checked_add(amount_offered)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let cpi_accounts = Approve {
+            to: accounts.maker_token_account.to_account_info(),
+            delegate: accounts.delegate.to_account_info(),
+            authority: maker.to_account_info(),
+        };
+        let cpi_program = accounts.token_program.to_account_info();
+        token::approve(CpiContext::new(cpi_program, cpi_accounts), allowance)?;
+
+        let delegated_offer = &mut accounts.delegated_offer;
+        delegated_offer.offer_id = offer_id;
+        delegated_offer.maker = maker.key();
+        delegated_offer.maker_token_account = accounts.maker_token_account.key();
+        delegated_offer.mint_offered = accounts.maker_token_account.mint;
+        delegated_offer.mint_wanted = accounts.mint_wanted.key();
+        delegated_offer.amount_offered = amount_offered;
+        delegated_offer.amount_wanted = amount_wanted;
+        delegated_offer.bump = ctx.bumps.delegated_offer;
+        delegated_offer.created_at = clock.unix_timestamp;
+        delegated_offer.expiry_ts = expiry_ts;
+
+        msg!(
+            "Delegated offer {} created: {} {} for {} {}",
+            offer_id,
+            amount_offered,
+            delegated_offer.mint_offered,
+            amount_wanted,
+            delegated_offer.mint_wanted
+        );
+
+        Ok(())
+    }
+
+    /// Fill a delegated offer whole, pulling the offered tokens straight from
+    /// the maker's token account through the delegate PDA
+    pub fn accept_delegated_offer(
+        ctx: Context<AcceptDelegatedOffer>,
+        _offer_id: u64,
+    ) -> Result<()> {
+        let delegated_offer = &ctx.accounts.delegated_offer;
+        let clock = Clock::get()?;
+        require!(
+            delegated_offer
+                .expiry_ts
+                .is_none_or(|expiry_ts| clock.unix_timestamp < expiry_ts),
+            ErrorCode::OfferExpired
+        );
+        check_mints_unpaused(&ctx.accounts.mint_status_offered, &ctx.accounts.mint_status_wanted)?;
+        check_no_emergency(&ctx.accounts.config)?;
+        // Catch a revoked approval or spent tokens before the taker pays
+        let maker_token_account = &ctx.accounts.maker_token_account_offered;
+        require!(
+            delegated_allowance(maker_token_account, &ctx.accounts.delegate.key())
+                >= delegated_offer.amount_offered
+                && maker_token_account.amount >= delegated_offer.amount_offered,
+            ErrorCode::DelegateRevoked
+        );
+
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.taker_token_account_offered.to_account_info(),
+            to: ctx.accounts.maker_token_account_wanted.to_account_info(),
+            authority: ctx.accounts.taker.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        token::transfer(
+            CpiContext::new(cpi_program, cpi_accounts),
+            delegated_offer.amount_wanted,
+        )?;
+
+        let seeds = &[b"delegate".as_ref(), &[ctx.bumps.delegate]];
+        let signer = &[&seeds[..]];
+        let cpi_accounts = Transfer {
+            from: maker_token_account.to_account_info(),
+            to: ctx.accounts.taker_token_account_wanted.to_account_info(),
+            authority: ctx.accounts.delegate.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        token::transfer(
+            CpiContext::new_with_signer(cpi_program, cpi_accounts, signer),
+            delegated_offer.amount_offered,
+        )?;
+        release_mint_tvl(&ctx.accounts.mint_tvl, delegated_offer.amount_offered)?;
+        ctx.accounts.user_profile.release_offer_slot()?;
+
+        msg!(
+            "Delegated offer {} accepted by {}",
+            delegated_offer.offer_id,
+            ctx.accounts.taker.key()
+        );
+
+        Ok(())
+    }
+
+    /// Cancel a delegated offer, withdrawing its share of the delegate
+    /// approval (revoking it once nothing else is listed against it)
+    pub fn cancel_delegated_offer(
+        ctx: Context<CancelDelegatedOffer>,
+        _offer_id: u64,
+    ) -> Result<()> {
+        let accounts = ctx.accounts;
+        let delegated_offer = &accounts.delegated_offer;
+        let allowance =
+            delegated_allowance(&accounts.maker_token_account, &accounts.delegate.key());
+
+        // An approval already revoked or moved elsewhere is left as is
+        if allowance > 0 {
+            let remaining = allowance.saturating_sub(delegated_offer.amount_offered);
+            let cpi_program = accounts.token_program.to_account_info();
+            if remaining == 0 {
+                let cpi_accounts = Revoke {
+                    source: accounts.maker_token_account.to_account_info(),
+                    authority: accounts.maker.to_account_info(),
+                };
+                token::revoke(CpiContext::new(cpi_program, cpi_accounts))?;
+            } else {
+                let cpi_accounts = Approve {
+                    to: accounts.maker_token_account.to_account_info(),
+                    delegate: accounts.delegate.to_account_info(),
+                    authority: accounts.maker.to_account_info(),
+                };
+                token::approve(CpiContext::new(cpi_program, cpi_accounts), remaining)?;
+            }
+        }
+
+        release_mint_tvl(&accounts.mint_tvl, delegated_offer.amount_offered)?;
+        accounts.user_profile.release_offer_slot()?;
+
+        msg!("Delegated offer {} cancelled", delegated_offer.offer_id);
+
+        Ok(())
+    }
+
+    /// List plain SOL for an SPL token. The lamports are escrowed on the SOL
+    /// offer PDA itself, on top of the rent that keeps it exempt, so no
+    /// wrapped SOL or vault is involved. They count toward the native mint's
+    /// `MintTvl` and its cap, alongside wrapped SOL escrow. The offer goes
+    /// through the same listing checks, cap and fee as any other.
+    pub fn create_sol_offer(
+        ctx: Context<CreateSolOffer>,
+        lamports_offered: u64,
+        amount_wanted: u64,
+        expiry_ts: Option<i64>,
+    ) -> Result<()> {
+        require!(lamports_offered > 0, ErrorCode::InvalidAmount);
+        require!(amount_wanted > 0, ErrorCode::InvalidAmount);
+
+        let clock = Clock::get()?;
+        if let Some(expiry_ts) = expiry_ts {
+            validate_expiry(expiry_ts, clock.unix_timestamp)?;
+        }
+        check_mints_unpaused(&ctx.accounts.mint_status_offered, &ctx.accounts.mint_status_wanted)?;
+        check_mints_allowed(
+            &ctx.accounts.config,
+            ctx.accounts.allowed_mint_offered.as_ref(),
+            ctx.accounts.allowed_mint_wanted.as_ref(),
+        )?;
+
+        let accounts = ctx.accounts;
+        let maker = &accounts.maker;
+        let (offer_id, _) = claim_listing(
+            &mut accounts.user_profile,
+            maker.key(),
+            &accounts.config,
+            accounts.maker_status.as_ref(),
+            lamports_offered,
+            amount_wanted,
+            clock.unix_timestamp,
+        )?;
+        collect_listing_fee(
+            &accounts.config,
+            &maker.to_account_info(),
+            accounts.treasury.as_ref(),
+            &accounts.system_program,
+        )?;
+
+        let cpi_accounts = system_program::Transfer {
+            from: maker.to_account_info(),
+            to: accounts.sol_offer.to_account_info(),
+        };
+        let cpi_program = accounts.system_program.to_account_info();
+        system_program::transfer(CpiContext::new(cpi_program, cpi_accounts), lamports_offered)?;
+
+        init_mint_tvl(
+            &accounts.mint_tvl,
+            ctx.bumps.mint_tvl,
+            token::spl_token::native_mint::ID,
+            &maker.to_account_info(),
+            &accounts.system_program,
+        )?;
+        lock_mint_tvl(&accounts.mint_tvl, &accounts.config, lamports_offered)?;
+
+        let sol_offer = &mut accounts.sol_offer;
+        sol_offer.offer_id = offer_id;
+        sol_offer.maker = maker.key();
+        sol_offer.mint_wanted = accounts.mint_wanted.key();
+        sol_offer.lamports_offered = lamports_offered;
+        sol_offer.amount_wanted = amount_wanted;
+        sol_offer.bump = ctx.bumps.sol_offer;
+        sol_offer.created_at = clock.unix_timestamp;
+        sol_offer.expiry_ts = expiry_ts;
+
+        msg!(
+            "SOL offer {} created: {} lamports for {} {}",
+            offer_id,
+            lamports_offered,
+            amount_wanted,
+            sol_offer.mint_wanted
+        );
+
+        Ok(())
+    }
+
+    /// Fill a SOL offer whole: the taker pays the wanted tokens and receives
+    /// the escrowed lamports, and the rent goes back to the maker
+    pub fn accept_sol_offer(ctx: Context<AcceptSolOffer>, _offer_id: u64) -> Result<()> {
+        let sol_offer = &ctx.accounts.sol_offer;
+        let clock = Clock::get()?;
+        require!(
+            sol_offer.expiry_ts.is_none_or(|expiry_ts| clock.unix_timestamp < expiry_ts),
+            ErrorCode::OfferExpired
+        );
+        check_mints_unpaused(&ctx.accounts.mint_status_offered, &ctx.accounts.mint_status_wanted)?;
+        check_no_emergency(&ctx.accounts.config)?;
+
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.taker_token_account_offered.to_account_info(),
+            to: ctx.accounts.maker_token_account_wanted.to_account_info(),
+            authority: ctx.accounts.taker.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        token::transfer(CpiContext::new(cpi_program, cpi_accounts), sol_offer.amount_wanted)?;
+
+        // The PDA is owned by the program, so its lamports move directly;
+        // `close` then returns the rent that is left to the maker
+        let lamports = sol_offer.lamports_offered;
+        **sol_offer.to_account_info().try_borrow_mut_lamports()? -= lamports;
+        **ctx.accounts.taker.to_account_info().try_borrow_mut_lamports()? += lamports;
+        release_mint_tvl(&ctx.accounts.mint_tvl, lamports)?;
+        ctx.accounts.user_profile.release_offer_slot()?;
+
+        msg!(
+            "SOL offer {} accepted by {}",
+            sol_offer.offer_id,
+            ctx.accounts.taker.key()
+        );
+
+        Ok(())
+    }
+
+    /// Cancel a SOL offer, returning the escrowed lamports and rent to the
+    /// maker as the PDA closes
+    pub fn cancel_sol_offer(ctx: Context<CancelSolOffer>, _offer_id: u64) -> Result<()> {
+        release_mint_tvl(&ctx.accounts.mint_tvl, ctx.accounts.sol_offer.lamports_offered)?;
+        ctx.accounts.user_profile.release_offer_slot()?;
+
+        msg!("SOL offer {} cancelled", ctx.accounts.sol_offer.offer_id);
+
+        Ok(())
+    }
+
+    /// Emit the offer's recorded escrow next to the live vault balance so
+    /// monitoring can spot drift between them
+    pub fn inspect_offer(ctx: Context<InspectOffer>, _offer_id: u64) -> Result<()> {
+        let offer = &ctx.accounts.offer;
+        let vault_amount = ctx.accounts.vault.amount;
+
+        emit!(OfferInspected {
+            offer: offer.key(),
+            offer_id: offer.offer_id,
+            amount_offered: offer.amount_offered,
+            vault_amount,
+            balanced: vault_amount == offer.amount_escrowed(),
+        });
+
+        Ok(())
+    }
+
+    /// Return the offer's full state, Borsh-encoded as stored after the
+    /// discriminator, so integrating programs can record it through CPI
+    /// without reading the account themselves
+    pub fn snapshot_offer(ctx: Context<SnapshotOffer>, _offer_id: u64) -> Result<()> {
+        let snapshot = ctx.accounts.offer.try_to_vec()?;
+        // Long blocklists and counter-offer lists can outgrow the return data
+        require!(snapshot.len() <= MAX_RETURN_DATA, ErrorCode::SnapshotTooLarge);
+        set_return_data(&snapshot);
+        Ok(())
+    }
+
+    /// Record the signer's interest in an open offer as an `OfferBookmarked`
+    /// event for frontends to index; changes no state
+    pub fn bookmark_offer(ctx: Context<BookmarkOffer>, _offer_id: u64) -> Result<()> {
+        let offer = &ctx.accounts.offer;
+        let clock = Clock::get()?;
+        require!(!offer.is_expired(&clock), ErrorCode::OfferExpired);
+
+        emit!(OfferBookmarked {
+            offer: offer.key(),
+            user: ctx.accounts.user.key(),
+        });
+
+        Ok(())
+    }
+
+    /// Check an offer against its invariants and emit a `ValidationResult`
+    /// whose `failures` bitflags name every one that doesn't hold. Unlike the
+    /// other instructions it takes the accounts unchecked, so corrupted state
+    /// is reported rather than rejected. Mutates nothing.
+    pub fn validate_offer(ctx: Context<ValidateOffer>) -> Result<()> {
+        let offer = &ctx.accounts.offer;
+        let vault = &ctx.accounts.vault;
+        let (_, bump) = offer.canonical_address();
+        let (vault_key, vault_bump) = offer.canonical_vault(offer.key());
+
+        let checks = [
+            (ValidationResult::VAULT_ADDRESS, vault.key() == vault_key),
+            (ValidationResult::VAULT_MINT, vault.mint == offer.mint_offered),
+            (ValidationResult::VAULT_AUTHORITY, vault.owner == vault.key()),
+            (ValidationResult::VAULT_BALANCE, vault.amount >= offer.amount_escrowed()),
+            (ValidationResult::OFFER_BUMP, bump == offer.bump),
+            (ValidationResult::VAULT_BUMP, vault_bump == offer.vault_bump),
+            (
+                ValidationResult::AMOUNTS,
+                offer.amount_offered > 0 && offer.amount_wanted > 0,
+            ),
+            (ValidationResult::MAKER, ctx.accounts.maker.key() == offer.maker),
+        ];
+        let failures = checks
+            .iter()
+            .filter(|(_, holds)| !holds)
+            .fold(0, |failures, (flag, _)| failures | flag);
+
+        emit!(ValidationResult {
+            offer: offer.key(),
+            offer_id: offer.offer_id,
+            failures,
+        });
+
+        msg!("Offer {} validated, failures {:#010b}", offer.offer_id, failures);
+
+        Ok(())
+    }
+
+    /// Return the core fields of every offer in `remaining_accounts` as a
+    /// Borsh-encoded `Vec<OfferSummary>` in return data, in account order, so
+    /// clients can read up to `MAX_INSPECT_BATCH` offers in one call
+    pub fn batch_inspect<'info>(
+        ctx: Context<'_, '_, 'info, 'info, BatchInspect>,
+    ) -> Result<()> {
+        require!(!ctx.remaining_accounts.is_empty(), ErrorCode::InvalidAmount);
+        require!(
+            ctx.remaining_accounts.len() <= MAX_INSPECT_BATCH,
+            ErrorCode::BatchTooLarge
+        );
+
+        let summaries = ctx
+            .remaining_accounts
+            .iter()
+            .map(|info| {
+                let offer = Account::<Offer>::try_from(info)?;
+                Ok(OfferSummary {
+                    offer_id: offer.offer_id,
+                    amount_offered: offer.amount_offered,
+                    amount_wanted: offer.amount_wanted,
+                    maker: offer.maker,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        set_return_data(&summaries.try_to_vec()?);
+        Ok(())
+    }
+
+    /// Work out what taking the whole offer would move right now: the taker's
+    /// payment in the primary wanted mint and its split into protocol fee,
+    /// referral, royalty and the maker's proceeds. Nothing is transferred;
+    /// the breakdown is emitted as `QuoteComputed` and returned as a
+    /// Borsh-encoded `AcceptQuote` in return data.
+    pub fn quote_accept(ctx: Context<QuoteAccept>, _offer_id: u64) -> Result<()> {
+        let offer = &ctx.accounts.offer;
+
+        // Oracle, index and AMM offers are quoted at the price an accept
+        // would reprice to; index feeds come as remaining accounts
+        let amount_in = match (offer.price_feed, offer.amm_pool) {
+            (Some(price_feed), _) => {
+                let price_feed_info = ctx
+                    .accounts
+                    .price_feed
+                    .as_ref()
+                    .ok_or(ErrorCode::InvalidOracle)?;
+                require_keys_eq!(price_feed_info.key(), price_feed, ErrorCode::InvalidOracle);
+
+                let clock = Clock::get()?;
+                let max_staleness = oracle_staleness(&ctx.accounts.config)?;
+                let price =
+                    load_pyth_price(price_feed_info, clock.unix_timestamp, max_staleness)?;
+                oracle_amount_wanted(
+                    offer.amount_offered,
+                    &price,
+                    offer.multiplier_bps,
+                    offer.decimals_offered,
+                    offer.decimals_wanted,
+                )?
+            }
+            (None, Some(amm_pool)) => {
+                let pool_info = ctx
+                    .accounts
+                    .amm_pool
+                    .as_ref()
+                    .ok_or(ErrorCode::InvalidPool)?;
+                require_keys_eq!(pool_info.key(), amm_pool, ErrorCode::InvalidPool);
+
+                let reserves = load_pool_reserves(
+                    pool_info,
+                    &ctx.accounts.config,
+                    offer.mint_offered,
+                    offer.mint_wanted,
+                    Clock::get()?.slot,
+                )?;
+                pool_amount_wanted(offer.amount_offered, &reserves, offer.multiplier_bps)?
+            }
+            (None, None) if !offer.index_components.is_empty() => {
+                let clock = Clock::get()?;
+                let max_staleness = oracle_staleness(&ctx.accounts.config)?;
+                index_amount_wanted(
+                    &offer.index_components,
+                    ctx.remaining_accounts,
+                    offer.amount_offered,
+                    offer.multiplier_bps,
+                    offer.decimals_offered,
+                    offer.decimals_wanted,
+                    clock.unix_timestamp,
+                    max_staleness,
+                )?
+            }
+            (None, None) => offer.amount_wanted,
+        };
+
+        let (fee, taker_fee) = match load_config(&ctx.accounts.config)? {
+            Some(config) => (
+                protocol_fee(
+                    &config,
+                    offer.current_owner,
+                    amount_in,
+                    ctx.accounts.maker_rebate_token_account.as_ref(),
+                )?,
+                taker_fee(&config, amount_in)?,
+            ),
+            None => (0, 0),
+        };
+        let (referral, royalty) = offer_cuts(offer, amount_in)?;
+        let quote = AcceptQuote {
+            amount_in,
+            amount_out: offer.amount_offered,
+            fee,
+            taker_fee,
+            referral,
+            royalty,
+            maker_proceeds: maker_proceeds(amount_in, fee, referral, royalty)?,
+            taker_rebate: offer.taker_rebate_escrowed,
+        };
+
+        emit!(QuoteComputed {
+            offer: offer.key(),
+            offer_id: offer.offer_id,
+            amount_in: quote.amount_in,
+            amount_out: quote.amount_out,
+            fee: quote.fee,
+            taker_fee: quote.taker_fee,
+            referral: quote.referral,
+            royalty: quote.royalty,
+            maker_proceeds: quote.maker_proceeds,
+        });
+
+        set_return_data(&quote.try_to_vec()?);
+        Ok(())
+    }
+
+    /// Return the deployed program's crate version, e.g. `0.1.0`, as UTF-8
+    /// return data, so operators can tell which build is live. Builds with
+    /// `verbose-logs` carry it as build metadata, e.g. `0.1.0+verbose-logs`.
+    pub fn version(_ctx: Context<Version>) -> Result<()> {
+        let version = if cfg!(feature = "verbose-logs") {
+            concat!(env!("CARGO_PKG_VERSION"), "+verbose-logs")
+        } else {
+            env!("CARGO_PKG_VERSION")
+        };
+        set_return_data(version.as_bytes());
+        Ok(())
+    }
+}
+
+// ============================================================================
+// Helpers
+// ============================================================================
+
+/// Shared body of the accept instructions: the taker pays `amount_in` of the
+/// wanted mint (less any protocol fee) to the maker and receives `amount_out`
+/// from the vault, or into their vesting vault when `vesting`. The vault and
+/// offer are closed once fully consumed. The settled amounts are set as
+/// return data as a Borsh-encoded `FillResult`, so a router can chain the
+/// proceeds into its next swap.
+fn fill_offer(
+    accounts: &mut AcceptOffer,
+    amount_in: u64,
+    amount_out: u64,
+    authorized: bool,
+    attested: bool,
+    vesting: bool,
+) -> Result<()> {
+    settle_fill(accounts, amount_in, amount_out, authorized, attested, vesting, true)?;
+    Ok(())
+}
+
+/// `fill_offer`, with the `OfferAccepted` event left out unless `emit_event`,
+/// for batches that summarize their fills in one event instead. Returns what
+/// the fill moved, as also set in return data.
+fn settle_fill(
+    accounts: &mut AcceptOffer,
+    amount_in: u64,
+    amount_out: u64,
+    authorized: bool,
+    attested: bool,
+    vesting: bool,
+    emit_event: bool,
+) -> Result<FillResult> {
+    let clock = Clock::get()?;
+    // A lapsed turn passes to the next taker in line before anyone may fill
+    accounts.offer.serve_fill_queue(clock.unix_timestamp)?;
+    let offer = &accounts.offer;
+
+    validate_bumps(offer)?;
+    validate_vault(&accounts.vault, offer)?;
+    // One account on both sides would pay the taker with their own tokens
+    require_keys_neq!(
+        accounts.taker_token_account_offered.key(),
+        accounts.taker_token_account_wanted.key(),
+        ErrorCode::DuplicateAccount
+    );
+    require!(!offer.is_expired(&clock), ErrorCode::OfferExpired);
+    require!(offer.is_active(clock.unix_timestamp), ErrorCode::OfferNotYetActive);
+    require!(offer.in_fill_window(clock.slot), ErrorCode::OutsideFillWindow);
+    require!(!offer.is_paused, ErrorCode::OfferPausedByMaker);
+    check_mints_unpaused(&accounts.mint_status_offered, &accounts.mint_status_wanted)?;
+    require!(
+        offer.auto_relist_amount_wanted.is_none(),
+        ErrorCode::AutoRelistOffer
+    );
+    // Taking whatever remains is always allowed, even past the fill cap
+    require!(
+        amount_out == offer.amount_offered
+            || offer.max_fills == 0
+            || offer.fill_count < offer.max_fills,
+        ErrorCode::MaxFillsReached
+    );
+    require!(
+        amount_out == offer.amount_offered || offer.fill_count < u16::MAX,
+        ErrorCode::FillCountExhausted
+    );
+    // Once only dust is left it must be taken whole
+    require!(
+        !offer.is_dust() || amount_out == offer.amount_offered,
+        ErrorCode::DustRemainder
+    );
+    require!(
+        offer.lot_size == 0
+            || amount_out == offer.amount_offered
+            || amount_in.is_multiple_of(offer.lot_size),
+        ErrorCode::InvalidLotSize
+    );
+    require!(
+        !offer.is_reserved(clock.unix_timestamp) || offer.reserved_by == accounts.taker.key(),
+        ErrorCode::OfferReserved
+    );
+    require!(
+        !offer.is_committed(clock.unix_timestamp)
+            || offer.committed_taker == Some(accounts.taker.key()),
+        ErrorCode::OfferReserved
+    );
+    // High-value offers are only filled by a taker who committed first
+    let high_value_threshold =
+        load_config(&accounts.config)?.map_or(0, |config| config.high_value_threshold);
+    if high_value_threshold > 0 && offer.amount_offered > high_value_threshold {
+        offer.check_commit(&accounts.taker.key(), clock.unix_timestamp)?;
+    }
+    require!(!offer.is_blocked(&accounts.taker.key()), ErrorCode::TakerBlocked);
+    if let Some(gate_mint) = offer.gate_mint {
+        let holding = accounts
+            .taker_gate_token_account
+            .as_ref()
+            .ok_or(ErrorCode::GateNotSatisfied)?;
+        require!(
+            holding.mint == gate_mint
+                && holding.owner == accounts.taker.key()
+                && holding.amount >= 1,
+            ErrorCode::GateNotSatisfied
+        );
+    }
+    require!(
+        authorized || !offer.requires_authorization,
+        ErrorCode::AuthorizationRequired
+    );
+    require!(
+        attested || offer.attestation_max_age_slots == 0,
+        ErrorCode::AttestationRequired
+    );
+    // Vesting offers only pay out through `accept_with_vesting`
+    require!(
+        vesting == (offer.vesting_cliff_secs > 0),
+        ErrorCode::VestingMismatch
+    );
+    require!(
+        accounts.taker_stats.completed_swaps >= offer.min_taker_swaps,
+        ErrorCode::InsufficientReputation
+    );
+    // An accepted counter-offer may only be taken by its proposer
+    require!(
+        offer.locked_taker.is_none_or(|locked_taker| locked_taker == accounts.taker.key()),
+        ErrorCode::NoActiveCounter
+    );
+    // Settling before the reservation's deadline returns the taker's deposit
+    if let Some(deposit) = &accounts.reservation_deposit {
+        if clock.unix_timestamp < deposit.settlement_deadline {
+            deposit.close(accounts.taker.to_account_info())?;
+        }
+    }
+
+    // A frozen destination would make the payment fail with an opaque error
+    require!(
+        !accounts.maker_token_account_wanted.is_frozen(),
+        ErrorCode::MakerAccountUnavailable
+    );
+
+    // Withhold the maker-side fee, if any, from the maker's proceeds, and
+    // charge the taker-side fee on top of the price
+    // In fee-token mode a flat fee in `config.fee_mint` replaces both bps fees
+    let config = load_config(&accounts.config)?;
+    require!(
+        !config.as_ref().is_some_and(|config| config.emergency),
+        ErrorCode::EmergencyDeclared
+    );
+    if config.as_ref().is_some_and(|config| config.load_mint_accounts) {
+        for mint in [&accounts.mint_offered, &accounts.mint_wanted] {
+            require!(
+                [token::ID, spl_token_2022::ID].contains(mint.owner),
+                ErrorCode::InvalidMint
+            );
+            token_interface::Mint::try_deserialize(&mut &mint.try_borrow_data()?[..])?;
+        }
+    }
+    let gross_up = config.as_ref().is_some_and(|config| config.gross_up_transfer_fees);
+    let (fee, taker_fee, treasury, fee_token) = match config {
+        Some(config) if config.fee_mint != Pubkey::default() => (
+            0,
+            0,
+            config.treasury,
+            Some((config.fee_mint, config.fee_token_amount)),
+        ),
+        Some(config) => (
+            protocol_fee(
+                &config,
+                offer.current_owner,
+                amount_in,
+                accounts.maker_rebate_token_account.as_ref(),
+            )?,
+            taker_fee(&config, amount_in)?,
+            config.treasury,
+            None,
+        ),
+        None => (0, 0, Pubkey::default(), None),
+    };
+    let treasury_fee = fee.checked_add(taker_fee).ok_or(ErrorCode::MathOverflow)?;
+
+    // The maker's rebate pool pays out in step with the escrow it rides on
+    let rebate = if amount_out == offer.amount_offered {
+        offer.taker_rebate_escrowed
+    } else {
+        mul_div(offer.taker_rebate_escrowed, amount_out, offer.amount_offered, false)?
+    };
+    let amount_released = amount_out.checked_add(rebate).ok_or(ErrorCode::MathOverflow)?;
+    require!(
+        accounts.vault.amount >= amount_released,
+        ErrorCode::VaultBalanceMismatch
+    );
+    let mut fill_result = FillResult {
+        paid: amount_in.checked_add(taker_fee).ok_or(ErrorCode::MathOverflow)?,
+        received: amount_released,
+        fee: treasury_fee,
+    };
+
+    // A taker who can no longer cover the price and taker fee would fail
+    // opaquely too
+    require!(
+        accounts.taker_token_account_offered.amount
+            >= amount_in.checked_add(taker_fee).ok_or(ErrorCode::MathOverflow)?,
+        ErrorCode::InsufficientBalance
+    );
+
+    if treasury_fee > 0 {
+        let treasury_token_account = accounts
+            .treasury_token_account
+            .as_ref()
+            .ok_or(ErrorCode::MissingTreasuryAccount)?;
+        // Fees routed back into either side of the swap would be evaded
+        require!(
+            treasury_token_account.key() != accounts.maker_token_account_wanted.key()
+                && treasury_token_account.key() != accounts.taker_token_account_offered.key(),
+            ErrorCode::InvalidFeeRoute
+        );
+        require!(
+            treasury_token_account.mint == accounts.mint_wanted.key(),
+            ErrorCode::WrongWantedMint
+        );
+        require!(
+            treasury_token_account.owner == treasury,
+            ErrorCode::Unauthorized
+        );
+
+        let transfer_fee = pay_wanted(
+            accounts,
+            treasury_token_account.to_account_info(),
+            treasury_fee,
+            gross_up,
+        )?;
+        fill_result.paid =
+            fill_result.paid.checked_add(transfer_fee).ok_or(ErrorCode::MathOverflow)?;
+    }
+
+    let fee_token_amount = match fee_token {
+        Some((fee_mint, amount)) => {
+            collect_fee_token(accounts, fee_mint, amount, treasury)?;
+            amount
+        }
+        None => 0,
+    };
+
+    let (referral, royalty) = offer_cuts(offer, amount_in)?;
+
+    // Pay the referrer's cut, if any, also out of the maker's proceeds
+    match offer.referrer {
+        Some(referrer) if offer.referrer_bps > 0 => {
+            let referrer_token_account = accounts
+                .referrer_token_account
+                .as_ref()
+                .ok_or(ErrorCode::MissingReferrerAccount)?;
+            require_keys_eq!(
+                referrer_token_account.key(),
+                get_associated_token_address(&referrer, &accounts.mint_wanted.key()),
+                ErrorCode::MissingReferrerAccount
+            );
+
+            let transfer_fee = pay_wanted(
+                accounts,
+                referrer_token_account.to_account_info(),
+                referral,
+                gross_up,
+            )?;
+            fill_result.paid =
+                fill_result.paid.checked_add(transfer_fee).ok_or(ErrorCode::MathOverflow)?;
+        }
+        _ => {}
+    }
+
+    // Divert the creator's royalty, if any, before paying the maker
+    match offer.royalty_recipient {
+        Some(royalty_recipient) if offer.royalty_bps > 0 => {
+            let royalty_token_account = accounts
+                .royalty_token_account
+                .as_ref()
+                .ok_or(ErrorCode::MissingRoyaltyAccount)?;
+            require_keys_eq!(
+                royalty_token_account.key(),
+                get_associated_token_address(&royalty_recipient, &accounts.mint_wanted.key()),
+                ErrorCode::MissingRoyaltyAccount
+            );
+
+            let transfer_fee = pay_wanted(
+                accounts,
+                royalty_token_account.to_account_info(),
+                royalty,
+                gross_up,
+            )?;
+            fill_result.paid =
+                fill_result.paid.checked_add(transfer_fee).ok_or(ErrorCode::MathOverflow)?;
+        }
+        _ => {}
+    }
+
+    // Transfer wanted tokens from taker to maker
+    let maker_proceeds = maker_proceeds(amount_in, fee, referral, royalty)?;
+    let transfer_fee = pay_wanted(
+        accounts,
+        accounts.maker_token_account_wanted.to_account_info(),
+        maker_proceeds,
+        gross_up,
+    )?;
+    fill_result.paid = fill_result.paid.checked_add(transfer_fee).ok_or(ErrorCode::MathOverflow)?;
+
+    if cfg!(feature = "verbose-logs") {
+        msg!(
+            "Offer {} amounts: in {}, out {}, fee {}, referral {}, royalty {}, maker {}",
+            offer.offer_id,
+            amount_in,
+            amount_out,
+            fee,
+            referral,
+            royalty,
+            maker_proceeds
+        );
+    }
+
+    // Transfer offered tokens from vault to taker using PDA signer
+    let offer_key = offer.key();
+    let vault_key_seed = offer.vault_key_seed(offer_key);
+    let vault_id_seed = offer.vault_id_seed();
+    let mint_key = offer.mint_offered;
+    let seeds = &[
+        b"vault",
+        vault_key_seed.as_ref(),
+        vault_id_seed.as_slice(),
+        mint_key.as_ref(),
+        &[offer.vault_bump],
+    ];
+    let signer = &[&seeds[..]];
+
+    let destination = match (&accounts.taker_receive_account, vesting) {
+        (_, true) => accounts
+            .vesting_vault
+            .as_ref()
+            .ok_or(ErrorCode::MissingVestingAccount)?
+            .to_account_info(),
+        (Some(taker_receive_account), false) => taker_receive_account.to_account_info(),
+        (None, false) => accounts.taker_token_account_wanted.to_account_info(),
+    };
+    let cpi_accounts = Transfer {
+        from: accounts.vault.to_account_info(),
+        to: destination,
+        authority: accounts.vault.to_account_info(),
+    };
+    let cpi_program = accounts.token_program.to_account_info();
+    let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+    let vault_balance = accounts.vault.amount;
+    token::transfer(cpi_ctx, amount_released)?;
+    log_vault_transfer(&accounts.vault.to_account_info(), vault_balance, amount_released)?;
+    if rebate > 0 {
+        msg!("Taker rebate of {} paid", rebate);
+    }
+
+    let offer = &mut accounts.offer;
+    let amount_offered_before = offer.amount_offered;
+    offer.amount_offered = offer
+        .amount_offered
+        .checked_sub(amount_out)
+        .ok_or(ErrorCode::MathOverflow)?;
+    offer.taker_rebate_escrowed = offer
+        .taker_rebate_escrowed
+        .checked_sub(rebate)
+        .ok_or(ErrorCode::MathOverflow)?;
+    // The maker's floor shrinks with the escrow it covers
+    offer.min_acceptable_wanted = mul_div(
+        offer.min_acceptable_wanted,
+        offer.amount_offered,
+        amount_offered_before,
+        true,
+    )?;
+    // Alternative quotes are only ever taken whole
+    offer.amount_wanted = if accounts.mint_wanted.key() == offer.mint_wanted {
+        offer
+            .amount_wanted
+            .checked_sub(amount_in)
+            .ok_or(ErrorCode::MathOverflow)?
+    } else {
+        0
+    };
+    offer.admit_acceptor(accounts.taker.key())?;
+    if offer.tvl_tracked {
+        release_mint_tvl(&accounts.mint_tvl, amount_out)?;
+    }
+    offer.taker = Some(accounts.taker.key());
+    offer.accepted_at = clock.unix_timestamp;
+    offer.fill_count = offer.fill_count.saturating_add(1);
+
+    let taker_stats = &mut accounts.taker_stats;
+    taker_stats.taker = accounts.taker.key();
+    taker_stats.completed_swaps = taker_stats
+        .completed_swaps
+        .checked_add(1)
+        .ok_or(ErrorCode::CounterOverflow)?;
+
+    if emit_event {
+        emit!(OfferAccepted {
+            offer: offer.key(),
+            maker: offer.maker,
+            offer_id: offer.offer_id,
+            taker: accounts.taker.key(),
+            amount_in,
+            amount_out,
+            fee,
+            taker_fee,
+            fee_token_amount,
+            accepted_at: clock.unix_timestamp,
+            remaining_offered: offer.amount_offered,
+            remaining_wanted: offer.amount_wanted,
+        });
+    }
+
+    if offer.amount_offered > 0 {
+        // A commit covers a single fill
+        if offer.committed_taker == Some(accounts.taker.key()) {
+            offer.committed_taker = None;
+            offer.commit_expires_at = 0;
+        }
+
+        // Each fill ends the filling taker's turn in the queue
+        if offer.fill_queue_capacity > 0 && offer.reserved_by == accounts.taker.key() {
+            offer.reserved_by = Pubkey::default();
+            offer.reserved_until = 0;
+            offer.serve_fill_queue(clock.unix_timestamp)?;
+        }
+
+        set_return_data(&fill_result.try_to_vec()?);
+        msg!(
+            "Offer {} filled {} for {} by {}, {} remaining",
+            offer.offer_id,
+            amount_out,
+            amount_in,
+            accounts.taker.key(),
+            offer.amount_offered
+        );
+        return Ok(fill_result);
+    }
+
+    accounts.user_profile.release_offer_slot()?;
+
+    // The rent refunds must reach the offer's owner, whatever the accounts
+    // struct checked
+    require_keys_eq!(
+        accounts.maker.key(),
+        offer.current_owner,
+        ErrorCode::Unauthorized
+    );
+
+    // Whoever completes the offer collects the maker's tip; the rest of the
+    // offer's lamports go back to the owner on close
+    let tip = offer.taker_tip_lamports;
+    if tip > 0 {
+        **offer.to_account_info().try_borrow_mut_lamports()? -= tip;
+        **accounts.taker.to_account_info().try_borrow_mut_lamports()? += tip;
+        msg!("Taker tip of {} lamports paid", tip);
+    }
+
+    // Close vault token account (refund rent to maker), unless other offers
+    // share it
+    if !offer.omnibus_vault {
+        let cpi_accounts = CloseAccount {
+            account: accounts.vault.to_account_info(),
+            destination: accounts.maker.to_account_info(),
+            authority: accounts.vault.to_account_info(),
+        };
+        let cpi_program = accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        token::close_account(cpi_ctx)?;
+    }
+
+    set_return_data(&fill_result.try_to_vec()?);
+    msg!(
+        "Offer {} accepted by {}",
+        offer.offer_id,
+        accounts.taker.key()
+    );
+
+    accounts.offer.close(accounts.maker.to_account_info())?;
+    Ok(fill_result)
+}
+
+/// For oracle and AMM offers, recompute `amount_wanted` for what remains in
+/// escrow at the current price. Fixed-price offers are left untouched.
+fn reprice_from_oracle(accounts: &mut AcceptOffer) -> Result<()> {
+    require!(
+        accounts.offer.index_components.is_empty(),
+        ErrorCode::IndexFeedsRequired
+    );
+    if accounts.offer.amm_pool.is_some() {
+        return reprice_from_pool(accounts);
+    }
+    let Some(price_feed) = accounts.offer.price_feed else {
+        return Ok(());
+    };
+    let price_feed_info = accounts
+        .price_feed
+        .as_ref()
+        .ok_or(ErrorCode::InvalidOracle)?;
+    require_keys_eq!(price_feed_info.key(), price_feed, ErrorCode::InvalidOracle);
+
+    let clock = Clock::get()?;
+    let max_staleness = oracle_staleness(&accounts.config)?;
+    let price = load_pyth_price(price_feed_info, clock.unix_timestamp, max_staleness)?;
+
+    let offer = &mut accounts.offer;
+    offer.amount_wanted = oracle_amount_wanted(
+        offer.amount_offered,
+        &price,
+        offer.multiplier_bps,
+        offer.decimals_offered,
+        offer.decimals_wanted,
+    )?;
+    offer.check_maker_floor()?;
+
+    msg!(
+        "Offer {} repriced from oracle: {} wanted",
+        offer.offer_id,
+        offer.amount_wanted
+    );
+
+    Ok(())
+}
+
+/// Recompute an index offer's `amount_wanted` from its component feeds
+fn reprice_from_index(accounts: &mut AcceptOffer, feeds: &[AccountInfo]) -> Result<()> {
+    require!(
+        !accounts.offer.index_components.is_empty(),
+        ErrorCode::NotIndexOffer
+    );
+
+    let clock = Clock::get()?;
+    let max_staleness = oracle_staleness(&accounts.config)?;
+    let offer = &mut accounts.offer;
+    offer.amount_wanted = index_amount_wanted(
+        &offer.index_components,
+        feeds,
+        offer.amount_offered,
+        offer.multiplier_bps,
+        offer.decimals_offered,
+        offer.decimals_wanted,
+        clock.unix_timestamp,
+        max_staleness,
+    )?;
+    offer.check_maker_floor()?;
+
+    msg!(
+        "Offer {} repriced from index: {} wanted",
+        offer.offer_id,
+        offer.amount_wanted
+    );
+
+    Ok(())
+}
+
+fn reprice_from_pool(accounts: &mut AcceptOffer) -> Result<()> {
+    let Some(amm_pool) = accounts.offer.amm_pool else {
+        return Ok(());
+    };
+    let pool_info = accounts.amm_pool.as_ref().ok_or(ErrorCode::InvalidPool)?;
+    require_keys_eq!(pool_info.key(), amm_pool, ErrorCode::InvalidPool);
+
+    let reserves = load_pool_reserves(
+        pool_info,
+        &accounts.config,
+        accounts.offer.mint_offered,
+        accounts.offer.mint_wanted,
+        Clock::get()?.slot,
+    )?;
+
+    let offer = &mut accounts.offer;
+    offer.amount_wanted =
+        pool_amount_wanted(offer.amount_offered, &reserves, offer.multiplier_bps)?;
+    offer.check_maker_floor()?;
+
+    msg!(
+        "Offer {} repriced from AMM pool: {} wanted",
+        offer.offer_id,
+        offer.amount_wanted
+    );
+
+    Ok(())
+}
+
+/// Oldest oracle price the program will trade on, from the config if set
+fn oracle_staleness(config_info: &UncheckedAccount) -> Result<i64> {
+    Ok(match load_config(config_info)? {
+        Some(config) if config.max_oracle_staleness_secs > 0 => config.max_oracle_staleness_secs,
+        _ => DEFAULT_MAX_ORACLE_STALENESS_SECS,
+    })
+}
+
+/// Check that the instruction just before the current one is an Ed25519
+/// program verification of a single signature by `signer` over `message`,
+/// with the key and message embedded in that instruction
+fn verify_ed25519_signature(
+    instructions: &UncheckedAccount,
+    signer: &Pubkey,
+    message: &[u8],
+) -> Result<()> {
+    let current = load_current_index_checked(instructions)?;
+    require!(current > 0, ErrorCode::InvalidSignature);
+    let ix = load_instruction_at_checked(usize::from(current - 1), instructions)?;
+    require_keys_eq!(ix.program_id, ed25519_program::ID, ErrorCode::InvalidSignature);
+
+    // Header: signature count and padding, then one set of u16 offsets
+    let data = &ix.data;
+    require!(data.len() >= 16 && data[0] == 1, ErrorCode::InvalidSignature);
+    let read_u16 = |at: usize| usize::from(u16::from_le_bytes([data[at], data[at + 1]]));
+    let this_ix = usize::from(u16::MAX);
+    require!(
+        read_u16(4) == this_ix && read_u16(8) == this_ix && read_u16(14) == this_ix,
+        ErrorCode::InvalidSignature
+    );
+
+    let pubkey_offset = read_u16(6);
+    let message_offset = read_u16(10);
+    let message_size = read_u16(12);
+    let signed_pubkey = data.get(pubkey_offset..pubkey_offset + 32);
+    let signed_message = data.get(message_offset..message_offset + message_size);
+    require!(
+        signed_pubkey == Some(signer.as_ref()) && signed_message == Some(message),
+        ErrorCode::InvalidSignature
+    );
+
+    Ok(())
+}
+
+/// How long a `commit_accept` holds an offer, from the config if set
+fn accept_commit_ttl(config_info: &UncheckedAccount) -> Result<i64> {
+    Ok(match load_config(config_info)? {
+        Some(config) if config.accept_commit_ttl_secs > 0 => config.accept_commit_ttl_secs,
+        _ => DEFAULT_ACCEPT_COMMIT_TTL_SECS,
+    })
+}
+
+/// Time after an offer's expiry during which only the maker may cancel it
+fn expiry_grace(config_info: &UncheckedAccount) -> Result<i64> {
+    Ok(match load_config(config_info)? {
+        Some(config) if config.expiry_grace_secs > 0 => config.expiry_grace_secs,
+        _ => DEFAULT_EXPIRY_GRACE_SECS,
+    })
+}
+
+/// Aggregate price read from a Pyth price account
+struct PythPrice {
+    price: i64,
+    expo: i32,
+}
+
+/// Read the aggregate price from a legacy Pyth price account, rejecting
+/// prices that aren't currently trading or are older than `max_staleness`
+fn load_pyth_price(price_feed: &AccountInfo, now: i64, max_staleness: i64) -> Result<PythPrice> {
+    const MAGIC: u32 = 0xa1b2_c3d4;
+    const PRICE_ACCOUNT_TYPE: u32 = 3;
+    const STATUS_TRADING: u32 = 1;
+
+    require_keys_eq!(*price_feed.owner, PYTH_ORACLE_PROGRAM_ID, ErrorCode::InvalidOracle);
+
+    let data = price_feed.try_borrow_data()?;
+    require!(data.len() >= 240, ErrorCode::InvalidOracle);
+
+    let read_u32 = |at: usize| u32::from_le_bytes(data[at..at + 4].try_into().unwrap());
+    let read_i64 = |at: usize| i64::from_le_bytes(data[at..at + 8].try_into().unwrap());
+
+    require!(read_u32(0) == MAGIC, ErrorCode::InvalidOracle);
+    require!(read_u32(8) == PRICE_ACCOUNT_TYPE, ErrorCode::InvalidOracle);
+    require!(read_u32(224) == STATUS_TRADING, ErrorCode::InvalidOracle);
+
+    let publish_time = read_i64(96);
+    require!(
+        now.saturating_sub(publish_time) <= max_staleness,
+        ErrorCode::StaleOracle
+    );
+
+    Ok(PythPrice {
+        price: read_i64(208),
+        expo: read_u32(20) as i32,
+    })
+}
+
+/// Wanted amount for `amount_offered` at the oracle price scaled by
+/// `multiplier_bps`, adjusted for the mints' decimals and rounded up so the
+/// maker is never shortchanged
+fn oracle_amount_wanted(
+    amount_offered: u64,
+    price: &PythPrice,
+    multiplier_bps: u16,
+    decimals_offered: u8,
+    decimals_wanted: u8,
+) -> Result<u64> {
+    require!(price.price > 0, ErrorCode::InvalidOracle);
+
+    let exponent = price.expo + decimals_wanted as i32 - decimals_offered as i32;
+    let scale = 10u128
+        .checked_pow(exponent.unsigned_abs())
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    let mut numerator = (amount_offered as u128)
+        .checked_mul(price.price as u128)
+        .and_then(|n| n.checked_mul(multiplier_bps as u128))
+        .ok_or(ErrorCode::MathOverflow)?;
+    let mut denominator = BPS_DENOMINATOR as u128;
+    if exponent >= 0 {
+        numerator = numerator.checked_mul(scale).ok_or(ErrorCode::MathOverflow)?;
+    } else {
+        denominator = denominator.checked_mul(scale).ok_or(ErrorCode::MathOverflow)?;
+    }
+
+    let amount_wanted = u64::try_from(numerator.div_ceil(denominator))
+        .map_err(|_| error!(ErrorCode::MathOverflow))?;
+    require!(amount_wanted > 0, ErrorCode::InvalidAmount);
+    Ok(amount_wanted)
+}
+
+/// Pair each index weight with its feed, in order, checking there are as
+/// many feeds as weights and that the nonzero weights sum to the whole
+fn index_components(weights: &[u16], feeds: &[AccountInfo]) -> Result<Vec<IndexComponent>> {
+    require!(
+        !weights.is_empty() && weights.len() <= MAX_INDEX_COMPONENTS,
+        ErrorCode::InvalidIndexWeights
+    );
+    require!(feeds.len() == weights.len(), ErrorCode::InvalidOracle);
+    require!(
+        weights.iter().all(|weight| *weight > 0)
+            && weights.iter().map(|weight| u32::from(*weight)).sum::<u32>()
+                == u32::from(BPS_DENOMINATOR),
+        ErrorCode::InvalidIndexWeights
+    );
+
+    let mut components: Vec<IndexComponent> = Vec::with_capacity(weights.len());
+    for (weight, feed) in weights.iter().zip(feeds) {
+        require!(
+            components.iter().all(|c| c.price_feed != feed.key()),
+            ErrorCode::InvalidOracle
+        );
+        components.push(IndexComponent {
+            price_feed: feed.key(),
+            weight_bps: *weight,
+        });
+    }
+    Ok(components)
+}
+
+/// Wanted amount for `amount_offered` at the weighted average of the
+/// components' oracle prices, read from `feeds` in component order. A stale
+/// or missing component fails the whole index.
+#[allow(clippy::too_many_arguments)]
+fn index_amount_wanted(
+    components: &[IndexComponent],
+    feeds: &[AccountInfo],
+    amount_offered: u64,
+    multiplier_bps: u16,
+    decimals_offered: u8,
+    decimals_wanted: u8,
+    now: i64,
+    max_staleness: i64,
+) -> Result<u64> {
+    require!(feeds.len() >= components.len(), ErrorCode::InvalidOracle);
+
+    let mut weighted: u128 = 0;
+    for (component, feed) in components.iter().zip(feeds) {
+        require_keys_eq!(feed.key(), component.price_feed, ErrorCode::InvalidOracle);
+        let price = load_pyth_price(feed, now, max_staleness)?;
+        let amount = oracle_amount_wanted(
+            amount_offered,
+            &price,
+            multiplier_bps,
+            decimals_offered,
+            decimals_wanted,
+        )?;
+        weighted = weighted
+            .checked_add(amount as u128 * component.weight_bps as u128)
+            .ok_or(ErrorCode::MathOverflow)?;
+    }
+
+    u64::try_from(weighted.div_ceil(BPS_DENOMINATOR as u128))
+        .map_err(|_| error!(ErrorCode::MathOverflow))
+}
+
+/// Reserves of a constant-product pool, oriented to an offer's mints
+struct PoolReserves {
+    offered: u64,
+    wanted: u64,
+}
+
+/// Read a constant-product pool account laid out as an 8-byte
+/// discriminator, `mint_a`, `mint_b`, `reserve_a`, `reserve_b` and the slot
+/// the reserves were last updated at. The pool must belong to the config's
+/// `amm_program`, pair exactly the two mints, hold liquidity on both sides
+/// and have been updated within the config's staleness window.
+fn load_pool_reserves(
+    pool: &AccountInfo,
+    config_info: &UncheckedAccount,
+    mint_offered: Pubkey,
+    mint_wanted: Pubkey,
+    slot: u64,
+) -> Result<PoolReserves> {
+    let config = load_config(config_info)?.ok_or(ErrorCode::InvalidPool)?;
+    require_keys_eq!(*pool.owner, config.amm_program, ErrorCode::InvalidPool);
+
+    let data = pool.try_borrow_data()?;
+    require!(data.len() >= 96, ErrorCode::InvalidPool);
+
+    let read_pubkey = |at: usize| Pubkey::try_from(&data[at..at + 32]).unwrap();
+    let read_u64 = |at: usize| u64::from_le_bytes(data[at..at + 8].try_into().unwrap());
+
+    let (mint_a, mint_b) = (read_pubkey(8), read_pubkey(40));
+    let (reserve_a, reserve_b) = (read_u64(72), read_u64(80));
+    let reserves = if (mint_a, mint_b) == (mint_offered, mint_wanted) {
+        PoolReserves { offered: reserve_a, wanted: reserve_b }
+    } else if (mint_a, mint_b) == (mint_wanted, mint_offered) {
+        PoolReserves { offered: reserve_b, wanted: reserve_a }
+    } else {
+        return err!(ErrorCode::InvalidPool);
+    };
+    require!(
+        reserves.offered > 0 && reserves.wanted > 0,
+        ErrorCode::EmptyPool
+    );
+
+    let max_staleness = match config.max_pool_staleness_slots {
+        0 => DEFAULT_MAX_POOL_STALENESS_SLOTS,
+        slots => slots,
+    };
+    require!(
+        slot.saturating_sub(read_u64(88)) <= max_staleness,
+        ErrorCode::StalePool
+    );
+
+    Ok(reserves)
+}
+
+/// Wanted amount for `amount_offered` at the pool's spot price scaled by
+/// `multiplier_bps`, rounded up so the maker is never shortchanged
+fn pool_amount_wanted(
+    amount_offered: u64,
+    reserves: &PoolReserves,
+    multiplier_bps: u16,
+) -> Result<u64> {
+    let numerator = (amount_offered as u128)
+        .checked_mul(reserves.wanted as u128)
+        .and_then(|n| n.checked_mul(multiplier_bps as u128))
+        .ok_or(ErrorCode::MathOverflow)?;
+    let denominator = (reserves.offered as u128) * BPS_DENOMINATOR as u128;
+
+    let amount_wanted = u64::try_from(numerator.div_ceil(denominator))
+        .map_err(|_| error!(ErrorCode::MathOverflow))?;
+    require!(amount_wanted > 0, ErrorCode::InvalidAmount);
+    Ok(amount_wanted)
+}
+
+/// `amount * numerator / denominator`, rounded down or up. Works in `u128`
+/// so large 9-decimal amounts can't overflow the intermediate product.
+fn mul_div(amount: u64, numerator: u64, denominator: u64, round_up: bool) -> Result<u64> {
+    let rounding = if round_up {
+        RoundingMode::Up
+    } else {
+        RoundingMode::Down
+    };
+    mul_div_rounded(amount, numerator, denominator, rounding)
+}
+
+/// `amount * numerator / denominator` in `u128`, rounded per `rounding`
+fn mul_div_rounded(
+    amount: u64,
+    numerator: u64,
+    denominator: u64,
+    rounding: RoundingMode,
+) -> Result<u64> {
+    require!(denominator > 0, ErrorCode::MathOverflow);
+
+    let product = (amount as u128)
+        .checked_mul(numerator as u128)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let denominator = denominator as u128;
+    let quotient = match rounding {
+        RoundingMode::Up => product.div_ceil(denominator),
+        RoundingMode::Down => product / denominator,
+        // Exact halves go up, to the maker
+        RoundingMode::Nearest => {
+            product
+                .checked_add(denominator / 2)
+                .ok_or(ErrorCode::MathOverflow)?
+                / denominator
+        }
+    };
+    u64::try_from(quotient).map_err(|_| error!(ErrorCode::MathOverflow))
+}
+
+/// Reject offers whose price `amount_wanted / amount_offered` strays more
+/// than `tolerance_bps` from `expected_price` (scaled by `PRICE_SCALE`)
+fn check_price_tolerance(
+    amount_offered: u64,
+    amount_wanted: u64,
+    expected_price: u64,
+    tolerance_bps: u16,
+) -> Result<()> {
+    require!(expected_price > 0, ErrorCode::InvalidAmount);
+    require!(tolerance_bps <= BPS_DENOMINATOR, ErrorCode::InvalidFeeBps);
+
+    // Compare amount_wanted * PRICE_SCALE against expected_price * amount_offered
+    let actual = (amount_wanted as u128)
+        .checked_mul(PRICE_SCALE as u128)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let expected = (expected_price as u128)
+        .checked_mul(amount_offered as u128)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let deviation = actual.abs_diff(expected);
+    let allowed = expected
+        .checked_mul(tolerance_bps as u128)
+        .ok_or(ErrorCode::MathOverflow)?;
+    require!(
+        deviation
+            .checked_mul(BPS_DENOMINATOR as u128)
+            .ok_or(ErrorCode::MathOverflow)?
+            <= allowed,
+        ErrorCode::PriceOutOfTolerance
+    );
+
+    Ok(())
+}
+
+/// Runs the checks every new listing shares (maker approval, emergency
+/// switch, dust guards, rate limit, open offer cap) and returns the maker's
+/// next offer ID along with the config, if it exists.
+fn claim_listing(
+    user_profile: &mut Account<UserProfile>,
+    maker: Pubkey,
+    config: &UncheckedAccount,
+    maker_status: Option<&Account<MakerStatus>>,
+    amount_offered: u64,
+    amount_wanted: u64,
+    now: i64,
+) -> Result<(u64, Option<Config>)> {
+    check_maker_approved(config, maker_status)?;
+
+    // Dust guards and rate limits only apply once the protocol config exists
+    let config = load_config(config)?;
+    let mut max_open_offers = 0;
+    if let Some(config) = &config {
+        require!(!config.emergency, ErrorCode::EmergencyDeclared);
+        max_open_offers = config.max_offers_per_user;
+        require!(
+            amount_offered >= config.min_amount_offered,
+            ErrorCode::BelowMinimum
+        );
+        require!(
+            amount_wanted >= config.min_amount_wanted,
+            ErrorCode::BelowMinimum
+        );
+        require!(
+            now.saturating_sub(user_profile.last_offer_ts) >= config.min_offer_interval_secs,
+            ErrorCode::RateLimited
+        );
+    }
+
+    // Initialize UserProfile if this is first time
+    user_profile.claim(maker)?;
+
+    user_profile.record_offer_ts(now)?;
+    user_profile.claim_offer_slot(max_open_offers)?;
+
+    // Get current offer ID and increment counter
+    let offer_id = user_profile.offer_count;
+    user_profile.offer_count = offer_id
+        .checked_add(1)
+        .ok_or(ErrorCode::CounterOverflow)?;
+
+    Ok((offer_id, config))
+}
+
+/// Protocol accounts a new offer is checked against and counted in
+struct OfferGates<'a, 'info> {
+    /// Protocol config PDA; may not be initialized yet, see `load_config`
+    config: &'a UncheckedAccount<'info>,
+    /// `MintTvl` of the offered mint, created on its first tracked offer
+    mint_tvl: &'a UncheckedAccount<'info>,
+    mint_tvl_bump: u8,
+    /// Funds a new `MintTvl`
+    payer: &'a AccountInfo<'info>,
+    /// Pause status PDAs of both mints; may not exist, see
+    /// `check_mints_unpaused`
+    mint_status_offered: &'a UncheckedAccount<'info>,
+    mint_status_wanted: &'a UncheckedAccount<'info>,
+    /// Maker's allowlist entry; required while `config.maker_allowlist` is on
+    maker_status: Option<&'a Account<'info, MakerStatus>>,
+}
+
+/// Shared body of the offer creation instructions: claims the next offer ID
+/// from the maker's profile, records the offer, escrows the offered tokens
+/// and counts them in the offered mint's `MintTvl`
+#[allow(clippy::too_many_arguments)]
+fn open_offer<'info>(
+    user_profile: &mut Account<'info, UserProfile>,
+    offer: &mut Account<'info, Offer>,
+    vault: &mut Account<'info, TokenAccount>,
+    maker_token_account: &Account<'info, TokenAccount>,
+    mint_offered: &Account<'info, Mint>,
+    mint_wanted: &InterfaceAccount<'info, token_interface::Mint>,
+    maker: &AccountInfo<'info>,
+    escrow_authority: &AccountInfo<'info>,
+    escrow_signer_seeds: &[&[&[u8]]],
+    token_program: &Program<'info, Token>,
+    system_program: &Program<'info, System>,
+    rent: Option<&Sysvar<'info, Rent>>,
+    gates: &OfferGates<'_, 'info>,
+    amount_offered: u64,
+    amount_wanted: u64,
+    options: &OfferOptions,
+    nonce: Option<[u8; 16]>,
+    offer_bump: u8,
+    vault_bump: u8,
+    allow_same_mint: bool,
+) -> Result<()> {
+    check_system_accounts(
+        &system_program.to_account_info(),
+        rent.map(|rent| rent.to_account_info()).as_ref(),
+    )?;
+
+    // Validate amounts
+    require!(amount_offered > 0, ErrorCode::InvalidAmount);
+    require!(amount_wanted > 0, ErrorCode::InvalidAmount);
+    // No balance can cover more than the mint has ever issued
+    require!(
+        amount_offered <= mint_offered.supply,
+        ErrorCode::ExceedsMintSupply
+    );
+    // Only rebate offers may want back the token they offer
+    require!(
+        allow_same_mint || mint_offered.key() != mint_wanted.key(),
+        ErrorCode::SameMint
+    );
+    check_mints_unpaused(gates.mint_status_offered, gates.mint_status_wanted)?;
+
+    let clock = Clock::get()?;
+    let (offer_id, config) = claim_listing(
+        user_profile,
+        maker.key(),
+        gates.config,
+        gates.maker_status,
+        amount_offered,
+        amount_wanted,
+        clock.unix_timestamp,
+    )?;
+    let max_fee_bps = config.as_ref().map_or(0, |config| config.max_fee_bps());
+    let default_expiry_secs = config.as_ref().map_or(0, |config| config.default_expiry_secs);
+
+    // Offers without any expiry get the config's default lifetime, if set
+    let expiry_ts = match options.expiry_ts {
+        None if options.expiry_slot.is_none() && default_expiry_secs > 0 => {
+            Some(checked_add_ts(clock.unix_timestamp, default_expiry_secs)?)
+        }
+        expiry_ts => expiry_ts,
+    };
+    if let Some(expiry_ts) = expiry_ts {
+        validate_expiry(expiry_ts, clock.unix_timestamp)?;
+        if let Some(active_from_ts) = options.active_from_ts {
+            require!(active_from_ts < expiry_ts, ErrorCode::InvalidExpiry);
+        }
+    }
+    if let Some(expiry_slot) = options.expiry_slot {
+        require!(expiry_slot > clock.slot, ErrorCode::InvalidExpiry);
+    }
+    require!(
+        options.category <= MAX_OFFER_CATEGORY,
+        ErrorCode::InvalidCategory
+    );
+    require!(
+        options.referrer_bps <= BPS_DENOMINATOR,
+        ErrorCode::InvalidFeeBps
+    );
+    require!(
+        options.taker_rebate_bps <= BPS_DENOMINATOR,
+        ErrorCode::InvalidFeeBps
+    );
+    if let Some(no_cancel_until) = options.no_cancel_until {
+        require!(
+            no_cancel_until > clock.unix_timestamp
+                && no_cancel_until - clock.unix_timestamp <= MAX_CANCEL_LOCK_SECS,
+            ErrorCode::InvalidCancelLock
+        );
+    }
+    require!(
+        options.auto_relist_amount_wanted != Some(0),
+        ErrorCode::InvalidAmount
+    );
+    require!(
+        options.lot_size == 0 || (options.allow_partial && options.lot_size <= amount_wanted),
+        ErrorCode::InvalidLotSize
+    );
+    // A seal without an expiry would lock the escrow forever
+    require!(
+        !options.sealed || expiry_ts.is_some() || options.expiry_slot.is_some(),
+        ErrorCode::InvalidExpiry
+    );
+    require!(
+        options.blocked_takers.len() <= MAX_BLOCKED_TAKERS,
+        ErrorCode::BlocklistTooLarge
+    );
+    require!(
+        options.fill_queue_len <= MAX_FILL_QUEUE_LEN,
+        ErrorCode::FillQueueTooLong
+    );
+    require!(
+        options.max_acceptors <= MAX_ACCEPTORS,
+        ErrorCode::AcceptorCapTooLarge
+    );
+    require!(
+        (0..=MAX_ESCROW_AGE_SECS).contains(&options.vesting_cliff_secs),
+        ErrorCode::InvalidVestingCliff
+    );
+    require!(
+        options.fill_window.is_none_or(|window| window.is_valid()),
+        ErrorCode::InvalidFillWindow
+    );
+    // Every cut comes out of the wanted amount, so together they must fit in it
+    require!(
+        u32::from(options.royalty_bps) + u32::from(options.referrer_bps) + u32::from(max_fee_bps)
+            <= u32::from(BPS_DENOMINATOR),
+        ErrorCode::FeesExceedTotal
+    );
+    if let Some(expected_price) = options.expected_price {
+        check_price_tolerance(
+            amount_offered,
+            amount_wanted,
+            expected_price,
+            options.tolerance_bps,
+        )?;
+    }
+
+    // `init` already refuses live accounts; this keeps a reused one from
+    // being silently overwritten should that ever change
+    require!(offer.is_fresh(), ErrorCode::AlreadyInitialized);
+
+    // Initialize offer account
+    offer.offer_id = offer_id;
+    offer.maker = maker.key();
+    offer.mint_offered = maker_token_account.mint;
+    offer.mint_wanted = mint_wanted.key();
+    offer.amount_offered = amount_offered;
+    offer.amount_wanted = amount_wanted;
+    offer.vault_bump = vault_bump;
+    offer.bump = offer_bump;
+    offer.created_at = clock.unix_timestamp;
+    offer.nonce = nonce;
+    offer.expiry_ts = expiry_ts;
+    offer.allow_partial = options.allow_partial;
+    offer.category = options.category;
+    offer.maker_receive_account = options.maker_receive_account;
+    offer.decimals_offered = mint_offered.decimals;
+    offer.decimals_wanted = mint_wanted.decimals;
+    offer.version = OFFER_VERSION;
+    offer.active_from_ts = options.active_from_ts;
+    offer.referrer = options.referrer;
+    offer.referrer_bps = options.referrer_bps;
+    offer.max_fills = options.max_fills;
+    offer.maker_derived_vault = options.maker_derived_vault;
+    offer.dust_threshold = options.dust_threshold;
+    offer.dust_in_whole_tokens = options.dust_in_whole_tokens;
+    offer.metadata_hash = options.metadata_hash;
+    offer.no_cancel_until = options.no_cancel_until;
+    offer.auto_relist_amount_wanted = options.auto_relist_amount_wanted;
+    offer.lot_size = options.lot_size;
+    offer.max_acceptors = options.max_acceptors;
+    offer.acceptors = Vec::new();
+    offer.attestation_max_age_slots = options.attestation_max_age_slots;
+    offer.min_acceptable_wanted = options.min_acceptable_wanted;
+    offer.gate_mint = options.gate_mint;
+    offer.taker_rebate_bps = options.taker_rebate_bps;
+    offer.taker_rebate_escrowed = mul_div(
+        amount_offered,
+        u64::from(options.taker_rebate_bps),
+        u64::from(BPS_DENOMINATOR),
+        false,
+    )?;
+    let amount_escrowed = amount_offered
+        .checked_add(offer.taker_rebate_escrowed)
+        .ok_or(ErrorCode::MathOverflow)?;
+    require!(offer.dust_threshold_units().is_some(), ErrorCode::MathOverflow);
+    offer.royalty_recipient = options.royalty_recipient;
+    offer.royalty_bps = options.royalty_bps;
+    offer.blocked_takers = options.blocked_takers.clone();
+    offer.sealed = options.sealed;
+    offer.expiry_slot = options.expiry_slot;
+    offer.current_owner = maker.key();
+    offer.omnibus_vault = options.omnibus_vault;
+    offer.min_taker_swaps = options.min_taker_swaps;
+    offer.requires_authorization = options.requires_authorization;
+    offer.fill_queue_capacity = options.fill_queue_len;
+    offer.rounding = options.rounding;
+    offer.vesting_cliff_secs = options.vesting_cliff_secs;
+    offer.fill_window = options.fill_window;
+
+    // Escrow in a vault that starts frozen could never be released
+    require!(!vault.is_frozen(), ErrorCode::FrozenMintUnsupported);
+    // Only a shared vault may already hold tokens
+    require!(
+        options.omnibus_vault || vault.amount == 0,
+        ErrorCode::AlreadyInitialized
+    );
+
+    // Transfer tokens from maker to vault, signed by the maker or, for
+    // relayed offers, the program's delegate PDA
+    let cpi_accounts = Transfer {
+        from: maker_token_account.to_account_info(),
+        to: vault.to_account_info(),
+        authority: escrow_authority.clone(),
+    };
+    let cpi_program = token_program.to_account_info();
+    let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, escrow_signer_seeds);
+    token::transfer(cpi_ctx, amount_escrowed)?;
+
+    // The vault is freshly created, so it must hold exactly what was escrowed
+    vault.reload()?;
+    require!(
+        vault.amount == amount_escrowed,
+        ErrorCode::VaultBalanceMismatch
+    );
+
+    init_mint_tvl(
+        gates.mint_tvl,
+        gates.mint_tvl_bump,
+        offer.mint_offered,
+        gates.payer,
+        system_program,
+    )?;
+    lock_mint_tvl(gates.mint_tvl, gates.config, amount_offered)?;
+    offer.tvl_tracked = true;
+
+    // The taker tip rides on the offer account, above its rent
+    if options.taker_tip_lamports > 0 {
+        let cpi_accounts = system_program::Transfer {
+            from: maker.clone(),
+            to: offer.to_account_info(),
+        };
+        let cpi_program = system_program.to_account_info();
+        system_program::transfer(
+            CpiContext::new(cpi_program, cpi_accounts),
+            options.taker_tip_lamports,
+        )?;
+    }
+    offer.taker_tip_lamports = options.taker_tip_lamports;
+
+    emit!(OfferCreated {
+        offer: offer.key(),
+        maker: offer.maker,
+        offer_id,
+        mint_offered: offer.mint_offered,
+        mint_wanted: offer.mint_wanted,
+        amount_offered,
+        amount_wanted,
+        category: offer.category,
+        decimals_offered: offer.decimals_offered,
+        decimals_wanted: offer.decimals_wanted,
+        active_from_ts: offer.active_from_ts,
+        metadata_hash: offer.metadata_hash,
+    });
+
+    msg!(
+        "Offer {} created: {} {} for {} {}",
+        offer_id,
+        amount_offered,
+        offer.mint_offered,
+        amount_wanted,
+        offer.mint_wanted
+    );
+
+    // Let CPI callers read the new offer without parsing logs
+    set_return_data(&(offer_id, offer.key()).try_to_vec()?);
+
+    Ok(())
+}
+
+/// Amount `token_account` has approved for `delegate`, 0 when another
+/// delegate (or none) is set
+fn delegated_allowance(token_account: &TokenAccount, delegate: &Pubkey) -> u64 {
+    if token_account.delegate == COption::Some(*delegate) {
+        token_account.delegated_amount
+    } else {
+        0
+    }
+}
+
+/// Empty each vault of a sell bundle into the paired destination token
+/// account from `groups` (vault, destination, `MintTvl` per item), take the
+/// items off their mints' `MintTvl` and close the vaults, refunding their
+/// rent to the maker. With `exact`, as for a taker's fill, each vault must
+/// hold exactly its item; otherwise it is emptied of whatever it holds.
+fn release_bundle<'info>(
+    bundle: &Account<'info, SellBundle>,
+    groups: &'info [AccountInfo<'info>],
+    maker: &AccountInfo<'info>,
+    token_program: &Program<'info, Token>,
+    program_id: &Pubkey,
+    exact: bool,
+) -> Result<()> {
+    require!(
+        groups.len() == bundle.items.len() * 3,
+        ErrorCode::InvalidBundle
+    );
+    check_compute_budget(bundle.items.len(), BUNDLE_ITEM_COMPUTE_UNITS)?;
+
+    let bundle_key = bundle.key();
+    for (group, item) in groups.chunks(3).zip(bundle.items.iter()) {
+        // Each group must line up with the stored item at the same index, or
+        // one item's amount would be settled against another's accounts
+        let vault = &group[0];
+        let destination = Account::<TokenAccount>::try_from(&group[1])?;
+        require_keys_eq!(destination.mint, item.mint, ErrorCode::BundleOrderMismatch);
+
+        let seeds = &[
+            b"vault",
+            bundle_key.as_ref(),
+            item.mint.as_ref(),
+            &[item.vault_bump],
+        ];
+        let vault_key = Pubkey::create_program_address(seeds, program_id)
+            .map_err(|_| error!(ErrorCode::InvalidBundle))?;
+        require_keys_eq!(vault.key(), vault_key, ErrorCode::BundleOrderMismatch);
+        let signer = &[&seeds[..]];
+
+        // Tokens sent to a vault on top of its item would keep it from
+        // closing; the maker takes them back, a taker gets the item only
+        let balance = Account::<TokenAccount>::try_from(vault)?.amount;
+        let amount = if exact {
+            require!(balance == item.amount, ErrorCode::VaultBalanceMismatch);
+            item.amount
+        } else {
+            balance
+        };
+
+        let cpi_accounts = Transfer {
+            from: vault.clone(),
+            to: destination.to_account_info(),
+            authority: vault.clone(),
+        };
+        let cpi_program = token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        token::transfer(cpi_ctx, amount)?;
+
+        let cpi_accounts = CloseAccount {
+            account: vault.clone(),
+            destination: maker.clone(),
+            authority: vault.clone(),
+        };
+        let cpi_program = token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        token::close_account(cpi_ctx)?;
+
+        let mint_tvl = &group[2];
+        let (mint_tvl_key, _) =
+            Pubkey::find_program_address(&[b"mint_tvl", item.mint.as_ref()], program_id);
+        require_keys_eq!(mint_tvl.key(), mint_tvl_key, ErrorCode::BundleOrderMismatch);
+        release_mint_tvl(mint_tvl, item.amount)?;
+    }
+
+    Ok(())
+}
+
+/// With the `verbose-logs` feature, log a vault's balance before and after a
+/// transfer out of it. Default builds stay quiet.
+fn log_vault_transfer(vault: &AccountInfo, balance_before: u64, amount: u64) -> Result<()> {
+    if cfg!(feature = "verbose-logs") {
+        let balance_after =
+            TokenAccount::try_deserialize(&mut &vault.try_borrow_data()?[..])?.amount;
+        msg!(
+            "Vault {} balance {} -> {} (transferred {})",
+            vault.key(),
+            balance_before,
+            balance_after,
+            amount
+        );
+    }
+    Ok(())
+}
+
+/// Whether `offer` can be settled against `counterparty` in `match_offers`
+fn check_matchable(offer: &Offer, counterparty: &Offer, clock: &Clock) -> Result<()> {
+    require!(
+        offer.mint_offered == counterparty.mint_wanted
+            && offer.mint_wanted == counterparty.mint_offered,
+        ErrorCode::OffersNotCompatible
+    );
+    require!(!offer.is_expired(clock), ErrorCode::OfferExpired);
+    require!(offer.is_active(clock.unix_timestamp), ErrorCode::OfferNotYetActive);
+    require!(
+        !offer.is_held(clock.unix_timestamp) && offer.locked_taker.is_none(),
+        ErrorCode::OfferReserved
+    );
+    require!(
+        !offer.is_blocked(&counterparty.current_owner),
+        ErrorCode::TakerBlocked
+    );
+    require!(
+        offer.auto_relist_amount_wanted.is_none(),
+        ErrorCode::AutoRelistOffer
+    );
+    // Matching has no taker to vet and pays no cuts, and oracle and AMM
+    // offers have no fixed ask to compare
+    require!(
+        offer.price_feed.is_none()
+            && offer.amm_pool.is_none()
+            && offer.index_components.is_empty()
+            && !offer.requires_authorization
+            && offer.attestation_max_age_slots == 0
+            && offer.gate_mint.is_none()
+            && offer.taker_rebate_escrowed == 0
+            && offer.min_taker_swaps == 0
+            && offer.referrer_bps == 0
+            && offer.royalty_bps == 0,
+        ErrorCode::OffersNotCompatible
+    );
+    Ok(())
+}
+
+/// Amounts `match_offers` moves out of each vault: `(out of a's vault, out of
+/// b's vault)`. An offer whose whole escrow falls short of the other's ask is
+/// consumed whole, filling the other partially at the other's own price,
+/// rounded down in its favor; the consumed offer must still get at least its
+/// ask. When each escrow covers the other's ask, both are swapped whole,
+/// neither exceeding the ask by more than `MATCH_TOLERANCE_BPS`.
+fn match_amounts(a: &Offer, b: &Offer) -> Result<(u64, u64)> {
+    let (amount_a, amount_b) = if b.amount_offered < a.amount_wanted {
+        let amount_a = mul_div(b.amount_offered, a.amount_offered, a.amount_wanted, false)?;
+        (amount_a, b.amount_offered)
+    } else if a.amount_offered < b.amount_wanted {
+        let amount_b = mul_div(a.amount_offered, b.amount_offered, b.amount_wanted, false)?;
+        (a.amount_offered, amount_b)
+    } else {
+        let within_tolerance = |asked: u64, received: u64| {
+            (received - asked) as u128 * BPS_DENOMINATOR as u128
+                <= asked as u128 * MATCH_TOLERANCE_BPS as u128
+        };
+        require!(
+            within_tolerance(a.amount_wanted, b.amount_offered)
+                && within_tolerance(b.amount_wanted, a.amount_offered),
+            ErrorCode::OffersNotCompatible
+        );
+        (a.amount_offered, b.amount_offered)
+    };
+
+    require!(
+        amount_a < a.amount_offered || amount_b >= a.amount_wanted,
+        ErrorCode::OffersNotCompatible
+    );
+    require!(
+        amount_b < b.amount_offered || amount_a >= b.amount_wanted,
+        ErrorCode::OffersNotCompatible
+    );
+    check_partial_match(a, amount_a)?;
+    check_partial_match(b, amount_b)?;
+    Ok((amount_a, amount_b))
+}
+
+/// Taking less than an offer's whole escrow follows the partial fill rules
+fn check_partial_match(offer: &Offer, amount_out: u64) -> Result<()> {
+    if amount_out == offer.amount_offered {
+        return Ok(());
+    }
+    require!(offer.allow_partial, ErrorCode::PartialFillNotAllowed);
+    require!(amount_out > 0, ErrorCode::OffersNotCompatible);
+    require!(
+        offer.max_fills == 0 || offer.fill_count < offer.max_fills,
+        ErrorCode::MaxFillsReached
+    );
+    require!(!offer.is_dust(), ErrorCode::DustRemainder);
+    Ok(())
+}
+
+/// Record one side of a `match_offers` settlement like a fill by the other
+/// side's owner
+fn record_match(
+    offer: &mut Offer,
+    amount_out: u64,
+    amount_in: u64,
+    counterparty: Pubkey,
+    now: i64,
+) -> Result<()> {
+    require!(
+        amount_out == offer.amount_offered || offer.fill_count < u16::MAX,
+        ErrorCode::FillCountExhausted
+    );
+    offer.amount_offered = offer
+        .amount_offered
+        .checked_sub(amount_out)
+        .ok_or(ErrorCode::MathOverflow)?;
+    // A whole swap may pay more than was asked
+    offer.amount_wanted = offer.amount_wanted.saturating_sub(amount_in);
+    offer.admit_acceptor(counterparty)?;
+    offer.taker = Some(counterparty);
+    offer.accepted_at = now;
+    offer.fill_count = offer.fill_count.saturating_add(1);
+    Ok(())
+}
+
+/// Return the escrow to the maker and close the vault (refund rent to maker).
+/// Moves what the vault actually holds, up to `offer.amount_escrowed()`, so a
+/// vault left short of its recorded escrow can still be closed; returns that
+/// amount.
+fn refund_vault<'info>(
+    offer: &Account<'info, Offer>,
+    vault: &Account<'info, TokenAccount>,
+    maker_token_account: &Account<'info, TokenAccount>,
+    maker: &AccountInfo<'info>,
+    token_program: &Program<'info, Token>,
+    mint_tvl: &UncheckedAccount<'info>,
+) -> Result<u64> {
+    let amount_returned = vault.amount.min(offer.amount_escrowed());
+    release_escrow(
+        offer,
+        vault,
+        maker_token_account,
+        maker,
+        token_program,
+        amount_returned,
+        true,
+    )?;
+    if offer.tvl_tracked {
+        release_mint_tvl(mint_tvl, offer.amount_offered)?;
+    }
+    Ok(amount_returned)
+}
+
+/// Transfer `amount` out of an offer's vault to `destination`, then, if
+/// `close`, close the vault and refund its rent to `maker`
+fn release_escrow<'info>(
+    offer: &Account<'info, Offer>,
+    vault: &Account<'info, TokenAccount>,
+    destination: &Account<'info, TokenAccount>,
+    maker: &AccountInfo<'info>,
+    token_program: &Program<'info, Token>,
+    amount: u64,
+    close: bool,
+) -> Result<()> {
+    // Transfer tokens out of the vault using PDA signer
+    let offer_key = offer.key();
+    let vault_key_seed = offer.vault_key_seed(offer_key);
+    let vault_id_seed = offer.vault_id_seed();
+    let mint_key = offer.mint_offered;
+    let seeds = &[
+        b"vault",
+        vault_key_seed.as_ref(),
+        vault_id_seed.as_slice(),
+        mint_key.as_ref(),
+        &[offer.vault_bump],
+    ];
+    let signer = &[&seeds[..]];
+
+    let cpi_accounts = Transfer {
+        from: vault.to_account_info(),
+        to: destination.to_account_info(),
+        authority: vault.to_account_info(),
+    };
+    let cpi_program = token_program.to_account_info();
+    let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+    token::transfer(cpi_ctx, amount)?;
+    log_vault_transfer(&vault.to_account_info(), vault.amount, amount)?;
+
+    // A shared vault still holds other offers' escrow
+    if !close || offer.omnibus_vault {
+        return Ok(());
+    }
+    // The rent refund must reach the offer's owner, whatever the accounts
+    // struct checked
+    require_keys_eq!(maker.key(), offer.current_owner, ErrorCode::Unauthorized);
+
+    let cpi_accounts = CloseAccount {
+        account: vault.to_account_info(),
+        destination: maker.clone(),
+        authority: vault.to_account_info(),
+    };
+    let cpi_program = token_program.to_account_info();
+    let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+    token::close_account(cpi_ctx)
+}
+
+/// Vault seed following `b"vault"`: the offer address by default, the
+/// maker for offers created with `maker_derived_vault`, or the program ID
+/// for the shared vaults of `omnibus_vault` offers
+fn vault_key_seed(
+    maker_derived_vault: bool,
+    omnibus_vault: bool,
+    offer_key: Pubkey,
+    maker: Pubkey,
+) -> Pubkey {
+    if omnibus_vault {
+        crate::ID
+    } else if maker_derived_vault {
+        maker
+    } else {
+        offer_key
+    }
+}
+
+/// Vault seed preceding the mint: the offer's ID seed for maker-derived
+/// vaults, so integrators can compute `[b"vault", maker, offer_id, mint]`
+/// without the offer address, and `OMNIBUS_VAULT_SEED` for omnibus vaults.
+/// Empty by default, which leaves the original `[b"vault", offer, mint]`
+/// address unchanged.
+fn vault_id_seed(maker_derived_vault: bool, omnibus_vault: bool, id_seed: &[u8]) -> Vec<u8> {
+    if omnibus_vault {
+        OMNIBUS_VAULT_SEED.to_vec()
+    } else if maker_derived_vault {
+        id_seed.to_vec()
+    } else {
+        Vec::new()
+    }
+}
+
+/// The vault must hold the offered mint and be its own authority. The seeds
+/// already imply this; the explicit check keeps it true across refactors.
+fn validate_vault(vault: &Account<TokenAccount>, offer: &Offer) -> Result<()> {
+    require!(vault.mint == offer.mint_offered, ErrorCode::InvalidMint);
+    require!(vault.owner == vault.key(), ErrorCode::Unauthorized);
+    Ok(())
+}
+
+/// The stored bumps must be the canonical ones. The seeds constraints only
+/// prove that they derive the accounts passed in, which a non-canonical bump
+/// left by corrupted state would too.
+fn validate_bumps(offer: &Account<Offer>) -> Result<()> {
+    let (_, bump) = offer.canonical_address();
+    require!(bump == offer.bump, ErrorCode::InvalidBump);
+
+    let (_, vault_bump) = offer.canonical_vault(offer.key());
+    require!(vault_bump == offer.vault_bump, ErrorCode::InvalidBump);
+    Ok(())
+}
+
+/// An expiry must lie in the future and within `MAX_OFFER_LIFETIME_SECS`
+fn validate_expiry(expiry_ts: i64, now: i64) -> Result<()> {
+    require!(expiry_ts > now, ErrorCode::InvalidExpiry);
+    require!(
+        expiry_ts - now <= MAX_OFFER_LIFETIME_SECS,
+        ErrorCode::InvalidExpiry
+    );
+    Ok(())
+}
+
+/// Shift a unix timestamp by `delta` seconds; every derived deadline goes
+/// through here so absurd durations fail cleanly instead of wrapping
+fn checked_add_ts(base: i64, delta: i64) -> Result<i64> {
+    Ok(base.checked_add(delta).ok_or(ErrorCode::TimestampOverflow)?)
+}
+
+/// Fail up front when `entries` loop iterations at `units_per_entry` each
+/// likely won't fit in the compute units left, rather than running out
+/// midway with an opaque error
+fn check_compute_budget(entries: usize, units_per_entry: u64) -> Result<()> {
+    let needed = (entries as u64).saturating_mul(units_per_entry);
+    let remaining = sol_remaining_compute_units();
+    if remaining < needed {
+        msg!(
+            "{} entries need about {} compute units but {} remain; split them into \
+             smaller batches or raise the compute unit limit",
+            entries,
+            needed,
+            remaining
+        );
+        return err!(ErrorCode::ComputeBudgetRisk);
+    }
+    Ok(())
+}
+
+/// Read the protocol config, treating a not-yet-created account as absent
+fn load_config(config_info: &UncheckedAccount) -> Result<Option<Config>> {
+    if config_info.data_is_empty() {
+        return Ok(None);
+    }
+    require_keys_eq!(*config_info.owner, crate::ID, ErrorCode::InvalidConfig);
+    let data = config_info.try_borrow_data()?;
+    Ok(Some(Config::try_deserialize(&mut &data[..])?))
+}
+
+/// Protocol fee withheld from the maker's share of `amount_in`, at the rate
+/// of the volume tier it falls into. Makers holding at least
+/// `rebate_threshold` of the rebate mint get `maker_rebate_bps` knocked off
+/// the fee rate.
+fn protocol_fee(
+    config: &Config,
+    maker: Pubkey,
+    amount_in: u64,
+    maker_rebate_token_account: Option<&Account<TokenAccount>>,
+) -> Result<u64> {
+    let mut fee_bps = config.fee_bps_for(amount_in);
+
+    if let Some(rebate_account) = maker_rebate_token_account {
+        require!(
+            rebate_account.mint == config.rebate_mint,
+            ErrorCode::InvalidMint
+        );
+        require!(
+            rebate_account.owner == maker,
+            ErrorCode::Unauthorized
+        );
+        if rebate_account.amount >= config.rebate_threshold {
+            fee_bps = fee_bps.saturating_sub(config.maker_rebate_bps);
+        }
+    }
+
+    mul_div(amount_in, fee_bps as u64, BPS_DENOMINATOR as u64, false)
+}
+
+/// Fee the taker pays the treasury on top of a fill paying `amount_in`
+fn taker_fee(config: &Config, amount_in: u64) -> Result<u64> {
+    mul_div(
+        amount_in,
+        config.taker_fee_bps as u64,
+        BPS_DENOMINATOR as u64,
+        false,
+    )
+}
+
+/// Check the system program and, for handlers that take one, the rent
+/// sysvar passed to a create handler are the canonical accounts,
+/// independently of Anchor's own account checks
+fn check_system_accounts(system_program: &AccountInfo, rent: Option<&AccountInfo>) -> Result<()> {
+    require_keys_eq!(
+        system_program.key(),
+        system_program::ID,
+        ErrorCode::InvalidSysvar
+    );
+    if let Some(rent) = rent {
+        require_keys_eq!(
+            rent.key(),
+            anchor_lang::solana_program::sysvar::rent::ID,
+            ErrorCode::InvalidSysvar
+        );
+    }
+    Ok(())
+}
+
+/// Refuse fills outside the shared accept path while the admin has declared
+/// an emergency, as `settle_fill` does for offers
+fn check_no_emergency(config: &UncheckedAccount) -> Result<()> {
+    require!(
+        !load_config(config)?.is_some_and(|config| config.emergency),
+        ErrorCode::EmergencyDeclared
+    );
+    Ok(())
+}
+
+/// Reject makers without an approved `MakerStatus` while the config's
+/// maker allowlist is on
+fn check_maker_approved(
+    config: &UncheckedAccount,
+    maker_status: Option<&Account<MakerStatus>>,
+) -> Result<()> {
+    let Some(config) = load_config(config)? else {
+        return Ok(());
+    };
+    if !config.maker_allowlist {
+        return Ok(());
+    }
+
+    require!(
+        maker_status.is_some_and(|status| status.approved),
+        ErrorCode::MakerNotApproved
+    );
+    Ok(())
+}
+
+/// Reject offers in a mint without an `AllowedMint` entry while the config's
+/// mint allowlist is on; the entries' seeds already tie them to the mints
+fn check_mints_allowed(
+    config: &UncheckedAccount,
+    allowed_mint_offered: Option<&Account<AllowedMint>>,
+    allowed_mint_wanted: Option<&Account<AllowedMint>>,
+) -> Result<()> {
+    check_mint_allowed(config, allowed_mint_offered)?;
+    check_mint_allowed(config, allowed_mint_wanted)
+}
+
+/// `check_mints_allowed` for a single mint
+fn check_mint_allowed(
+    config: &UncheckedAccount,
+    allowed_mint: Option<&Account<AllowedMint>>,
+) -> Result<()> {
+    let Some(config) = load_config(config)? else {
+        return Ok(());
+    };
+    require!(
+        !config.mint_allowlist || allowed_mint.is_some(),
+        ErrorCode::MintNotAllowed
+    );
+    Ok(())
+}
+
+/// `check_mints_allowed` for mints whose `AllowedMint` entries come in
+/// `remaining_accounts`, such as a bundle's items or a multi-wanted offer's
+/// alternative quotes; the entries are only required while the list is on
+fn check_listed_mints_allowed<'a>(
+    config: &UncheckedAccount,
+    mints: impl ExactSizeIterator<Item = &'a Pubkey>,
+    allowed_mints: &[AccountInfo],
+    program_id: &Pubkey,
+) -> Result<()> {
+    let Some(config) = load_config(config)? else {
+        return Ok(());
+    };
+    if !config.mint_allowlist {
+        return Ok(());
+    }
+
+    require!(allowed_mints.len() == mints.len(), ErrorCode::MintNotAllowed);
+    for (mint, allowed_mint_info) in mints.zip(allowed_mints) {
+        let (allowed_mint_key, _) =
+            Pubkey::find_program_address(&[b"allowed_mint", mint.as_ref()], program_id);
+        require!(
+            allowed_mint_info.key() == allowed_mint_key
+                && *allowed_mint_info.owner == crate::ID
+                && AllowedMint::try_deserialize(&mut &allowed_mint_info.try_borrow_data()?[..])
+                    .is_ok(),
+            ErrorCode::MintNotAllowed
+        );
+    }
+    Ok(())
+}
+
+/// Reject a trade in a mint while the admin has it paused through
+/// `set_mint_paused`; a mint without a `MintStatus` was never paused
+fn check_mint_unpaused(mint_status_info: &AccountInfo) -> Result<()> {
+    if mint_status_info.data_is_empty() {
+        return Ok(());
+    }
+    require_keys_eq!(*mint_status_info.owner, crate::ID, ErrorCode::InvalidMint);
+    let data = mint_status_info.try_borrow_data()?;
+    let mint_status = MintStatus::try_deserialize(&mut &data[..])?;
+    require!(!mint_status.paused, ErrorCode::MintPaused);
+    Ok(())
+}
+
+/// `check_mint_unpaused` for both mints of a trade
+fn check_mints_unpaused(
+    mint_status_offered: &AccountInfo,
+    mint_status_wanted: &AccountInfo,
+) -> Result<()> {
+    check_mint_unpaused(mint_status_offered)?;
+    check_mint_unpaused(mint_status_wanted)
+}
+
+/// `check_mint_unpaused` for each item of a sell bundle, given the items'
+/// `MintStatus` PDAs in item order
+fn check_bundle_unpaused<'a>(
+    mints: impl Iterator<Item = &'a Pubkey>,
+    mint_statuses: &[AccountInfo],
+    program_id: &Pubkey,
+) -> Result<()> {
+    for (mint, mint_status_info) in mints.zip(mint_statuses) {
+        let (mint_status_key, _) =
+            Pubkey::find_program_address(&[b"mint_status", mint.as_ref()], program_id);
+        require_keys_eq!(mint_status_info.key(), mint_status_key, ErrorCode::InvalidBundle);
+        check_mint_unpaused(mint_status_info)?;
+    }
+    Ok(())
+}
+
+/// Create the `MintTvl` counter of `mint` on its first tracked escrow.
+/// Allocates and assigns in place of `create_account`, so lamports sent to
+/// the address beforehand cannot block the mint.
+fn init_mint_tvl<'info>(
+    mint_tvl_info: &AccountInfo<'info>,
+    bump: u8,
+    mint: Pubkey,
+    payer: &AccountInfo<'info>,
+    system_program: &Program<'info, System>,
+) -> Result<()> {
+    if !mint_tvl_info.data_is_empty() {
+        return Ok(());
+    }
+
+    let space = 8 + MintTvl::SIZE;
+    let shortfall = Rent::get()?
+        .minimum_balance(space)
+        .saturating_sub(mint_tvl_info.lamports());
+    let cpi_program = system_program.to_account_info();
+    if shortfall > 0 {
+        let cpi_accounts = system_program::Transfer {
+            from: payer.clone(),
+            to: mint_tvl_info.clone(),
+        };
+        system_program::transfer(CpiContext::new(cpi_program.clone(), cpi_accounts), shortfall)?;
+    }
+    let seeds = &[b"mint_tvl", mint.as_ref(), &[bump]];
+    let signer = &[&seeds[..]];
+    let cpi_accounts = system_program::Allocate {
+        account_to_allocate: mint_tvl_info.clone(),
+    };
+    system_program::allocate(
+        CpiContext::new_with_signer(cpi_program.clone(), cpi_accounts, signer),
+        space as u64,
+    )?;
+    let cpi_accounts = system_program::Assign {
+        account_to_assign: mint_tvl_info.clone(),
+    };
+    system_program::assign(
+        CpiContext::new_with_signer(cpi_program, cpi_accounts, signer),
+        &crate::ID,
+    )?;
+
+    let mint_tvl = MintTvl {
+        mint,
+        total_locked: 0,
+        bump,
+    };
+    mint_tvl.try_serialize(&mut &mut mint_tvl_info.try_borrow_mut_data()?[..])?;
+    Ok(())
+}
+
+/// Count `amount` of newly escrowed tokens in their mint's `MintTvl`,
+/// refusing escrow past the config's `max_mint_tvl`
+fn lock_mint_tvl(
+    mint_tvl_info: &AccountInfo,
+    config: &UncheckedAccount,
+    amount: u64,
+) -> Result<()> {
+    let mut data = mint_tvl_info.try_borrow_mut_data()?;
+    let mut mint_tvl = MintTvl::try_deserialize(&mut &data[..])?;
+    mint_tvl.total_locked = mint_tvl
+        .total_locked
+        .checked_add(amount)
+        .ok_or(ErrorCode::MathOverflow)?;
+    if let Some(config) = load_config(config)? {
+        require!(
+            config.max_mint_tvl == 0 || mint_tvl.total_locked <= config.max_mint_tvl,
+            ErrorCode::TvlCapExceeded
+        );
+    }
+    mint_tvl.try_serialize(&mut &mut data[..])?;
+    Ok(())
+}
+
+/// Take `amount` of escrow, paid out or returned, off its mint's `MintTvl`.
+/// Saturates rather than fail: escrow resized before every path kept the
+/// count could otherwise never be released.
+fn release_mint_tvl(mint_tvl_info: &AccountInfo, amount: u64) -> Result<()> {
+    let mut data = mint_tvl_info.try_borrow_mut_data()?;
+    let mut mint_tvl = MintTvl::try_deserialize(&mut &data[..])?;
+    mint_tvl.total_locked = mint_tvl.total_locked.saturating_sub(amount);
+    mint_tvl.try_serialize(&mut &mut data[..])?;
+    Ok(())
+}
+
+/// Charge the config's flat listing fee, if any, from `payer` to the
+/// treasury. The fee is kept even if the offer is later cancelled.
+fn collect_listing_fee<'info>(
+    config: &UncheckedAccount<'info>,
+    payer: &AccountInfo<'info>,
+    treasury: Option<&UncheckedAccount<'info>>,
+    system_program: &Program<'info, System>,
+) -> Result<()> {
+    let Some(config) = load_config(config)? else {
+        return Ok(());
+    };
+    if config.listing_fee_lamports == 0 {
+        return Ok(());
+    }
+
+    let treasury = treasury.ok_or(ErrorCode::MissingTreasuryAccount)?;
+    require_keys_eq!(treasury.key(), config.treasury, ErrorCode::Unauthorized);
+
+    let cpi_accounts = system_program::Transfer {
+        from: payer.clone(),
+        to: treasury.to_account_info(),
+    };
+    let cpi_program = system_program.to_account_info();
+    system_program::transfer(
+        CpiContext::new(cpi_program, cpi_accounts),
+        config.listing_fee_lamports,
+    )
+}
+
+/// Create the taker's vesting claim and vault PDAs for `accept_with_vesting`,
+/// returning their bumps. The claim is written once the fill went through.
+fn open_vesting_escrow(accounts: &AcceptOffer, program_id: &Pubkey) -> Result<(u8, u8)> {
+    let claim_info = accounts
+        .vesting_claim
+        .as_ref()
+        .ok_or(ErrorCode::MissingVestingAccount)?;
+    let vault_info = accounts
+        .vesting_vault
+        .as_ref()
+        .ok_or(ErrorCode::MissingVestingAccount)?;
+
+    let offer_key = accounts.offer.key();
+    let taker_key = accounts.taker.key();
+    let (claim_key, claim_bump) = Pubkey::find_program_address(
+        &[b"vesting_claim", offer_key.as_ref(), taker_key.as_ref()],
+        program_id,
+    );
+    require_keys_eq!(claim_info.key(), claim_key, ErrorCode::MissingVestingAccount);
+    let (vault_key, vault_bump) =
+        Pubkey::find_program_address(&[b"vesting_vault", claim_key.as_ref()], program_id);
+    require_keys_eq!(vault_info.key(), vault_key, ErrorCode::MissingVestingAccount);
+
+    let rent = Rent::get()?;
+    let space = 8 + VestingClaim::SIZE;
+    let seeds = &[
+        b"vesting_claim",
+        offer_key.as_ref(),
+        taker_key.as_ref(),
+        &[claim_bump],
+    ];
+    let signer = &[&seeds[..]];
+    let cpi_accounts = system_program::CreateAccount {
+        from: accounts.taker.to_account_info(),
+        to: claim_info.to_account_info(),
+    };
+    let cpi_program = accounts.system_program.to_account_info();
+    system_program::create_account(
+        CpiContext::new_with_signer(cpi_program, cpi_accounts, signer),
+        rent.minimum_balance(space),
+        space as u64,
+        program_id,
+    )?;
+
+    // The vault is a token account that is its own authority
+    let seeds = &[b"vesting_vault", claim_key.as_ref(), &[vault_bump]];
+    let signer = &[&seeds[..]];
+    let cpi_accounts = system_program::CreateAccount {
+        from: accounts.taker.to_account_info(),
+        to: vault_info.to_account_info(),
+    };
+    let cpi_program = accounts.system_program.to_account_info();
+    system_program::create_account(
+        CpiContext::new_with_signer(cpi_program, cpi_accounts, signer),
+        rent.minimum_balance(TokenAccount::LEN),
+        TokenAccount::LEN as u64,
+        &accounts.token_program.key(),
+    )?;
+    let cpi_accounts = InitializeAccount3 {
+        account: vault_info.to_account_info(),
+        mint: accounts.mint_offered.to_account_info(),
+        authority: vault_info.to_account_info(),
+    };
+    let cpi_program = accounts.token_program.to_account_info();
+    token::initialize_account3(CpiContext::new(cpi_program, cpi_accounts))?;
+
+    Ok((claim_bump, vault_bump))
+}
+
+/// Charge the taker the flat fee-token fee of `amount` in `fee_mint`, paid
+/// from `taker_fee_token_account` to the treasury's account of that mint
+/// Pay `amount` of the wanted mint from the taker to `to`. Token-2022 mints
+/// move through `wanted_token_program`; with `gross_up`, the taker also pays
+/// the mint's transfer fee, returned, so `to` nets `amount`.
+fn pay_wanted<'info>(
+    accounts: &AcceptOffer<'info>,
+    to: AccountInfo<'info>,
+    amount: u64,
+    gross_up: bool,
+) -> Result<u64> {
+    let mint_info = accounts.mint_wanted.to_account_info();
+    if *mint_info.owner != spl_token_2022::ID {
+        let cpi_accounts = Transfer {
+            from: accounts.taker_token_account_offered.to_account_info(),
+            to,
+            authority: accounts.taker.to_account_info(),
+        };
+        let cpi_program = accounts.token_program.to_account_info();
+        token::transfer(CpiContext::new(cpi_program, cpi_accounts), amount)?;
+        return Ok(0);
+    }
+
+    let wanted_token_program = accounts
+        .wanted_token_program
+        .as_ref()
+        .ok_or(ErrorCode::MissingWantedTokenProgram)?;
+    require_keys_eq!(
+        wanted_token_program.key(),
+        spl_token_2022::ID,
+        ErrorCode::MissingWantedTokenProgram
+    );
+    let (decimals, transfer_fee) = {
+        let data = mint_info.try_borrow_data()?;
+        let mint = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&data)?;
+        let transfer_fee = match mint.get_extension::<TransferFeeConfig>() {
+            Ok(fee_config) if gross_up => fee_config
+                .calculate_inverse_epoch_fee(Clock::get()?.epoch, amount)
+                .ok_or(ErrorCode::MathOverflow)?,
+            _ => 0,
+        };
+        (mint.base.decimals, transfer_fee)
+    };
+
+    let cpi_accounts = TransferChecked {
+        from: accounts.taker_token_account_offered.to_account_info(),
+        mint: mint_info,
+        to,
+        authority: accounts.taker.to_account_info(),
+    };
+    let cpi_program = wanted_token_program.to_account_info();
+    token_interface::transfer_checked(
+        CpiContext::new(cpi_program, cpi_accounts),
+        amount.checked_add(transfer_fee).ok_or(ErrorCode::MathOverflow)?,
+        decimals,
+    )?;
+    Ok(transfer_fee)
+}
+
+fn collect_fee_token(
+    accounts: &AcceptOffer,
+    fee_mint: Pubkey,
+    amount: u64,
+    treasury: Pubkey,
+) -> Result<()> {
+    if amount == 0 {
+        return Ok(());
+    }
+
+    let taker_fee_token_account = accounts
+        .taker_fee_token_account
+        .as_ref()
+        .ok_or(ErrorCode::MissingFeeTokenAccount)?;
+    require_keys_eq!(taker_fee_token_account.mint, fee_mint, ErrorCode::InvalidMint);
+    require_keys_eq!(
+        taker_fee_token_account.owner,
+        accounts.taker.key(),
+        ErrorCode::Unauthorized
+    );
+    require!(
+        taker_fee_token_account.amount >= amount,
+        ErrorCode::InsufficientBalance
+    );
+
+    let treasury_token_account = accounts
+        .treasury_token_account
+        .as_ref()
+        .ok_or(ErrorCode::MissingTreasuryAccount)?;
+    require_keys_eq!(treasury_token_account.mint, fee_mint, ErrorCode::InvalidMint);
+    require_keys_eq!(treasury_token_account.owner, treasury, ErrorCode::Unauthorized);
+
+    let cpi_accounts = Transfer {
+        from: taker_fee_token_account.to_account_info(),
+        to: treasury_token_account.to_account_info(),
+        authority: accounts.taker.to_account_info(),
+    };
+    let cpi_program = accounts.token_program.to_account_info();
+    token::transfer(CpiContext::new(cpi_program, cpi_accounts), amount)
+}
+
+/// Fee for pushing an offer's expiry back by `extension_secs`, at the
+/// config's daily rate pro rata, rounded up
+fn extension_fee(config: &Config, extension_secs: i64) -> Result<u64> {
+    mul_div(
+        u64::try_from(extension_secs).map_err(|_| error!(ErrorCode::InvalidExpiry))?,
+        config.extension_fee_lamports_per_day,
+        SECS_PER_DAY as u64,
+        true,
+    )
+}
+
+/// Log `memo` through the SPL Memo program, signed by the taker so it is
+/// attributed to them
+fn attach_memo(accounts: &AcceptOffer, memo: &str) -> Result<()> {
+    let memo_program = accounts
+        .memo_program
+        .as_ref()
+        .ok_or(ErrorCode::MissingMemoProgram)?;
+
+    memo::build_memo(
+        CpiContext::new(memo_program.to_account_info(), BuildMemo {})
+            .with_remaining_accounts(vec![accounts.taker.to_account_info()]),
+        memo.as_bytes(),
+    )
+}
+
+/// Referrer's and royalty recipient's cuts of a fill paying `amount_in`
+fn offer_cuts(offer: &Offer, amount_in: u64) -> Result<(u64, u64)> {
+    let cut = |recipient: Option<Pubkey>, bps: u16| match recipient {
+        Some(_) if bps > 0 => mul_div(amount_in, bps.into(), BPS_DENOMINATOR.into(), false),
+        _ => Ok(0),
+    };
+    Ok((
+        cut(offer.referrer, offer.referrer_bps)?,
+        cut(offer.royalty_recipient, offer.royalty_bps)?,
+    ))
+}
+
+/// What the maker keeps of `amount_in` after every cut
+fn maker_proceeds(amount_in: u64, fee: u64, referral: u64, royalty: u64) -> Result<u64> {
+    // The fee may have been raised since listing, past what the cuts leave
+    amount_in
+        .checked_sub(fee)
+        .and_then(|proceeds| proceeds.checked_sub(referral))
+        .and_then(|proceeds| proceeds.checked_sub(royalty))
+        .ok_or_else(|| error!(ErrorCode::FeesExceedTotal))
+}
+
+fn set_offer_paused(offer: &mut Account<Offer>, paused: bool) -> Result<()> {
+    require!(!offer.is_held(Clock::get()?.unix_timestamp), ErrorCode::OfferReserved);
+    offer.is_paused = paused;
+
+    emit!(OfferPauseChanged {
+        offer: offer.key(),
+        maker: offer.maker,
+        offer_id: offer.offer_id,
+        paused,
+    });
+
+    msg!(
+        "Offer {} {}",
+        offer.offer_id,
+        if paused { "paused" } else { "resumed" }
+    );
+
+    Ok(())
+}
+
+// ============================================================================
+// Account Structures
+// ============================================================================
+
+#[derive(Accounts)]
+pub struct InitializeConfig<'info> {
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + Config::SIZE,
+        seeds = [b"config"],
+        bump
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateConfig<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeUser<'info> {
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + UserProfile::SIZE,
+        seeds = [b"user_profile", authority.key().as_ref()],
+        bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeUsersBatch<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+
+    /// Pays the rent of every profile created
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CloseUserProfile<'info> {
+    #[account(
+        mut,
+        seeds = [b"user_profile", authority.key().as_ref()],
+        bump,
+        constraint = user_profile.open_offers == 0 @ ErrorCode::OpenOffersRemaining,
+        close = authority,
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SkipOfferId<'info> {
+    #[account(
+        mut,
+        seeds = [b"user_profile", authority.key().as_ref()],
+        bump,
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    /// CHECK: Listing of any kind at the counter's next ID, matched against
+    /// `COUNTER_SEEDED_PREFIXES` by the handler; only that it exists matters
+    #[account(constraint = !listing.data_is_empty() @ ErrorCode::InvalidOfferId)]
+    pub listing: UncheckedAccount<'info>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CloseConfig<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ ErrorCode::Unauthorized,
+        close = admin,
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptAdmin<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+
+    pub new_admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AddAllowedMint<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + AllowedMint::SIZE,
+        seeds = [b"allowed_mint", mint.key().as_ref()],
+        bump
+    )]
+    pub allowed_mint: Account<'info, AllowedMint>,
+
+    pub mint: Account<'info, Mint>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RemoveAllowedMint<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [b"allowed_mint", allowed_mint.mint.as_ref()],
+        bump = allowed_mint.bump,
+        close = admin,
+    )]
+    pub allowed_mint: Account<'info, AllowedMint>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ApproveMaker<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        init_if_needed,
+        payer = admin,
+        space = 8 + MakerStatus::SIZE,
+        seeds = [b"maker_status", maker.key().as_ref()],
+        bump
+    )]
+    pub maker_status: Account<'info, MakerStatus>,
+
+    /// CHECK: Only used as a seed for the maker status PDA
+    pub maker: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetMintPaused<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        init_if_needed,
+        payer = admin,
+        space = 8 + MintStatus::SIZE,
+        seeds = [b"mint_status", mint.key().as_ref()],
+        bump
+    )]
+    pub mint_status: Account<'info, MintStatus>,
+
+    pub mint: Account<'info, Mint>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(amount_offered: u64, _amount_wanted: u64, options: OfferOptions)]
+pub struct CreateOffer<'info> {
+    #[account(
+        init_if_needed,
+        payer = maker,
+        space = 8 + UserProfile::SIZE,
+        seeds = [b"user_profile", maker.key().as_ref()],
+        bump,
+        // Checked here, ahead of the offer's `init`, so an ID still taken
+        // by an existing offer fails with a clear error
+        constraint = offer.data_is_empty() @ ErrorCode::SlotInUse,
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(
+        init,
+        payer = maker,
+        space = 8 + Offer::space(
+            0,
+            options.blocked_takers.len(),
+            options.fill_queue_len.into(),
+            options.max_acceptors.into(),
+        ),
+        seeds = [
+            b"offer",
+            maker.key().as_ref(),
+            &user_profile.offer_count.to_le_bytes(),
+        ],
+        bump
+    )]
+    pub offer: Account<'info, Offer>,
+
+    #[account(
+        init_if_needed,
+        payer = maker,
+        seeds = [
+            b"vault",
+            options.vault_key_seed(offer.key(), maker.key()).as_ref(),
+            &options.vault_id_seed(&user_profile.offer_count.to_le_bytes()),
+            mint_offered.key().as_ref(),
+        ],
+        bump,
+        token::mint = mint_offered,
+        token::authority = vault,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    /// Funds the escrow: the maker's own account or, for custodial setups,
+    /// one that has approved the maker as delegate for `amount_offered`
+    #[account(
+        mut,
+        constraint = maker_token_account.mint == mint_offered.key() @ ErrorCode::InvalidMint,
+        constraint = maker_token_account.owner == maker.key()
+            || delegated_allowance(&maker_token_account, &maker.key()) >= amount_offered
+            @ ErrorCode::Unauthorized,
+    )]
+    pub maker_token_account: Account<'info, TokenAccount>,
+
+    pub mint_offered: Account<'info, Mint>,
+    /// SPL Token or Token-2022 mint; see `Config::gross_up_transfer_fees`
+    /// for who bears a transfer fee
+    pub mint_wanted: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(mut)]
+    pub maker: Signer<'info>,
+
+    /// CHECK: Protocol config PDA; may not be initialized yet, see `load_config`
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+
+    /// CHECK: Pause status PDAs of both mints; may not exist, see
+    /// `check_mints_unpaused`
+    #[account(seeds = [b"mint_status", mint_offered.key().as_ref()], bump)]
+    pub mint_status_offered: UncheckedAccount<'info>,
+    /// CHECK: See `mint_status_offered`
+    #[account(seeds = [b"mint_status", mint_wanted.key().as_ref()], bump)]
+    pub mint_status_wanted: UncheckedAccount<'info>,
+
+    /// CHECK: `MintTvl` of the offered mint, created on its first counted
+    /// offer by `init_mint_tvl`
+    #[account(mut, seeds = [b"mint_tvl", mint_offered.key().as_ref()], bump)]
+    pub mint_tvl: UncheckedAccount<'info>,
+
+    /// CHECK: Treasury wallet, matched against `config.treasury` by
+    /// `collect_listing_fee`; required when a listing fee applies
+    #[account(mut)]
+    pub treasury: Option<UncheckedAccount<'info>>,
+
+    /// Maker's allowlist entry; required while `config.maker_allowlist` is on
+    #[account(
+        seeds = [b"maker_status", maker.key().as_ref()],
+        bump = maker_status.bump,
+    )]
+    pub maker_status: Option<Account<'info, MakerStatus>>,
+
+    /// Allowlist entries of both mints; required while
+    /// `config.mint_allowlist` is on
+    #[account(
+        seeds = [b"allowed_mint", mint_offered.key().as_ref()],
+        bump = allowed_mint_offered.bump,
+    )]
+    pub allowed_mint_offered: Option<Account<'info, AllowedMint>>,
+    #[account(
+        seeds = [b"allowed_mint", mint_wanted.key().as_ref()],
+        bump = allowed_mint_wanted.bump,
+    )]
+    pub allowed_mint_wanted: Option<Account<'info, AllowedMint>>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+#[instruction(_amount_offered: u64, _amount_wanted: u64, options: OfferOptions)]
+pub struct CreatePdaOffer<'info> {
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + UserProfile::SIZE,
+        seeds = [b"user_profile", maker.key().as_ref()],
+        bump,
+        // Ahead of `offer`'s init, see `CreateOffer`
+        constraint = offer.data_is_empty() @ ErrorCode::SlotInUse,
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + Offer::space(
+            0,
+            options.blocked_takers.len(),
+            options.fill_queue_len.into(),
+            options.max_acceptors.into(),
+        ),
+        seeds = [
+            b"offer",
+            maker.key().as_ref(),
+            &user_profile.offer_count.to_le_bytes(),
+        ],
+        bump
+    )]
+    pub offer: Account<'info, Offer>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        seeds = [
+            b"vault",
+            options.vault_key_seed(offer.key(), maker.key()).as_ref(),
+            &options.vault_id_seed(&user_profile.offer_count.to_le_bytes()),
+            mint_offered.key().as_ref(),
+        ],
+        bump,
+        token::mint = mint_offered,
+        token::authority = vault,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = maker_token_account.mint == mint_offered.key() @ ErrorCode::InvalidMint,
+        constraint = maker_token_account.owner == maker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub maker_token_account: Account<'info, TokenAccount>,
+
+    pub mint_offered: Account<'info, Mint>,
+    pub mint_wanted: InterfaceAccount<'info, token_interface::Mint>,
+
+    /// Maker, typically a PDA signing through its controlling program
+    pub maker: Signer<'info>,
+
+    /// Funds the offer, vault and profile rent and any listing fee
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// CHECK: Protocol config PDA; may not be initialized yet, see `load_config`
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+
+    /// CHECK: Pause status PDAs of both mints; may not exist, see
+    /// `check_mints_unpaused`
+    #[account(seeds = [b"mint_status", mint_offered.key().as_ref()], bump)]
+    pub mint_status_offered: UncheckedAccount<'info>,
+    /// CHECK: See `mint_status_offered`
+    #[account(seeds = [b"mint_status", mint_wanted.key().as_ref()], bump)]
+    pub mint_status_wanted: UncheckedAccount<'info>,
+
+    /// CHECK: `MintTvl` of the offered mint, created on its first counted
+    /// offer by `init_mint_tvl`
+    #[account(mut, seeds = [b"mint_tvl", mint_offered.key().as_ref()], bump)]
+    pub mint_tvl: UncheckedAccount<'info>,
+
+    /// CHECK: Treasury wallet, matched against `config.treasury` by
+    /// `collect_listing_fee`; required when a listing fee applies
+    #[account(mut)]
+    pub treasury: Option<UncheckedAccount<'info>>,
+
+    /// Maker's allowlist entry; required while `config.maker_allowlist` is on
+    #[account(
+        seeds = [b"maker_status", maker.key().as_ref()],
+        bump = maker_status.bump,
+    )]
+    pub maker_status: Option<Account<'info, MakerStatus>>,
+
+    /// Allowlist entries of both mints; required while
+    /// `config.mint_allowlist` is on
+    #[account(
+        seeds = [b"allowed_mint", mint_offered.key().as_ref()],
+        bump = allowed_mint_offered.bump,
+    )]
+    pub allowed_mint_offered: Option<Account<'info, AllowedMint>>,
+    #[account(
+        seeds = [b"allowed_mint", mint_wanted.key().as_ref()],
+        bump = allowed_mint_wanted.bump,
+    )]
+    pub allowed_mint_wanted: Option<Account<'info, AllowedMint>>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+#[instruction(quotes: Vec<WantedQuote>, _amount_offered: u64, options: OfferOptions)]
+pub struct CreateOfferMultiWanted<'info> {
+    #[account(
+        init_if_needed,
+        payer = maker,
+        space = 8 + UserProfile::SIZE,
+        seeds = [b"user_profile", maker.key().as_ref()],
+        bump,
+        // Ahead of `offer`'s init, see `CreateOffer`
+        constraint = offer.data_is_empty() @ ErrorCode::SlotInUse,
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(
+        init,
+        payer = maker,
+        space = 8 + Offer::space(
+            quotes.len().saturating_sub(1),
+            options.blocked_takers.len(),
+            options.fill_queue_len.into(),
+            options.max_acceptors.into(),
+        ),
+        seeds = [
+            b"offer",
+            maker.key().as_ref(),
+            &user_profile.offer_count.to_le_bytes(),
+        ],
+        bump
+    )]
+    pub offer: Account<'info, Offer>,
+
+    #[account(
+        init_if_needed,
+        payer = maker,
+        seeds = [
+            b"vault",
+            options.vault_key_seed(offer.key(), maker.key()).as_ref(),
+            &options.vault_id_seed(&user_profile.offer_count.to_le_bytes()),
+            mint_offered.key().as_ref(),
+        ],
+        bump,
+        token::mint = mint_offered,
+        token::authority = vault,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = maker_token_account.mint == mint_offered.key() @ ErrorCode::InvalidMint,
+        constraint = maker_token_account.owner == maker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub maker_token_account: Account<'info, TokenAccount>,
+
+    pub mint_offered: Account<'info, Mint>,
+    pub mint_wanted: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(mut)]
+    pub maker: Signer<'info>,
+
+    /// CHECK: Protocol config PDA; may not be initialized yet, see `load_config`
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+
+    /// CHECK: Pause status PDAs of both mints; may not exist, see
+    /// `check_mints_unpaused`
+    #[account(seeds = [b"mint_status", mint_offered.key().as_ref()], bump)]
+    pub mint_status_offered: UncheckedAccount<'info>,
+    /// CHECK: See `mint_status_offered`
+    #[account(seeds = [b"mint_status", mint_wanted.key().as_ref()], bump)]
+    pub mint_status_wanted: UncheckedAccount<'info>,
+
+    /// CHECK: `MintTvl` of the offered mint, created on its first counted
+    /// offer by `init_mint_tvl`
+    #[account(mut, seeds = [b"mint_tvl", mint_offered.key().as_ref()], bump)]
+    pub mint_tvl: UncheckedAccount<'info>,
+
+    /// CHECK: Treasury wallet, matched against `config.treasury` by
+    /// `collect_listing_fee`; required when a listing fee applies
+    #[account(mut)]
+    pub treasury: Option<UncheckedAccount<'info>>,
+
+    /// Maker's allowlist entry; required while `config.maker_allowlist` is on
+    #[account(
+        seeds = [b"maker_status", maker.key().as_ref()],
+        bump = maker_status.bump,
+    )]
+    pub maker_status: Option<Account<'info, MakerStatus>>,
+
+    /// Allowlist entries of both mints; required while
+    /// `config.mint_allowlist` is on
+    #[account(
+        seeds = [b"allowed_mint", mint_offered.key().as_ref()],
+        bump = allowed_mint_offered.bump,
+    )]
+    pub allowed_mint_offered: Option<Account<'info, AllowedMint>>,
+    #[account(
+        seeds = [b"allowed_mint", mint_wanted.key().as_ref()],
+        bump = allowed_mint_wanted.bump,
+    )]
+    pub allowed_mint_wanted: Option<Account<'info, AllowedMint>>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+#[instruction(_amount_offered: u64, _multiplier_bps: u16, options: OfferOptions)]
+pub struct CreateOracleOffer<'info> {
+    #[account(
+        init_if_needed,
+        payer = maker,
+        space = 8 + UserProfile::SIZE,
+        seeds = [b"user_profile", maker.key().as_ref()],
+        bump,
+        // Ahead of `offer`'s init, see `CreateOffer`
+        constraint = offer.data_is_empty() @ ErrorCode::SlotInUse,
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(
+        init,
+        payer = maker,
+        space = 8 + Offer::space(
+            0,
+            options.blocked_takers.len(),
+            options.fill_queue_len.into(),
+            options.max_acceptors.into(),
+        ),
+        seeds = [
+            b"offer",
+            maker.key().as_ref(),
+            &user_profile.offer_count.to_le_bytes(),
+        ],
+        bump
+    )]
+    pub offer: Account<'info, Offer>,
+
+    #[account(
+        init_if_needed,
+        payer = maker,
+        seeds = [
+            b"vault",
+            options.vault_key_seed(offer.key(), maker.key()).as_ref(),
+            &options.vault_id_seed(&user_profile.offer_count.to_le_bytes()),
+            mint_offered.key().as_ref(),
+        ],
+        bump,
+        token::mint = mint_offered,
+        token::authority = vault,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = maker_token_account.mint == mint_offered.key() @ ErrorCode::InvalidMint,
+        constraint = maker_token_account.owner == maker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub maker_token_account: Account<'info, TokenAccount>,
+
+    pub mint_offered: Account<'info, Mint>,
+    pub mint_wanted: InterfaceAccount<'info, token_interface::Mint>,
+
+    /// CHECK: Pyth price account, validated by `load_pyth_price`
+    pub price_feed: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub maker: Signer<'info>,
+
+    /// CHECK: Protocol config PDA; may not be initialized yet, see `load_config`
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+
+    /// CHECK: Pause status PDAs of both mints; may not exist, see
+    /// `check_mints_unpaused`
+    #[account(seeds = [b"mint_status", mint_offered.key().as_ref()], bump)]
+    pub mint_status_offered: UncheckedAccount<'info>,
+    /// CHECK: See `mint_status_offered`
+    #[account(seeds = [b"mint_status", mint_wanted.key().as_ref()], bump)]
+    pub mint_status_wanted: UncheckedAccount<'info>,
+
+    /// CHECK: `MintTvl` of the offered mint, created on its first counted
+    /// offer by `init_mint_tvl`
+    #[account(mut, seeds = [b"mint_tvl", mint_offered.key().as_ref()], bump)]
+    pub mint_tvl: UncheckedAccount<'info>,
+
+    /// CHECK: Treasury wallet, matched against `config.treasury` by
+    /// `collect_listing_fee`; required when a listing fee applies
+    #[account(mut)]
+    pub treasury: Option<UncheckedAccount<'info>>,
+
+    /// Maker's allowlist entry; required while `config.maker_allowlist` is on
+    #[account(
+        seeds = [b"maker_status", maker.key().as_ref()],
+        bump = maker_status.bump,
+    )]
+    pub maker_status: Option<Account<'info, MakerStatus>>,
+
+    /// Allowlist entries of both mints; required while
+    /// `config.mint_allowlist` is on
+    #[account(
+        seeds = [b"allowed_mint", mint_offered.key().as_ref()],
+        bump = allowed_mint_offered.bump,
+    )]
+    pub allowed_mint_offered: Option<Account<'info, AllowedMint>>,
+    #[account(
+        seeds = [b"allowed_mint", mint_wanted.key().as_ref()],
+        bump = allowed_mint_wanted.bump,
+    )]
+    pub allowed_mint_wanted: Option<Account<'info, AllowedMint>>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+#[instruction(_amount_offered: u64, _multiplier_bps: u16, options: OfferOptions)]
+pub struct CreateIndexOffer<'info> {
+    #[account(
+        init_if_needed,
+        payer = maker,
+        space = 8 + UserProfile::SIZE,
+        seeds = [b"user_profile", maker.key().as_ref()],
+        bump,
+        // Ahead of `offer`'s init, see `CreateOffer`
+        constraint = offer.data_is_empty() @ ErrorCode::SlotInUse,
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(
+        init,
+        payer = maker,
+        space = 8 + Offer::space(
+            0,
+            options.blocked_takers.len(),
+            options.fill_queue_len.into(),
+            options.max_acceptors.into(),
+        ),
+        seeds = [
+            b"offer",
+            maker.key().as_ref(),
+            &user_profile.offer_count.to_le_bytes(),
+        ],
+        bump
+    )]
+    pub offer: Account<'info, Offer>,
+
+    #[account(
+        init_if_needed,
+        payer = maker,
+        seeds = [
+            b"vault",
+            options.vault_key_seed(offer.key(), maker.key()).as_ref(),
+            &options.vault_id_seed(&user_profile.offer_count.to_le_bytes()),
+            mint_offered.key().as_ref(),
+        ],
+        bump,
+        token::mint = mint_offered,
+        token::authority = vault,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = maker_token_account.mint == mint_offered.key() @ ErrorCode::InvalidMint,
+        constraint = maker_token_account.owner == maker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub maker_token_account: Account<'info, TokenAccount>,
+
+    pub mint_offered: Account<'info, Mint>,
+    pub mint_wanted: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(mut)]
+    pub maker: Signer<'info>,
+
+    /// CHECK: Protocol config PDA; may not be initialized yet, see `load_config`
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+
+    /// CHECK: Pause status PDAs of both mints; may not exist, see
+    /// `check_mints_unpaused`
+    #[account(seeds = [b"mint_status", mint_offered.key().as_ref()], bump)]
+    pub mint_status_offered: UncheckedAccount<'info>,
+    /// CHECK: See `mint_status_offered`
+    #[account(seeds = [b"mint_status", mint_wanted.key().as_ref()], bump)]
+    pub mint_status_wanted: UncheckedAccount<'info>,
+
+    /// CHECK: `MintTvl` of the offered mint, created on its first counted
+    /// offer by `init_mint_tvl`
+    #[account(mut, seeds = [b"mint_tvl", mint_offered.key().as_ref()], bump)]
+    pub mint_tvl: UncheckedAccount<'info>,
+
+    /// CHECK: Treasury wallet, matched against `config.treasury` by
+    /// `collect_listing_fee`; required when a listing fee applies
+    #[account(mut)]
+    pub treasury: Option<UncheckedAccount<'info>>,
+
+    /// Maker's allowlist entry; required while `config.maker_allowlist` is on
+    #[account(
+        seeds = [b"maker_status", maker.key().as_ref()],
+        bump = maker_status.bump,
+    )]
+    pub maker_status: Option<Account<'info, MakerStatus>>,
+
+    /// Allowlist entries of both mints; required while
+    /// `config.mint_allowlist` is on
+    #[account(
+        seeds = [b"allowed_mint", mint_offered.key().as_ref()],
+        bump = allowed_mint_offered.bump,
+    )]
+    pub allowed_mint_offered: Option<Account<'info, AllowedMint>>,
+    #[account(
+        seeds = [b"allowed_mint", mint_wanted.key().as_ref()],
+        bump = allowed_mint_wanted.bump,
+    )]
+    pub allowed_mint_wanted: Option<Account<'info, AllowedMint>>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+#[instruction(_amount_offered: u64, _spread_bps: u16, options: OfferOptions)]
+pub struct CreateAmmOffer<'info> {
+    #[account(
+        init_if_needed,
+        payer = maker,
+        space = 8 + UserProfile::SIZE,
+        seeds = [b"user_profile", maker.key().as_ref()],
+        bump,
+        // Ahead of `offer`'s init, see `CreateOffer`
+        constraint = offer.data_is_empty() @ ErrorCode::SlotInUse,
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(
+        init,
+        payer = maker,
+        space = 8 + Offer::space(
+            0,
+            options.blocked_takers.len(),
+            options.fill_queue_len.into(),
+            options.max_acceptors.into(),
+        ),
+        seeds = [
+            b"offer",
+            maker.key().as_ref(),
+            &user_profile.offer_count.to_le_bytes(),
+        ],
+        bump
+    )]
+    pub offer: Account<'info, Offer>,
+
+    #[account(
+        init_if_needed,
+        payer = maker,
+        seeds = [
+            b"vault",
+            options.vault_key_seed(offer.key(), maker.key()).as_ref(),
+            &options.vault_id_seed(&user_profile.offer_count.to_le_bytes()),
+            mint_offered.key().as_ref(),
+        ],
+        bump,
+        token::mint = mint_offered,
+        token::authority = vault,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = maker_token_account.mint == mint_offered.key() @ ErrorCode::InvalidMint,
+        constraint = maker_token_account.owner == maker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub maker_token_account: Account<'info, TokenAccount>,
+
+    pub mint_offered: Account<'info, Mint>,
+    pub mint_wanted: InterfaceAccount<'info, token_interface::Mint>,
+
+    /// CHECK: Constant-product pool, validated by `load_pool_reserves`
+    pub amm_pool: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub maker: Signer<'info>,
+
+    /// CHECK: Protocol config PDA; may not be initialized yet, see `load_config`
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+
+    /// CHECK: Pause status PDAs of both mints; may not exist, see
+    /// `check_mints_unpaused`
+    #[account(seeds = [b"mint_status", mint_offered.key().as_ref()], bump)]
+    pub mint_status_offered: UncheckedAccount<'info>,
+    /// CHECK: See `mint_status_offered`
+    #[account(seeds = [b"mint_status", mint_wanted.key().as_ref()], bump)]
+    pub mint_status_wanted: UncheckedAccount<'info>,
+
+    /// CHECK: `MintTvl` of the offered mint, created on its first counted
+    /// offer by `init_mint_tvl`
+    #[account(mut, seeds = [b"mint_tvl", mint_offered.key().as_ref()], bump)]
+    pub mint_tvl: UncheckedAccount<'info>,
+
+    /// CHECK: Treasury wallet, matched against `config.treasury` by
+    /// `collect_listing_fee`; required when a listing fee applies
+    #[account(mut)]
+    pub treasury: Option<UncheckedAccount<'info>>,
+
+    /// Maker's allowlist entry; required while `config.maker_allowlist` is on
+    #[account(
+        seeds = [b"maker_status", maker.key().as_ref()],
+        bump = maker_status.bump,
+    )]
+    pub maker_status: Option<Account<'info, MakerStatus>>,
+
+    /// Allowlist entries of both mints; required while
+    /// `config.mint_allowlist` is on
+    #[account(
+        seeds = [b"allowed_mint", mint_offered.key().as_ref()],
+        bump = allowed_mint_offered.bump,
+    )]
+    pub allowed_mint_offered: Option<Account<'info, AllowedMint>>,
+    #[account(
+        seeds = [b"allowed_mint", mint_wanted.key().as_ref()],
+        bump = allowed_mint_wanted.bump,
+    )]
+    pub allowed_mint_wanted: Option<Account<'info, AllowedMint>>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+#[instruction(nonce: [u8; 16], _amount_offered: u64, _amount_wanted: u64, options: OfferOptions)]
+pub struct CreateOfferWithNonce<'info> {
+    #[account(
+        init_if_needed,
+        payer = maker,
+        space = 8 + UserProfile::SIZE,
+        seeds = [b"user_profile", maker.key().as_ref()],
+        bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(
+        init,
+        payer = maker,
+        space = 8 + Offer::space(
+            0,
+            options.blocked_takers.len(),
+            options.fill_queue_len.into(),
+            options.max_acceptors.into(),
+        ),
+        seeds = [
+            b"offer",
+            maker.key().as_ref(),
+            nonce.as_ref(),
+        ],
+        bump
+    )]
+    pub offer: Account<'info, Offer>,
+
+    #[account(
+        init_if_needed,
+        payer = maker,
+        seeds = [
+            b"vault",
+            options.vault_key_seed(offer.key(), maker.key()).as_ref(),
+            &options.vault_id_seed(nonce.as_ref()),
+            mint_offered.key().as_ref(),
+        ],
+        bump,
+        token::mint = mint_offered,
+        token::authority = vault,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = maker_token_account.mint == mint_offered.key() @ ErrorCode::InvalidMint,
+        constraint = maker_token_account.owner == maker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub maker_token_account: Account<'info, TokenAccount>,
+
+    pub mint_offered: Account<'info, Mint>,
+    pub mint_wanted: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(mut)]
+    pub maker: Signer<'info>,
+
+    /// CHECK: Protocol config PDA; may not be initialized yet, see `load_config`
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+
+    /// CHECK: Pause status PDAs of both mints; may not exist, see
+    /// `check_mints_unpaused`
+    #[account(seeds = [b"mint_status", mint_offered.key().as_ref()], bump)]
+    pub mint_status_offered: UncheckedAccount<'info>,
+    /// CHECK: See `mint_status_offered`
+    #[account(seeds = [b"mint_status", mint_wanted.key().as_ref()], bump)]
+    pub mint_status_wanted: UncheckedAccount<'info>,
+
+    /// CHECK: `MintTvl` of the offered mint, created on its first counted
+    /// offer by `init_mint_tvl`
+    #[account(mut, seeds = [b"mint_tvl", mint_offered.key().as_ref()], bump)]
+    pub mint_tvl: UncheckedAccount<'info>,
+
+    /// CHECK: Treasury wallet, matched against `config.treasury` by
+    /// `collect_listing_fee`; required when a listing fee applies
+    #[account(mut)]
+    pub treasury: Option<UncheckedAccount<'info>>,
+
+    /// Maker's allowlist entry; required while `config.maker_allowlist` is on
+    #[account(
+        seeds = [b"maker_status", maker.key().as_ref()],
+        bump = maker_status.bump,
+    )]
+    pub maker_status: Option<Account<'info, MakerStatus>>,
+
+    /// Allowlist entries of both mints; required while
+    /// `config.mint_allowlist` is on
+    #[account(
+        seeds = [b"allowed_mint", mint_offered.key().as_ref()],
+        bump = allowed_mint_offered.bump,
+    )]
+    pub allowed_mint_offered: Option<Account<'info, AllowedMint>>,
+    #[account(
+        seeds = [b"allowed_mint", mint_wanted.key().as_ref()],
+        bump = allowed_mint_wanted.bump,
+    )]
+    pub allowed_mint_wanted: Option<Account<'info, AllowedMint>>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer_id: u64)]
+pub struct AcceptOffer<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"offer",
+            offer.maker.as_ref(),
+            &offer.id_seed(),
+        ],
+        bump = offer.bump,
+        constraint = offer.offer_id == offer_id @ ErrorCode::InvalidOfferId,
+        constraint = offer.current_owner == maker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub offer: Account<'info, Offer>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"vault",
+            offer.vault_key_seed(offer.key()).as_ref(),
+            &offer.vault_id_seed(),
+            offer.mint_offered.as_ref(),
+        ],
+        bump = offer.vault_bump,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    /// CHECK: Offer owner, receives the rent refund; matched against
+    /// `offer.current_owner`
+    #[account(mut)]
+    pub maker: UncheckedAccount<'info>,
+
+    /// Owner's profile, whose open-offer slot the offer frees
+    #[account(
+        mut,
+        seeds = [b"user_profile", maker.key().as_ref()],
+        bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(
+        mut,
+        constraint = maker_token_account_wanted.mint == mint_wanted.key()
+            @ ErrorCode::WrongWantedMint,
+        constraint = offer.maker_receive_account.map_or(
+            maker_token_account_wanted.owner == maker.key(),
+            |receive_account| maker_token_account_wanted.key() == receive_account,
+        ) @ ErrorCode::Unauthorized,
+    )]
+    pub maker_token_account_wanted: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut)]
+    pub taker: Signer<'info>,
+
+    /// CHECK: Only its key is used, matched against `offer.mint_offered`,
+    /// which was checked to be a mint at creation; skips deserializing it
+    #[account(constraint = mint_offered.key() == offer.mint_offered @ ErrorCode::WrongOfferedMint)]
+    pub mint_offered: UncheckedAccount<'info>,
+    /// CHECK: Any mint the offer quotes a price in, see
+    /// `create_offer_multi_wanted`; only its key is used, matched against
+    /// the offer's quotes
+    #[account(
+        constraint = !offer.alt_wanted.is_empty() || mint_wanted.key() == offer.mint_wanted
+            @ ErrorCode::WrongWantedMint,
+        constraint = offer.quote_for(&mint_wanted.key()).is_some() @ ErrorCode::MintNotAccepted,
+    )]
+    pub mint_wanted: UncheckedAccount<'info>,
+
+    /// Taker's ATA for the offered mint, created at the taker's expense if
+    /// this is their first time receiving it
+    #[account(
+        init_if_needed,
+        payer = taker,
+        associated_token::mint = mint_offered,
+        associated_token::authority = taker,
+    )]
+    pub taker_token_account_wanted: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = taker_token_account_offered.mint == mint_wanted.key()
+            @ ErrorCode::WrongWantedMint,
+        constraint = taker_token_account_offered.owner == taker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub taker_token_account_offered: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    /// Taker's swap history, created on their first accept
+    #[account(
+        init_if_needed,
+        payer = taker,
+        space = 8 + TakerStats::SIZE,
+        seeds = [b"taker_stats", taker.key().as_ref()],
+        bump
+    )]
+    pub taker_stats: Account<'info, TakerStats>,
+
+    /// CHECK: Protocol config PDA; may not be initialized yet, see `load_config`
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+
+    /// CHECK: Pause status PDAs of both mints; may not exist, see
+    /// `check_mints_unpaused`
+    #[account(seeds = [b"mint_status", mint_offered.key().as_ref()], bump)]
+    pub mint_status_offered: UncheckedAccount<'info>,
+    /// CHECK: See `mint_status_offered`
+    #[account(seeds = [b"mint_status", mint_wanted.key().as_ref()], bump)]
+    pub mint_status_wanted: UncheckedAccount<'info>,
+
+    /// CHECK: `MintTvl` of the offered mint, updated by `release_mint_tvl`
+    /// for offers counted in it
+    #[account(mut, seeds = [b"mint_tvl", mint_offered.key().as_ref()], bump)]
+    pub mint_tvl: UncheckedAccount<'info>,
+
+    /// Receives the protocol fee, of the wanted mint or, in fee-token mode,
+    /// of `config.fee_mint`; required when a non-zero fee applies
+    #[account(mut)]
+    pub treasury_token_account: Option<InterfaceAccount<'info, token_interface::TokenAccount>>,
+
+    /// Maker's holding of the rebate mint, to claim the fee rebate
+    pub maker_rebate_token_account: Option<Account<'info, TokenAccount>>,
+
+    /// CHECK: Pyth price account of an oracle offer, matched against
+    /// `offer.price_feed` and validated by `load_pyth_price`
+    pub price_feed: Option<UncheckedAccount<'info>>,
+
+    /// Referrer's ATA of the wanted mint; required when the offer has a referrer
+    #[account(mut)]
+    pub referrer_token_account: Option<InterfaceAccount<'info, token_interface::TokenAccount>>,
+
+    /// Required when `accept_offer` is given a memo
+    pub memo_program: Option<Program<'info, Memo>>,
+
+    /// Receives the offered tokens instead of the taker's ATA. Any owner is
+    /// allowed, e.g. a PDA vault of a program accepting through CPI.
+    #[account(
+        mut,
+        constraint = taker_receive_account.mint == offer.mint_offered
+            @ ErrorCode::WrongOfferedMint,
+    )]
+    pub taker_receive_account: Option<Account<'info, TokenAccount>>,
+
+    /// Royalty recipient's ATA of the wanted mint; required when the offer
+    /// carries a royalty
+    #[account(mut)]
+    pub royalty_token_account: Option<InterfaceAccount<'info, token_interface::TokenAccount>>,
+
+    /// CHECK: Instructions sysvar, read by `accept_offer_authorized` to find
+    /// the Ed25519 verification
+    #[account(address = anchor_lang::solana_program::sysvar::instructions::ID)]
+    pub instructions: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: AMM pool of an AMM-priced offer, matched against
+    /// `offer.amm_pool` and validated by `load_pool_reserves`
+    pub amm_pool: Option<UncheckedAccount<'info>>,
+
+    /// Taker's account of `config.fee_mint`; required in fee-token mode
+    #[account(mut)]
+    pub taker_fee_token_account: Option<Account<'info, TokenAccount>>,
+
+    /// CHECK: Taker's `VestingClaim` PDA `[b"vesting_claim", offer, taker]`,
+    /// created by `accept_with_vesting`
+    #[account(mut)]
+    pub vesting_claim: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Token account PDA `[b"vesting_vault", vesting_claim]` holding
+    /// the taker's tokens until the cliff, created by `accept_with_vesting`
+    #[account(mut)]
+    pub vesting_vault: Option<UncheckedAccount<'info>>,
+
+    /// Taker's deposit from `reserve_offer`, returned if they settle before
+    /// its deadline
+    #[account(
+        mut,
+        seeds = [b"reservation_deposit", offer.key().as_ref(), taker.key().as_ref()],
+        bump = reservation_deposit.bump,
+    )]
+    pub reservation_deposit: Option<Account<'info, ReservationDeposit>>,
+
+    /// Taker's holding of `offer.gate_mint`; required for token-gated offers
+    pub taker_gate_token_account: Option<Account<'info, TokenAccount>>,
+
+    /// Token-2022 program; required when the wanted mint is a Token-2022 one
+    pub wanted_token_program: Option<Interface<'info, TokenInterface>>,
+
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimVested<'info> {
+    #[account(
+        mut,
+        seeds = [b"vesting_claim", vesting_claim.offer.as_ref(), taker.key().as_ref()],
+        bump = vesting_claim.bump,
+        has_one = taker @ ErrorCode::Unauthorized,
+        close = taker,
+    )]
+    pub vesting_claim: Account<'info, VestingClaim>,
+
+    #[account(
+        mut,
+        seeds = [b"vesting_vault", vesting_claim.key().as_ref()],
+        bump = vesting_claim.vault_bump,
+    )]
+    pub vesting_vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = taker_token_account.mint == vesting_claim.mint @ ErrorCode::InvalidMint,
+        constraint = taker_token_account.owner == taker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub taker_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub taker: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer_id: u64)]
+pub struct CancelOffer<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"offer",
+            offer.maker.as_ref(),
+            &offer.id_seed(),
+        ],
+        bump = offer.bump,
+        constraint = offer.offer_id == offer_id @ ErrorCode::InvalidOfferId,
+        close = maker,
+        constraint = offer.current_owner == maker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub offer: Account<'info, Offer>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"vault",
+            offer.vault_key_seed(offer.key()).as_ref(),
+            &offer.vault_id_seed(),
+            mint_offered.key().as_ref(),
+        ],
+        bump = offer.vault_bump,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = maker_token_account.mint == offer.mint_offered @ ErrorCode::InvalidMint,
+        constraint = maker_token_account.owner == maker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub maker_token_account: Account<'info, TokenAccount>,
+
+    /// Receives the escrow instead of `maker_token_account` when given
+    #[account(mut)]
+    pub return_token_account: Option<Account<'info, TokenAccount>>,
+
+    pub mint_offered: Account<'info, Mint>,
+
+    #[account(mut)]
+    pub maker: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"user_profile", maker.key().as_ref()],
+        bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    /// CHECK: Protocol config PDA; may not be initialized yet, see `load_config`
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+
+    /// CHECK: `MintTvl` of the offered mint, updated by `release_mint_tvl`
+    /// for offers counted in it
+    #[account(mut, seeds = [b"mint_tvl", mint_offered.key().as_ref()], bump)]
+    pub mint_tvl: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer_id: u64)]
+pub struct MoveOfferToMaker<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"offer",
+            offer.maker.as_ref(),
+            &offer.id_seed(),
+        ],
+        bump = offer.bump,
+        constraint = offer.offer_id == offer_id @ ErrorCode::InvalidOfferId,
+        close = maker,
+        constraint = offer.current_owner == maker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub offer: Account<'info, Offer>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"vault",
+            offer.vault_key_seed(offer.key()).as_ref(),
+            &offer.vault_id_seed(),
+            mint_offered.key().as_ref(),
+        ],
+        bump = offer.vault_bump,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"user_profile", maker.key().as_ref()],
+        bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(
+        init_if_needed,
+        payer = new_maker,
+        space = 8 + UserProfile::SIZE,
+        seeds = [b"user_profile", new_maker.key().as_ref()],
+        bump,
+        // Ahead of `new_offer`'s init, see `CreateOffer`
+        constraint = new_offer.data_is_empty() @ ErrorCode::SlotInUse,
+    )]
+    pub new_user_profile: Account<'info, UserProfile>,
+
+    #[account(
+        init,
+        payer = new_maker,
+        space = 8 + Offer::space(
+            0,
+            offer.blocked_takers.len(),
+            offer.fill_queue_capacity.into(),
+            offer.max_acceptors.into(),
+        ),
+        seeds = [
+            b"offer",
+            new_maker.key().as_ref(),
+            &new_user_profile.offer_count.to_le_bytes(),
+        ],
+        bump
+    )]
+    pub new_offer: Account<'info, Offer>,
+
+    #[account(
+        init,
+        payer = new_maker,
+        seeds = [
+            b"vault",
+            vault_key_seed(false, false, new_offer.key(), new_maker.key()).as_ref(),
+            &vault_id_seed(
+                false,
+                false,
+                &new_user_profile.offer_count.to_le_bytes(),
+            ),
+            mint_offered.key().as_ref(),
+        ],
+        bump,
+        token::mint = mint_offered,
+        token::authority = new_vault,
+    )]
+    pub new_vault: Account<'info, TokenAccount>,
+
+    #[account(constraint = mint_offered.key() == offer.mint_offered @ ErrorCode::InvalidMint)]
+    pub mint_offered: Account<'info, Mint>,
+    #[account(constraint = mint_wanted.key() == offer.mint_wanted @ ErrorCode::InvalidMint)]
+    pub mint_wanted: InterfaceAccount<'info, token_interface::Mint>,
+
+    /// Current owner, giving up the offer
+    #[account(mut)]
+    pub maker: Signer<'info>,
+
+    /// Maker the offer is reopened under
+    #[account(mut)]
+    pub new_maker: Signer<'info>,
+
+    /// CHECK: Protocol config PDA; may not be initialized yet, see `load_config`
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+
+    /// CHECK: Pause status PDAs of both mints; may not exist, see
+    /// `check_mints_unpaused`
+    #[account(seeds = [b"mint_status", mint_offered.key().as_ref()], bump)]
+    pub mint_status_offered: UncheckedAccount<'info>,
+    /// CHECK: See `mint_status_offered`
+    #[account(seeds = [b"mint_status", mint_wanted.key().as_ref()], bump)]
+    pub mint_status_wanted: UncheckedAccount<'info>,
+
+    /// CHECK: `MintTvl` of the offered mint, created on its first counted
+    /// offer by `init_mint_tvl`
+    #[account(mut, seeds = [b"mint_tvl", mint_offered.key().as_ref()], bump)]
+    pub mint_tvl: UncheckedAccount<'info>,
+
+    /// New maker's allowlist entry; required while `config.maker_allowlist`
+    /// is on
+    #[account(
+        seeds = [b"maker_status", new_maker.key().as_ref()],
+        bump = new_maker_status.bump,
+    )]
+    pub new_maker_status: Option<Account<'info, MakerStatus>>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer_id: u64)]
+pub struct RelistOffer<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"offer",
+            offer.maker.as_ref(),
+            &offer.id_seed(),
+        ],
+        bump = offer.bump,
+        constraint = offer.offer_id == offer_id @ ErrorCode::InvalidOfferId,
+        close = maker,
+        constraint = offer.current_owner == maker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub offer: Account<'info, Offer>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"vault",
+            offer.vault_key_seed(offer.key()).as_ref(),
+            &offer.vault_id_seed(),
+            mint_offered.key().as_ref(),
+        ],
+        bump = offer.vault_bump,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    /// Always exists while it counts `offer`; `init_if_needed` only makes
+    /// the slot check below run ahead of `new_offer`'s init
+    #[account(
+        init_if_needed,
+        payer = maker,
+        space = 8 + UserProfile::SIZE,
+        seeds = [b"user_profile", maker.key().as_ref()],
+        bump,
+        // Ahead of `new_offer`'s init, see `CreateOffer`
+        constraint = new_offer.data_is_empty() @ ErrorCode::SlotInUse,
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(
+        init,
+        payer = maker,
+        space = 8 + Offer::space(
+            0,
+            offer.blocked_takers.len(),
+            offer.fill_queue_capacity.into(),
+            offer.max_acceptors.into(),
+        ),
+        seeds = [
+            b"offer",
+            maker.key().as_ref(),
+            &user_profile.offer_count.to_le_bytes(),
+        ],
+        bump
+    )]
+    pub new_offer: Account<'info, Offer>,
+
+    #[account(
+        init,
+        payer = maker,
+        seeds = [
+            b"vault",
+            vault_key_seed(offer.maker_derived_vault, false, new_offer.key(), maker.key()).as_ref(),
+            &vault_id_seed(
+                offer.maker_derived_vault,
+                false,
+                &user_profile.offer_count.to_le_bytes(),
+            ),
+            mint_offered.key().as_ref(),
+        ],
+        bump,
+        token::mint = mint_offered,
+        token::authority = new_vault,
+    )]
+    pub new_vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = maker_token_account.mint == offer.mint_offered @ ErrorCode::InvalidMint,
+        constraint = maker_token_account.owner == maker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub maker_token_account: Account<'info, TokenAccount>,
+
+    #[account(constraint = mint_offered.key() == offer.mint_offered @ ErrorCode::InvalidMint)]
+    pub mint_offered: Account<'info, Mint>,
+    #[account(constraint = mint_wanted.key() == offer.mint_wanted @ ErrorCode::InvalidMint)]
+    pub mint_wanted: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(mut)]
+    pub maker: Signer<'info>,
+
+    /// CHECK: Protocol config PDA; may not be initialized yet, see `load_config`
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+
+    /// CHECK: Pause status PDAs of both mints; may not exist, see
+    /// `check_mints_unpaused`
+    #[account(seeds = [b"mint_status", mint_offered.key().as_ref()], bump)]
+    pub mint_status_offered: UncheckedAccount<'info>,
+    /// CHECK: See `mint_status_offered`
+    #[account(seeds = [b"mint_status", mint_wanted.key().as_ref()], bump)]
+    pub mint_status_wanted: UncheckedAccount<'info>,
+
+    /// CHECK: `MintTvl` of the offered mint, created on its first counted
+    /// offer by `init_mint_tvl`
+    #[account(mut, seeds = [b"mint_tvl", mint_offered.key().as_ref()], bump)]
+    pub mint_tvl: UncheckedAccount<'info>,
+
+    /// CHECK: Treasury wallet, matched against `config.treasury` by
+    /// `collect_listing_fee`; required when a listing fee applies
+    #[account(mut)]
+    pub treasury: Option<UncheckedAccount<'info>>,
+
+    /// Maker's allowlist entry; required while `config.maker_allowlist` is on
+    #[account(
+        seeds = [b"maker_status", maker.key().as_ref()],
+        bump = maker_status.bump,
+    )]
+    pub maker_status: Option<Account<'info, MakerStatus>>,
+
+    /// Allowlist entries of both mints; required while
+    /// `config.mint_allowlist` is on
+    #[account(
+        seeds = [b"allowed_mint", mint_offered.key().as_ref()],
+        bump = allowed_mint_offered.bump,
+    )]
+    pub allowed_mint_offered: Option<Account<'info, AllowedMint>>,
+    #[account(
+        seeds = [b"allowed_mint", mint_wanted.key().as_ref()],
+        bump = allowed_mint_wanted.bump,
+    )]
+    pub allowed_mint_wanted: Option<Account<'info, AllowedMint>>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer_id: u64)]
+pub struct DecreaseOffer<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"offer",
+            offer.maker.as_ref(),
+            &offer.id_seed(),
+        ],
+        bump = offer.bump,
+        constraint = offer.offer_id == offer_id @ ErrorCode::InvalidOfferId,
+        constraint = offer.current_owner == maker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub offer: Account<'info, Offer>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"vault",
+            offer.vault_key_seed(offer.key()).as_ref(),
+            &offer.vault_id_seed(),
+            mint_offered.key().as_ref(),
+        ],
+        bump = offer.vault_bump,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = maker_token_account.mint == offer.mint_offered @ ErrorCode::InvalidMint,
+        constraint = maker_token_account.owner == maker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub maker_token_account: Account<'info, TokenAccount>,
+
+    pub mint_offered: Account<'info, Mint>,
+
+    #[account(mut)]
+    pub maker: Signer<'info>,
+
+    /// CHECK: `MintTvl` of the offered mint, updated by `release_mint_tvl`
+    /// for offers counted in it
+    #[account(mut, seeds = [b"mint_tvl", offer.mint_offered.as_ref()], bump)]
+    pub mint_tvl: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer_id: u64, new_mint_wanted: Pubkey)]
+pub struct ChangeWantedMint<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"offer",
+            offer.maker.as_ref(),
+            &offer.id_seed(),
+        ],
+        bump = offer.bump,
+        constraint = offer.offer_id == offer_id @ ErrorCode::InvalidOfferId,
+        constraint = offer.current_owner == maker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub offer: Account<'info, Offer>,
+
+    #[account(address = new_mint_wanted @ ErrorCode::WrongWantedMint)]
+    pub mint_wanted: Account<'info, Mint>,
+
+    /// CHECK: Pause status PDAs of both mints; may not exist, see
+    /// `check_mints_unpaused`
+    #[account(seeds = [b"mint_status", offer.mint_offered.as_ref()], bump)]
+    pub mint_status_offered: UncheckedAccount<'info>,
+    /// CHECK: See `mint_status_offered`
+    #[account(seeds = [b"mint_status", mint_wanted.key().as_ref()], bump)]
+    pub mint_status_wanted: UncheckedAccount<'info>,
+
+    /// CHECK: Protocol config PDA; may not be initialized yet, see `load_config`
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+
+    /// Allowlist entries of both mints; required while
+    /// `config.mint_allowlist` is on
+    #[account(
+        seeds = [b"allowed_mint", offer.mint_offered.as_ref()],
+        bump = allowed_mint_offered.bump,
+    )]
+    pub allowed_mint_offered: Option<Account<'info, AllowedMint>>,
+    #[account(
+        seeds = [b"allowed_mint", mint_wanted.key().as_ref()],
+        bump = allowed_mint_wanted.bump,
+    )]
+    pub allowed_mint_wanted: Option<Account<'info, AllowedMint>>,
+
+    pub maker: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer_id: u64)]
+pub struct ScaleOffer<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"offer",
+            offer.maker.as_ref(),
+            &offer.id_seed(),
+        ],
+        bump = offer.bump,
+        constraint = offer.offer_id == offer_id @ ErrorCode::InvalidOfferId,
+        constraint = offer.current_owner == maker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub offer: Account<'info, Offer>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"vault",
+            offer.vault_key_seed(offer.key()).as_ref(),
+            &offer.vault_id_seed(),
+            mint_offered.key().as_ref(),
+        ],
+        bump = offer.vault_bump,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = maker_token_account.mint == offer.mint_offered @ ErrorCode::InvalidMint,
+        constraint = maker_token_account.owner == maker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub maker_token_account: Account<'info, TokenAccount>,
+
+    pub mint_offered: Account<'info, Mint>,
+
+    #[account(mut)]
+    pub maker: Signer<'info>,
+
+    /// CHECK: Protocol config PDA; may not be initialized yet, see `load_config`
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+
+    /// CHECK: `MintTvl` of the offered mint, updated by `lock_mint_tvl` and
+    /// `release_mint_tvl` for offers counted in it
+    #[account(mut, seeds = [b"mint_tvl", offer.mint_offered.as_ref()], bump)]
+    pub mint_tvl: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer_id: u64)]
+pub struct ExtendExpiry<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"offer",
+            offer.maker.as_ref(),
+            &offer.id_seed(),
+        ],
+        bump = offer.bump,
+        constraint = offer.offer_id == offer_id @ ErrorCode::InvalidOfferId,
+        constraint = offer.current_owner == maker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub offer: Account<'info, Offer>,
+
+    /// Pays the extension fee, if any
+    #[account(mut)]
+    pub maker: Signer<'info>,
+
+    /// CHECK: Protocol config PDA; may not be initialized yet, see `load_config`
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+
+    /// CHECK: Treasury wallet, matched against `config.treasury` when an
+    /// extension fee is due
+    #[account(mut)]
+    pub treasury: Option<UncheckedAccount<'info>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer_id: u64, new_owner: Pubkey)]
+pub struct TransferOfferOwnership<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"offer",
+            offer.maker.as_ref(),
+            &offer.id_seed(),
+        ],
+        bump = offer.bump,
+        constraint = offer.offer_id == offer_id @ ErrorCode::InvalidOfferId,
+        constraint = offer.current_owner == owner.key() @ ErrorCode::Unauthorized,
+    )]
+    pub offer: Account<'info, Offer>,
+
+    pub owner: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"user_profile", owner.key().as_ref()],
+        bump
+    )]
+    pub owner_profile: Account<'info, UserProfile>,
+
+    #[account(
+        mut,
+        seeds = [b"user_profile", new_owner.as_ref()],
+        bump
+    )]
+    pub new_owner_profile: Account<'info, UserProfile>,
+
+    /// CHECK: Protocol config PDA; may not be initialized yet, see `load_config`
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer_id: u64)]
+pub struct SetOfferPaused<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"offer",
+            offer.maker.as_ref(),
+            &offer.id_seed(),
+        ],
+        bump = offer.bump,
+        constraint = offer.offer_id == offer_id @ ErrorCode::InvalidOfferId,
+        constraint = offer.current_owner == owner.key() @ ErrorCode::Unauthorized,
+    )]
+    pub offer: Account<'info, Offer>,
+
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer_id: u64)]
+pub struct ReserveOffer<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"offer",
+            maker.key().as_ref(),
+            &offer.id_seed(),
+        ],
+        bump = offer.bump,
+        constraint = offer.offer_id == offer_id @ ErrorCode::InvalidOfferId,
+        has_one = maker @ ErrorCode::Unauthorized,
+    )]
+    pub offer: Account<'info, Offer>,
+
+    /// CHECK: Only used to derive the offer PDA, validated by has_one in offer
+    pub maker: UncheckedAccount<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = taker,
+        space = 8 + ReservationDeposit::SIZE,
+        seeds = [b"reservation_deposit", offer.key().as_ref(), taker.key().as_ref()],
+        bump
+    )]
+    pub deposit: Account<'info, ReservationDeposit>,
+
+    #[account(mut)]
+    pub taker: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimForfeitedDeposit<'info> {
+    #[account(
+        mut,
+        seeds = [b"reservation_deposit", deposit.offer.as_ref(), deposit.taker.as_ref()],
+        bump = deposit.bump,
+        has_one = owner @ ErrorCode::Unauthorized,
+        close = owner,
+    )]
+    pub deposit: Account<'info, ReservationDeposit>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer_id: u64)]
+pub struct ProposeCounter<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"offer",
+            maker.key().as_ref(),
+            &offer.id_seed(),
+        ],
+        bump = offer.bump,
+        constraint = offer.offer_id == offer_id @ ErrorCode::InvalidOfferId,
+        has_one = maker @ ErrorCode::Unauthorized,
+    )]
+    pub offer: Account<'info, Offer>,
+
+    /// CHECK: Only used to derive the offer PDA, validated by has_one in offer
+    pub maker: UncheckedAccount<'info>,
+
+    pub taker: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer_id: u64)]
+pub struct AcceptCounter<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"offer",
+            offer.maker.as_ref(),
+            &offer.id_seed(),
+        ],
+        bump = offer.bump,
+        constraint = offer.offer_id == offer_id @ ErrorCode::InvalidOfferId,
+        constraint = offer.current_owner == maker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub offer: Account<'info, Offer>,
+
+    pub maker: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer_id: u64)]
+pub struct JoinFillQueue<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"offer",
+            maker.key().as_ref(),
+            &offer.id_seed(),
+        ],
+        bump = offer.bump,
+        constraint = offer.offer_id == offer_id @ ErrorCode::InvalidOfferId,
+        has_one = maker @ ErrorCode::Unauthorized,
+    )]
+    pub offer: Account<'info, Offer>,
+
+    /// CHECK: Only used to derive the offer PDA, validated by has_one in offer
+    pub maker: UncheckedAccount<'info>,
+
+    #[account(
+        init,
+        payer = taker,
+        space = 8 + QueueTicket::SIZE,
+        seeds = [b"queue_ticket", offer.key().as_ref(), taker.key().as_ref()],
+        bump
+    )]
+    pub ticket: Account<'info, QueueTicket>,
+
+    #[account(mut)]
+    pub taker: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer_id: u64)]
+pub struct ServeFillQueue<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"offer",
+            maker.key().as_ref(),
+            &offer.id_seed(),
+        ],
+        bump = offer.bump,
+        constraint = offer.offer_id == offer_id @ ErrorCode::InvalidOfferId,
+        has_one = maker @ ErrorCode::Unauthorized,
+    )]
+    pub offer: Account<'info, Offer>,
+
+    /// CHECK: Only used to derive the offer PDA, validated by has_one in offer
+    pub maker: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct LeaveFillQueue<'info> {
+    #[account(
+        mut,
+        seeds = [b"queue_ticket", ticket.offer.as_ref(), taker.key().as_ref()],
+        bump = ticket.bump,
+        has_one = taker @ ErrorCode::Unauthorized,
+        close = taker,
+    )]
+    pub ticket: Account<'info, QueueTicket>,
+
+    /// CHECK: The queued offer, if it still exists; matched against the ticket
+    #[account(mut, address = ticket.offer)]
+    pub offer: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub taker: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer_id: u64)]
+pub struct ReleaseReservation<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"offer",
+            maker.key().as_ref(),
+            &offer.id_seed(),
+        ],
+        bump = offer.bump,
+        constraint = offer.offer_id == offer_id @ ErrorCode::InvalidOfferId,
+        has_one = maker @ ErrorCode::Unauthorized,
+    )]
+    pub offer: Account<'info, Offer>,
+
+    /// CHECK: Only used to derive the offer PDA, validated by has_one in offer
+    pub maker: UncheckedAccount<'info>,
+
+    /// Reserving taker or the maker
+    pub signer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer_id: u64)]
+pub struct CommitAccept<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"offer",
+            maker.key().as_ref(),
+            &offer.id_seed(),
+        ],
+        bump = offer.bump,
+        constraint = offer.offer_id == offer_id @ ErrorCode::InvalidOfferId,
+        has_one = maker @ ErrorCode::Unauthorized,
+    )]
+    pub offer: Account<'info, Offer>,
+
+    /// CHECK: Only used to derive the offer PDA, validated by has_one in offer
+    pub maker: UncheckedAccount<'info>,
+
+    pub taker: Signer<'info>,
+
+    /// CHECK: Protocol config PDA; may not be initialized yet, see `load_config`
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer_seed: Vec<u8>)]
+pub struct SweepOrphanVault<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+
+    pub admin: Signer<'info>,
+
+    /// CHECK: Address of the vanished offer; must hold no account
+    #[account(
+        seeds = [
+            b"offer",
+            maker.key().as_ref(),
+            &offer_seed,
+        ],
+        bump,
+        constraint = offer.data_is_empty() @ ErrorCode::OfferStillOpen,
+    )]
+    pub offer: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"vault",
+            offer.key().as_ref(),
+            mint.key().as_ref(),
+        ],
+        bump,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    pub mint: Account<'info, Mint>,
+
+    /// CHECK: Original maker, bound to the vault through the offer seeds
+    #[account(mut)]
+    pub maker: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        constraint = maker_token_account.mint == mint.key() @ ErrorCode::InvalidMint,
+        constraint = maker_token_account.owner == maker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub maker_token_account: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer_id: u64)]
+pub struct RescueExcess<'info> {
+    #[account(
+        seeds = [
+            b"offer",
+            offer.maker.as_ref(),
+            &offer.id_seed(),
+        ],
+        bump = offer.bump,
+        constraint = offer.offer_id == offer_id @ ErrorCode::InvalidOfferId,
+    )]
+    pub offer: Account<'info, Offer>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"vault",
+            offer.vault_key_seed(offer.key()).as_ref(),
+            &offer.vault_id_seed(),
+            offer.mint_offered.as_ref(),
+        ],
+        bump = offer.vault_bump,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    /// Receives the excess
+    #[account(
+        mut,
+        constraint = destination.mint == offer.mint_offered @ ErrorCode::InvalidMint,
+    )]
+    pub destination: Account<'info, TokenAccount>,
+
+    /// The offer's owner or the protocol admin
+    pub authority: Signer<'info>,
+
+    /// CHECK: Protocol config PDA; may not be initialized yet, see `load_config`
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawFees<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    /// CHECK: Program treasury PDA, only signs for its fee accounts
+    #[account(seeds = [b"treasury"], bump)]
+    pub treasury: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        constraint = fee_account.owner == treasury.key() @ ErrorCode::Unauthorized,
+    )]
+    pub fee_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = destination.mint == fee_account.mint @ ErrorCode::InvalidMint,
+    )]
+    pub destination: Account<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = admin,
+        space = 8 + GlobalStats::SIZE,
+        seeds = [b"global_stats"],
+        bump,
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptOffersBatch<'info> {
+    /// Taker of every offer in the batch
+    #[account(mut)]
+    pub taker: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer_id: u64, new_offer_id: u64)]
+pub struct AcceptAndRelist<'info> {
+    /// Taker of the accepted offer; pays the relisted offer's rent
+    #[account(mut)]
+    pub taker: Signer<'info>,
+
+    /// CHECK: Offer owner the proceeds are relisted for; matched against the
+    /// accepted offer's owner
+    pub maker: UncheckedAccount<'info>,
+
+    #[account(
+        init,
+        payer = taker,
+        space = 8 + Offer::space(0, 0, 0, 0),
+        seeds = [
+            b"offer",
+            maker.key().as_ref(),
+            &new_offer_id.to_le_bytes(),
+        ],
+        bump
+    )]
+    pub new_offer: Account<'info, Offer>,
+
+    #[account(
+        init,
+        payer = taker,
+        seeds = [
+            b"vault",
+            vault_key_seed(false, false, new_offer.key(), maker.key()).as_ref(),
+            &vault_id_seed(false, false, &new_offer_id.to_le_bytes()),
+            mint_offered.key().as_ref(),
+        ],
+        bump,
+        token::mint = mint_offered,
+        token::authority = new_vault,
+    )]
+    pub new_vault: Account<'info, TokenAccount>,
+
+    /// The accepted offer's wanted mint, offered by the relisted offer
+    pub mint_offered: Account<'info, Mint>,
+    /// The accepted offer's offered mint, wanted back by the relisted offer
+    pub mint_wanted: InterfaceAccount<'info, token_interface::Mint>,
+
+    /// CHECK: `MintTvl` of the relisted mint, created on its first counted
+    /// offer by `init_mint_tvl`
+    #[account(mut, seeds = [b"mint_tvl", mint_offered.key().as_ref()], bump)]
+    pub new_mint_tvl: UncheckedAccount<'info>,
+
+    /// CHECK: Program PDA the maker approves as delegate of the proceeds
+    #[account(seeds = [b"delegate"], bump)]
+    pub delegate: UncheckedAccount<'info>,
+
+    /// Maker's allowlist entry; required while `config.maker_allowlist` is on
+    #[account(
+        seeds = [b"maker_status", maker.key().as_ref()],
+        bump = maker_status.bump,
+    )]
+    pub maker_status: Option<Account<'info, MakerStatus>>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptWithLoyalty<'info> {
+    /// Taker of the accepted offer; pays the loyalty account's rent
+    #[account(mut)]
+    pub taker: Signer<'info>,
+
+    /// Taker's points, created on their first loyalty accept
+    #[account(
+        init_if_needed,
+        payer = taker,
+        space = 8 + LoyaltyAccount::SIZE,
+        seeds = [b"loyalty", taker.key().as_ref()],
+        bump
+    )]
+    pub loyalty_account: Account<'info, LoyaltyAccount>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct LoyaltyPoints<'info> {
+    #[account(
+        seeds = [b"loyalty", loyalty_account.taker.as_ref()],
+        bump = loyalty_account.bump,
+    )]
+    pub loyalty_account: Account<'info, LoyaltyAccount>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer_id: u64)]
+pub struct CancelExpiredOffer<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"offer",
+            offer.maker.as_ref(),
+            &offer.id_seed(),
+        ],
+        bump = offer.bump,
+        constraint = offer.offer_id == offer_id @ ErrorCode::InvalidOfferId,
+        close = maker,
+        constraint = offer.current_owner == maker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub offer: Account<'info, Offer>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"vault",
+            offer.vault_key_seed(offer.key()).as_ref(),
+            &offer.vault_id_seed(),
+            offer.mint_offered.as_ref(),
+        ],
+        bump = offer.vault_bump,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = maker_token_account.mint == offer.mint_offered @ ErrorCode::InvalidMint,
+        constraint = maker_token_account.owner == maker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub maker_token_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Offer owner, receives the escrow and the rent left after the
+    /// tip; matched against `offer.current_owner`
+    #[account(mut)]
+    pub maker: UncheckedAccount<'info>,
+
+    /// Owner's profile, whose open-offer slot the offer frees
+    #[account(
+        mut,
+        seeds = [b"user_profile", maker.key().as_ref()],
+        bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    /// Anyone cleaning up the offer; collects the tip
+    #[account(mut)]
+    pub keeper: Signer<'info>,
+
+    /// CHECK: Protocol config PDA; may not be initialized yet, see `load_config`
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+
+    /// CHECK: `MintTvl` of the offered mint, updated by `release_mint_tvl`
+    /// for offers counted in it
+    #[account(mut, seeds = [b"mint_tvl", offer.mint_offered.as_ref()], bump)]
+    pub mint_tvl: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer_id: u64)]
+pub struct CleanupDustOffer<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"offer",
+            offer.maker.as_ref(),
+            &offer.id_seed(),
+        ],
+        bump = offer.bump,
+        constraint = offer.offer_id == offer_id @ ErrorCode::InvalidOfferId,
+        close = maker,
+        constraint = offer.current_owner == maker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub offer: Account<'info, Offer>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"vault",
+            offer.vault_key_seed(offer.key()).as_ref(),
+            &offer.vault_id_seed(),
+            offer.mint_offered.as_ref(),
+        ],
+        bump = offer.vault_bump,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = maker_token_account.mint == offer.mint_offered @ ErrorCode::InvalidMint,
+        constraint = maker_token_account.owner == maker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub maker_token_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Offer owner, receives the dust and all rent; matched against
+    /// `offer.current_owner`
+    #[account(mut)]
+    pub maker: UncheckedAccount<'info>,
+
+    /// Owner's profile, whose open-offer slot the offer frees
+    #[account(
+        mut,
+        seeds = [b"user_profile", maker.key().as_ref()],
+        bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    /// Anyone cleaning up the offer
+    pub keeper: Signer<'info>,
+
+    /// CHECK: `MintTvl` of the offered mint, updated by `release_mint_tvl`
+    /// for offers counted in it
+    #[account(mut, seeds = [b"mint_tvl", offer.mint_offered.as_ref()], bump)]
+    pub mint_tvl: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer_id: u64)]
+pub struct CancelIfFrozen<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"offer",
+            offer.maker.as_ref(),
+            &offer.id_seed(),
+        ],
+        bump = offer.bump,
+        constraint = offer.offer_id == offer_id @ ErrorCode::InvalidOfferId,
+        close = maker,
+        constraint = offer.current_owner == maker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub offer: Account<'info, Offer>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"vault",
+            offer.vault_key_seed(offer.key()).as_ref(),
+            &offer.vault_id_seed(),
+            offer.mint_offered.as_ref(),
+        ],
+        bump = offer.vault_bump,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = maker_token_account.mint == offer.mint_offered @ ErrorCode::InvalidMint,
+        constraint = maker_token_account.owner == maker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub maker_token_account: Account<'info, TokenAccount>,
+
+    /// Owner's account of the wanted mint, which must be frozen
+    #[account(
+        constraint = maker_token_account_wanted.mint == offer.mint_wanted
+            @ ErrorCode::WrongWantedMint,
+        constraint = maker_token_account_wanted.owner == maker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub maker_token_account_wanted: Account<'info, TokenAccount>,
+
+    /// CHECK: Offer owner, receives the escrow and the rent; matched against
+    /// `offer.current_owner`
+    #[account(mut)]
+    pub maker: UncheckedAccount<'info>,
+
+    /// Owner's profile, whose open-offer slot the offer frees
+    #[account(
+        mut,
+        seeds = [b"user_profile", maker.key().as_ref()],
+        bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    /// CHECK: `MintTvl` of the offered mint, updated by `release_mint_tvl`
+    /// for offers counted in it
+    #[account(mut, seeds = [b"mint_tvl", offer.mint_offered.as_ref()], bump)]
+    pub mint_tvl: UncheckedAccount<'info>,
+
+    /// Anyone freeing the escrow
+    pub caller: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct MatchOffers<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"offer",
+            offer_a.maker.as_ref(),
+            &offer_a.id_seed(),
+        ],
+        bump = offer_a.bump,
+        constraint = offer_a.current_owner == maker_a.key() @ ErrorCode::Unauthorized,
+    )]
+    pub offer_a: Account<'info, Offer>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"vault",
+            offer_a.vault_key_seed(offer_a.key()).as_ref(),
+            &offer_a.vault_id_seed(),
+            offer_a.mint_offered.as_ref(),
+        ],
+        bump = offer_a.vault_bump,
+    )]
+    pub vault_a: Account<'info, TokenAccount>,
+
+    /// CHECK: Owner of `offer_a`, receives its rent; matched against
+    /// `offer_a.current_owner`
+    #[account(mut)]
+    pub maker_a: UncheckedAccount<'info>,
+
+    /// Owner's profile, whose open-offer slot `offer_a` frees
+    #[account(
+        mut,
+        seeds = [b"user_profile", maker_a.key().as_ref()],
+        bump
+    )]
+    pub maker_a_profile: Account<'info, UserProfile>,
+
+    /// Receives `offer_b`'s escrow
+    #[account(
+        mut,
+        constraint = maker_a_token_account.mint == offer_a.mint_wanted
+            @ ErrorCode::WrongWantedMint,
+        constraint = offer_a.maker_receive_account.map_or(
+            maker_a_token_account.owner == maker_a.key(),
+            |receive_account| maker_a_token_account.key() == receive_account,
+        ) @ ErrorCode::Unauthorized,
+    )]
+    pub maker_a_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"offer",
+            offer_b.maker.as_ref(),
+            &offer_b.id_seed(),
+        ],
+        bump = offer_b.bump,
+        constraint = offer_b.current_owner == maker_b.key() @ ErrorCode::Unauthorized,
+    )]
+    pub offer_b: Account<'info, Offer>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"vault",
+            offer_b.vault_key_seed(offer_b.key()).as_ref(),
+            &offer_b.vault_id_seed(),
+            offer_b.mint_offered.as_ref(),
+        ],
+        bump = offer_b.vault_bump,
+    )]
+    pub vault_b: Account<'info, TokenAccount>,
+
+    /// CHECK: Owner of `offer_b`, receives its rent; matched against
+    /// `offer_b.current_owner`
+    #[account(mut)]
+    pub maker_b: UncheckedAccount<'info>,
+
+    /// Owner's profile, whose open-offer slot `offer_b` frees
+    #[account(
+        mut,
+        seeds = [b"user_profile", maker_b.key().as_ref()],
+        bump
+    )]
+    pub maker_b_profile: Account<'info, UserProfile>,
+
+    /// Receives `offer_a`'s escrow
+    #[account(
+        mut,
+        constraint = maker_b_token_account.mint == offer_b.mint_wanted
+            @ ErrorCode::WrongWantedMint,
+        constraint = offer_b.maker_receive_account.map_or(
+            maker_b_token_account.owner == maker_b.key(),
+            |receive_account| maker_b_token_account.key() == receive_account,
+        ) @ ErrorCode::Unauthorized,
+    )]
+    pub maker_b_token_account: Account<'info, TokenAccount>,
+
+    /// Anyone settling the pair
+    pub keeper: Signer<'info>,
+
+    /// CHECK: `MintTvl`s of both offered mints, updated by
+    /// `release_mint_tvl` for offers counted in them
+    #[account(mut, seeds = [b"mint_tvl", offer_a.mint_offered.as_ref()], bump)]
+    pub mint_tvl_a: UncheckedAccount<'info>,
+    /// CHECK: See `mint_tvl_a`
+    #[account(mut, seeds = [b"mint_tvl", offer_b.mint_offered.as_ref()], bump)]
+    pub mint_tvl_b: UncheckedAccount<'info>,
+
+    /// CHECK: Pause status PDAs of both offered mints; may not exist, see
+    /// `check_mints_unpaused`
+    #[account(seeds = [b"mint_status", offer_a.mint_offered.as_ref()], bump)]
+    pub mint_status_a: UncheckedAccount<'info>,
+    /// CHECK: See `mint_status_a`
+    #[account(seeds = [b"mint_status", offer_b.mint_offered.as_ref()], bump)]
+    pub mint_status_b: UncheckedAccount<'info>,
+
+    /// CHECK: Protocol config PDA; may not be initialized yet, see `load_config`
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer_id: u64)]
+pub struct ForceReclaim<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"offer",
+            offer.maker.as_ref(),
+            &offer.id_seed(),
+        ],
+        bump = offer.bump,
+        constraint = offer.offer_id == offer_id @ ErrorCode::InvalidOfferId,
+        close = maker,
+        constraint = offer.current_owner == maker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub offer: Account<'info, Offer>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"vault",
+            offer.vault_key_seed(offer.key()).as_ref(),
+            &offer.vault_id_seed(),
+            offer.mint_offered.as_ref(),
+        ],
+        bump = offer.vault_bump,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = maker_token_account.mint == offer.mint_offered @ ErrorCode::InvalidMint,
+        constraint = maker_token_account.owner == maker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub maker_token_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Offer owner, receives the escrow and all rent; matched against
+    /// `offer.current_owner`
+    #[account(mut)]
+    pub maker: UncheckedAccount<'info>,
+
+    /// Owner's profile, whose open-offer slot the offer frees
+    #[account(
+        mut,
+        seeds = [b"user_profile", maker.key().as_ref()],
+        bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+
+    pub admin: Signer<'info>,
+
+    /// CHECK: `MintTvl` of the offered mint, updated by `release_mint_tvl`
+    /// for offers counted in it
+    #[account(mut, seeds = [b"mint_tvl", offer.mint_offered.as_ref()], bump)]
+    pub mint_tvl: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer_id: u64)]
+pub struct EmergencyWithdraw<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"offer",
+            offer.maker.as_ref(),
+            &offer.id_seed(),
+        ],
+        bump = offer.bump,
+        constraint = offer.offer_id == offer_id @ ErrorCode::InvalidOfferId,
+        close = maker,
+        constraint = offer.current_owner == maker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub offer: Account<'info, Offer>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"vault",
+            offer.vault_key_seed(offer.key()).as_ref(),
+            &offer.vault_id_seed(),
+            offer.mint_offered.as_ref(),
+        ],
+        bump = offer.vault_bump,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = maker_token_account.mint == offer.mint_offered @ ErrorCode::InvalidMint,
+        constraint = maker_token_account.owner == maker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub maker_token_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Offer owner, receives the escrow and all rent; matched against
+    /// `offer.current_owner`
+    #[account(mut)]
+    pub maker: UncheckedAccount<'info>,
+
+    /// Owner's profile, whose open-offer slot the offer frees
+    #[account(
+        mut,
+        seeds = [b"user_profile", maker.key().as_ref()],
+        bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    /// Anyone; pays only the transaction fee
+    pub caller: Signer<'info>,
+
+    /// CHECK: `MintTvl` of the offered mint, updated by `release_mint_tvl`
+    /// for offers counted in it
+    #[account(mut, seeds = [b"mint_tvl", offer.mint_offered.as_ref()], bump)]
+    pub mint_tvl: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer_id: u64)]
+pub struct EmergencyReclaim<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"offer",
+            offer.maker.as_ref(),
+            &offer.id_seed(),
+        ],
+        bump = offer.bump,
+        constraint = offer.offer_id == offer_id @ ErrorCode::InvalidOfferId,
+        close = maker,
+        constraint = offer.current_owner == maker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub offer: Account<'info, Offer>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"vault",
+            offer.vault_key_seed(offer.key()).as_ref(),
+            &offer.vault_id_seed(),
+            offer.mint_offered.as_ref(),
+        ],
+        bump = offer.vault_bump,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = maker_token_account.mint == offer.mint_offered @ ErrorCode::InvalidMint,
+        constraint = maker_token_account.owner == maker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub maker_token_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Offer owner, receives the escrow and all rent; matched against
+    /// `offer.current_owner`
+    #[account(mut)]
+    pub maker: UncheckedAccount<'info>,
+
+    /// Owner's profile, whose open-offer slot the offer frees
+    #[account(
+        mut,
+        seeds = [b"user_profile", maker.key().as_ref()],
+        bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    /// Anyone; pays only the transaction fee
+    pub caller: Signer<'info>,
+
+    /// CHECK: `MintTvl` of the offered mint, updated by `release_mint_tvl`
+    /// for offers counted in it
+    #[account(mut, seeds = [b"mint_tvl", offer.mint_offered.as_ref()], bump)]
+    pub mint_tvl: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer_id: u64)]
+pub struct EmergencyReclaimSolOffer<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"sol_offer",
+            maker.key().as_ref(),
+            &offer_id.to_le_bytes(),
+        ],
+        bump = sol_offer.bump,
+        constraint = sol_offer.offer_id == offer_id @ ErrorCode::InvalidOfferId,
+        close = maker,
+        has_one = maker @ ErrorCode::Unauthorized,
+    )]
+    pub sol_offer: Account<'info, SolOffer>,
+
+    /// CHECK: Maker, receives the escrow and rent; validated by has_one
+    #[account(mut)]
+    pub maker: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"user_profile", maker.key().as_ref()],
+        bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    /// Anyone; pays only the transaction fee
+    pub caller: Signer<'info>,
+
+    /// CHECK: `MintTvl` of the native mint, updated by `release_mint_tvl`
+    #[account(
+        mut,
+        seeds = [b"mint_tvl", token::spl_token::native_mint::ID.as_ref()],
+        bump
+    )]
+    pub mint_tvl: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer_id: u64)]
+pub struct EmergencyReclaimBundle<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"bundle",
+            maker.key().as_ref(),
+            &offer_id.to_le_bytes(),
+        ],
+        bump = bundle.bump,
+        constraint = bundle.offer_id == offer_id @ ErrorCode::InvalidOfferId,
+        close = maker,
+        has_one = maker @ ErrorCode::Unauthorized,
+    )]
+    pub bundle: Account<'info, SellBundle>,
+
+    /// CHECK: Maker, receives the escrow and rent; validated by has_one
+    #[account(mut)]
+    pub maker: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"user_profile", maker.key().as_ref()],
+        bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    /// Anyone; pays only the transaction fee
+    pub caller: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer_id: u64)]
+pub struct OrphanOfferReclaim<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"offer",
+            offer.maker.as_ref(),
+            &offer.id_seed(),
+        ],
+        bump = offer.bump,
+        constraint = offer.offer_id == offer_id @ ErrorCode::InvalidOfferId,
+        close = maker,
+        has_one = maker @ ErrorCode::Unauthorized,
+        constraint = offer.current_owner == maker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub offer: Account<'info, Offer>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"vault",
+            offer.vault_key_seed(offer.key()).as_ref(),
+            &offer.vault_id_seed(),
+            offer.mint_offered.as_ref(),
+        ],
+        bump = offer.vault_bump,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = maker_token_account.mint == offer.mint_offered @ ErrorCode::InvalidMint,
+        constraint = maker_token_account.owner == maker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub maker_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub maker: Signer<'info>,
+
+    /// CHECK: Maker's profile PDA, which must no longer exist; offers with a
+    /// live profile go through `cancel_offer`
+    #[account(
+        seeds = [b"user_profile", maker.key().as_ref()],
+        bump,
+        constraint = user_profile.data_is_empty() @ ErrorCode::ProfileStillOpen,
+    )]
+    pub user_profile: UncheckedAccount<'info>,
+
+    /// CHECK: `MintTvl` of the offered mint, updated by `release_mint_tvl`
+    /// for offers counted in it
+    #[account(mut, seeds = [b"mint_tvl", offer.mint_offered.as_ref()], bump)]
+    pub mint_tvl: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(terms: RelayedOfferTerms)]
+pub struct CreateOfferRelayed<'info> {
+    #[account(
+        init_if_needed,
+        payer = relayer,
+        space = 8 + UserProfile::SIZE,
+        seeds = [b"user_profile", maker.key().as_ref()],
+        bump,
+        // Ahead of `offer`'s init, see `CreateOffer`
+        constraint = offer.data_is_empty() @ ErrorCode::SlotInUse,
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(
+        init,
+        payer = relayer,
+        space = 8 + Offer::space(
+            0,
+            terms.options.blocked_takers.len(),
+            terms.options.fill_queue_len.into(),
+            terms.options.max_acceptors.into(),
+        ),
+        seeds = [
+            b"offer",
+            maker.key().as_ref(),
+            &user_profile.offer_count.to_le_bytes(),
+        ],
+        bump
+    )]
+    pub offer: Account<'info, Offer>,
+
+    #[account(
+        init_if_needed,
+        payer = relayer,
+        seeds = [
+            b"vault",
+            terms.options.vault_key_seed(offer.key(), maker.key()).as_ref(),
+            &terms.options.vault_id_seed(&user_profile.offer_count.to_le_bytes()),
+            mint_offered.key().as_ref(),
+        ],
+        bump,
+        token::mint = mint_offered,
+        token::authority = vault,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = maker_token_account.mint == mint_offered.key() @ ErrorCode::InvalidMint,
+        constraint = maker_token_account.owner == maker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub maker_token_account: Account<'info, TokenAccount>,
+
+    pub mint_offered: Account<'info, Mint>,
+    pub mint_wanted: InterfaceAccount<'info, token_interface::Mint>,
+
+    /// CHECK: Authorizes the offer through the Ed25519 signature over the terms
+    pub maker: UncheckedAccount<'info>,
+
+    /// CHECK: Program PDA the maker approves as delegate of the offered tokens
+    #[account(seeds = [b"delegate"], bump)]
+    pub delegate: UncheckedAccount<'info>,
+
+    /// Submits the transaction and pays for the new accounts
+    #[account(mut)]
+    pub relayer: Signer<'info>,
+
+    /// CHECK: Protocol config PDA; may not be initialized yet, see `load_config`
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+
+    /// CHECK: Pause status PDAs of both mints; may not exist, see
+    /// `check_mints_unpaused`
+    #[account(seeds = [b"mint_status", mint_offered.key().as_ref()], bump)]
+    pub mint_status_offered: UncheckedAccount<'info>,
+    /// CHECK: See `mint_status_offered`
+    #[account(seeds = [b"mint_status", mint_wanted.key().as_ref()], bump)]
+    pub mint_status_wanted: UncheckedAccount<'info>,
+
+    /// CHECK: `MintTvl` of the offered mint, created on its first counted
+    /// offer by `init_mint_tvl`
+    #[account(mut, seeds = [b"mint_tvl", mint_offered.key().as_ref()], bump)]
+    pub mint_tvl: UncheckedAccount<'info>,
+
+    /// CHECK: Treasury wallet, matched against `config.treasury` by
+    /// `collect_listing_fee`; required when a listing fee applies
+    #[account(mut)]
+    pub treasury: Option<UncheckedAccount<'info>>,
+
+    /// Maker's allowlist entry; required while `config.maker_allowlist` is on
+    #[account(
+        seeds = [b"maker_status", maker.key().as_ref()],
+        bump = maker_status.bump,
+    )]
+    pub maker_status: Option<Account<'info, MakerStatus>>,
+
+    /// Allowlist entries of both mints; required while
+    /// `config.mint_allowlist` is on
+    #[account(
+        seeds = [b"allowed_mint", mint_offered.key().as_ref()],
+        bump = allowed_mint_offered.bump,
+    )]
+    pub allowed_mint_offered: Option<Account<'info, AllowedMint>>,
+    #[account(
+        seeds = [b"allowed_mint", mint_wanted.key().as_ref()],
+        bump = allowed_mint_wanted.bump,
+    )]
+    pub allowed_mint_wanted: Option<Account<'info, AllowedMint>>,
+
+    /// CHECK: Instructions sysvar, read to find the Ed25519 verification
+    #[account(address = anchor_lang::solana_program::sysvar::instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateOffer<'info> {
+    /// CHECK: Offer in a possibly outdated layout; its discriminator, maker
+    /// and address are checked by `migrate_offer`
+    #[account(mut, owner = crate::ID)]
+    pub offer: UncheckedAccount<'info>,
+
+    /// Pays the rent for the larger account
+    #[account(mut)]
+    pub maker: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct GetUserOffers<'info> {
+    #[account(
+        seeds = [b"user_profile", user_profile.authority.as_ref()],
+        bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+}
+
+#[derive(Accounts)]
+pub struct GetOfferCapacity<'info> {
+    #[account(
+        seeds = [b"user_profile", user_profile.authority.as_ref()],
+        bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    /// CHECK: Protocol config PDA; may not be initialized yet, see `load_config`
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(amounts: Vec<u64>)]
+pub struct CreateSellBundle<'info> {
+    #[account(
+        init_if_needed,
+        payer = maker,
+        space = 8 + UserProfile::SIZE,
+        seeds = [b"user_profile", maker.key().as_ref()],
+        bump,
+        // Ahead of `bundle`'s init, see `CreateOffer`
+        constraint = bundle.data_is_empty() @ ErrorCode::SlotInUse,
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(
+        init,
+        payer = maker,
+        space = 8 + SellBundle::space(amounts.len()),
+        seeds = [
+            b"bundle",
+            maker.key().as_ref(),
+            &user_profile.offer_count.to_le_bytes(),
+        ],
+        bump
+    )]
+    pub bundle: Account<'info, SellBundle>,
+
+    pub mint_wanted: Account<'info, Mint>,
+
+    /// CHECK: Pause status PDA of the wanted mint; may not exist, see
+    /// `check_mint_unpaused`
+    #[account(seeds = [b"mint_status", mint_wanted.key().as_ref()], bump)]
+    pub mint_status_wanted: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub maker: Signer<'info>,
+
+    /// CHECK: Protocol config PDA; may not be initialized yet, see `load_config`
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+
+    /// CHECK: Treasury wallet, matched against `config.treasury` by
+    /// `collect_listing_fee`; required when a listing fee applies
+    #[account(mut)]
+    pub treasury: Option<UncheckedAccount<'info>>,
+
+    /// Allowlist entry of the wanted mint; required while
+    /// `config.mint_allowlist` is on
+    #[account(
+        seeds = [b"allowed_mint", mint_wanted.key().as_ref()],
+        bump = allowed_mint_wanted.bump,
+    )]
+    pub allowed_mint_wanted: Option<Account<'info, AllowedMint>>,
+
+    /// Maker's allowlist entry; required while `config.maker_allowlist` is on
+    #[account(
+        seeds = [b"maker_status", maker.key().as_ref()],
+        bump = maker_status.bump,
+    )]
+    pub maker_status: Option<Account<'info, MakerStatus>>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer_id: u64)]
+pub struct AcceptSellBundle<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"bundle",
+            maker.key().as_ref(),
+            &offer_id.to_le_bytes(),
+        ],
+        bump = bundle.bump,
+        constraint = bundle.offer_id == offer_id @ ErrorCode::InvalidOfferId,
+        close = maker,
+        has_one = maker @ ErrorCode::Unauthorized,
+    )]
+    pub bundle: Account<'info, SellBundle>,
+
+    /// CHECK: Maker will receive rent refund, validated by has_one in bundle
+    #[account(mut)]
+    pub maker: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"user_profile", maker.key().as_ref()],
+        bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(
+        mut,
+        constraint = maker_token_account_wanted.mint == bundle.mint_wanted
+            @ ErrorCode::WrongWantedMint,
+        constraint = maker_token_account_wanted.owner == maker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub maker_token_account_wanted: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub taker: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = taker_token_account_offered.mint == bundle.mint_wanted
+            @ ErrorCode::WrongWantedMint,
+        constraint = taker_token_account_offered.owner == taker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub taker_token_account_offered: Account<'info, TokenAccount>,
+
+    /// CHECK: Pause status PDA of the wanted mint; may not exist, see
+    /// `check_mint_unpaused`
+    #[account(seeds = [b"mint_status", bundle.mint_wanted.as_ref()], bump)]
+    pub mint_status_wanted: UncheckedAccount<'info>,
+
+    /// CHECK: Protocol config PDA; may not be initialized yet, see `load_config`
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer_id: u64)]
+pub struct CancelSellBundle<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"bundle",
+            maker.key().as_ref(),
+            &offer_id.to_le_bytes(),
+        ],
+        bump = bundle.bump,
+        constraint = bundle.offer_id == offer_id @ ErrorCode::InvalidOfferId,
+        close = maker,
+        has_one = maker @ ErrorCode::Unauthorized,
+    )]
+    pub bundle: Account<'info, SellBundle>,
+
+    #[account(mut)]
+    pub maker: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"user_profile", maker.key().as_ref()],
+        bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CreateDelegatedOffer<'info> {
+    #[account(
+        init_if_needed,
+        payer = maker,
+        space = 8 + UserProfile::SIZE,
+        seeds = [b"user_profile", maker.key().as_ref()],
+        bump,
+        // Ahead of `delegated_offer`'s init, see `CreateOffer`
+        constraint = delegated_offer.data_is_empty() @ ErrorCode::SlotInUse,
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(
+        init,
+        payer = maker,
+        space = 8 + DelegatedOffer::SIZE,
+        seeds = [
+            b"delegated_offer",
+            maker.key().as_ref(),
+            &user_profile.offer_count.to_le_bytes(),
+        ],
+        bump
+    )]
+    pub delegated_offer: Account<'info, DelegatedOffer>,
+
+    #[account(
+        mut,
+        constraint = maker_token_account.owner == maker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub maker_token_account: Account<'info, TokenAccount>,
+
+    pub mint_wanted: Account<'info, Mint>,
+
+    /// CHECK: Pause status PDAs of both mints; may not exist, see
+    /// `check_mints_unpaused`
+    #[account(seeds = [b"mint_status", maker_token_account.mint.as_ref()], bump)]
+    pub mint_status_offered: UncheckedAccount<'info>,
+    /// CHECK: See `mint_status_offered`
+    #[account(seeds = [b"mint_status", mint_wanted.key().as_ref()], bump)]
+    pub mint_status_wanted: UncheckedAccount<'info>,
+
+    /// CHECK: Program-wide delegate PDA, only approved as a delegate here
+    #[account(seeds = [b"delegate"], bump)]
+    pub delegate: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub maker: Signer<'info>,
+
+    /// CHECK: Protocol config PDA; may not be initialized yet, see `load_config`
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+
+    /// CHECK: `MintTvl` of the offered mint, created on its first counted
+    /// offer by `init_mint_tvl`
+    #[account(
+        mut,
+        seeds = [b"mint_tvl", maker_token_account.mint.as_ref()],
+        bump
+    )]
+    pub mint_tvl: UncheckedAccount<'info>,
+
+    /// CHECK: Treasury wallet, matched against `config.treasury` by
+    /// `collect_listing_fee`; required when a listing fee applies
+    #[account(mut)]
+    pub treasury: Option<UncheckedAccount<'info>>,
+
+    /// Allowlist entries of both mints; required while
+    /// `config.mint_allowlist` is on
+    #[account(
+        seeds = [b"allowed_mint", maker_token_account.mint.as_ref()],
+        bump = allowed_mint_offered.bump,
+    )]
+    pub allowed_mint_offered: Option<Account<'info, AllowedMint>>,
+    #[account(
+        seeds = [b"allowed_mint", mint_wanted.key().as_ref()],
+        bump = allowed_mint_wanted.bump,
+    )]
+    pub allowed_mint_wanted: Option<Account<'info, AllowedMint>>,
+
+    /// Maker's allowlist entry; required while `config.maker_allowlist` is on
+    #[account(
+        seeds = [b"maker_status", maker.key().as_ref()],
+        bump = maker_status.bump,
+    )]
+    pub maker_status: Option<Account<'info, MakerStatus>>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer_id: u64)]
+pub struct AcceptDelegatedOffer<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"delegated_offer",
+            maker.key().as_ref(),
+            &offer_id.to_le_bytes(),
+        ],
+        bump = delegated_offer.bump,
+        constraint = delegated_offer.offer_id == offer_id @ ErrorCode::InvalidOfferId,
+        close = maker,
+        has_one = maker @ ErrorCode::Unauthorized,
+    )]
+    pub delegated_offer: Account<'info, DelegatedOffer>,
+
+    /// CHECK: Maker will receive rent refund, validated by has_one in offer
+    #[account(mut)]
+    pub maker: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"user_profile", maker.key().as_ref()],
+        bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(
+        mut,
+        constraint = maker_token_account_offered.key() == delegated_offer.maker_token_account
+            @ ErrorCode::Unauthorized,
+    )]
+    pub maker_token_account_offered: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = maker_token_account_wanted.mint == delegated_offer.mint_wanted
+            @ ErrorCode::WrongWantedMint,
+        constraint = maker_token_account_wanted.owner == maker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub maker_token_account_wanted: Account<'info, TokenAccount>,
+
+    /// CHECK: Program-wide delegate PDA, signs the pull from the maker
+    #[account(seeds = [b"delegate"], bump)]
+    pub delegate: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub taker: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = taker_token_account_offered.mint == delegated_offer.mint_wanted
+            @ ErrorCode::WrongWantedMint,
+        constraint = taker_token_account_offered.owner == taker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub taker_token_account_offered: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = taker_token_account_wanted.mint == delegated_offer.mint_offered
+            @ ErrorCode::WrongOfferedMint,
+    )]
+    pub taker_token_account_wanted: Account<'info, TokenAccount>,
+
+    /// CHECK: `MintTvl` of the offered mint, updated by `release_mint_tvl`
+    #[account(
+        mut,
+        seeds = [b"mint_tvl", delegated_offer.mint_offered.as_ref()],
+        bump
+    )]
+    pub mint_tvl: UncheckedAccount<'info>,
+
+    /// CHECK: Pause status PDAs of both mints; may not exist, see
+    /// `check_mints_unpaused`
+    #[account(seeds = [b"mint_status", delegated_offer.mint_offered.as_ref()], bump)]
+    pub mint_status_offered: UncheckedAccount<'info>,
+    /// CHECK: See `mint_status_offered`
+    #[account(seeds = [b"mint_status", delegated_offer.mint_wanted.as_ref()], bump)]
+    pub mint_status_wanted: UncheckedAccount<'info>,
+
+    /// CHECK: Protocol config PDA; may not be initialized yet, see `load_config`
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer_id: u64)]
+pub struct CancelDelegatedOffer<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"delegated_offer",
+            maker.key().as_ref(),
+            &offer_id.to_le_bytes(),
+        ],
+        bump = delegated_offer.bump,
+        constraint = delegated_offer.offer_id == offer_id @ ErrorCode::InvalidOfferId,
+        close = maker,
+        has_one = maker @ ErrorCode::Unauthorized,
+    )]
+    pub delegated_offer: Account<'info, DelegatedOffer>,
+
+    #[account(
+        mut,
+        constraint = maker_token_account.key() == delegated_offer.maker_token_account
+            @ ErrorCode::Unauthorized,
+    )]
+    pub maker_token_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Program-wide delegate PDA, compared against the approval
+    #[account(seeds = [b"delegate"], bump)]
+    pub delegate: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub maker: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"user_profile", maker.key().as_ref()],
+        bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    /// CHECK: `MintTvl` of the offered mint, updated by `release_mint_tvl`
+    #[account(
+        mut,
+        seeds = [b"mint_tvl", delegated_offer.mint_offered.as_ref()],
+        bump
+    )]
+    pub mint_tvl: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CreateSolOffer<'info> {
+    #[account(
+        init_if_needed,
+        payer = maker,
+        space = 8 + UserProfile::SIZE,
+        seeds = [b"user_profile", maker.key().as_ref()],
+        bump,
+        // Ahead of `sol_offer`'s init, see `CreateOffer`
+        constraint = sol_offer.data_is_empty() @ ErrorCode::SlotInUse,
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(
+        init,
+        payer = maker,
+        space = 8 + SolOffer::SIZE,
+        seeds = [
+            b"sol_offer",
+            maker.key().as_ref(),
+            &user_profile.offer_count.to_le_bytes(),
+        ],
+        bump
+    )]
+    pub sol_offer: Account<'info, SolOffer>,
+
+    pub mint_wanted: Account<'info, Mint>,
+
+    /// CHECK: Pause status PDAs of both mints; may not exist, see
+    /// `check_mints_unpaused`
+    #[account(seeds = [b"mint_status", token::spl_token::native_mint::ID.as_ref()], bump)]
+    pub mint_status_offered: UncheckedAccount<'info>,
+    /// CHECK: See `mint_status_offered`
+    #[account(seeds = [b"mint_status", mint_wanted.key().as_ref()], bump)]
+    pub mint_status_wanted: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub maker: Signer<'info>,
+
+    /// CHECK: Protocol config PDA; may not be initialized yet, see `load_config`
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+
+    /// CHECK: `MintTvl` of the native mint, created on its first counted
+    /// offer by `init_mint_tvl`
+    #[account(
+        mut,
+        seeds = [b"mint_tvl", token::spl_token::native_mint::ID.as_ref()],
+        bump
+    )]
+    pub mint_tvl: UncheckedAccount<'info>,
+
+    /// CHECK: Treasury wallet, matched against `config.treasury` by
+    /// `collect_listing_fee`; required when a listing fee applies
+    #[account(mut)]
+    pub treasury: Option<UncheckedAccount<'info>>,
+
+    /// Maker's allowlist entry; required while `config.maker_allowlist` is on
+    #[account(
+        seeds = [b"maker_status", maker.key().as_ref()],
+        bump = maker_status.bump,
+    )]
+    pub maker_status: Option<Account<'info, MakerStatus>>,
+
+    /// Allowlist entries of the native and wanted mints; required while
+    /// `config.mint_allowlist` is on
+    #[account(
+        seeds = [b"allowed_mint", token::spl_token::native_mint::ID.as_ref()],
+        bump = allowed_mint_offered.bump,
+    )]
+    pub allowed_mint_offered: Option<Account<'info, AllowedMint>>,
+    #[account(
+        seeds = [b"allowed_mint", mint_wanted.key().as_ref()],
+        bump = allowed_mint_wanted.bump,
+    )]
+    pub allowed_mint_wanted: Option<Account<'info, AllowedMint>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer_id: u64)]
+pub struct AcceptSolOffer<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"sol_offer",
+            maker.key().as_ref(),
+            &offer_id.to_le_bytes(),
+        ],
+        bump = sol_offer.bump,
+        constraint = sol_offer.offer_id == offer_id @ ErrorCode::InvalidOfferId,
+        close = maker,
+        has_one = maker @ ErrorCode::Unauthorized,
+    )]
+    pub sol_offer: Account<'info, SolOffer>,
+
+    /// CHECK: Maker will receive rent refund, validated by has_one in offer
+    #[account(mut)]
+    pub maker: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"user_profile", maker.key().as_ref()],
+        bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(
+        mut,
+        constraint = maker_token_account_wanted.mint == sol_offer.mint_wanted
+            @ ErrorCode::WrongWantedMint,
+        constraint = maker_token_account_wanted.owner == maker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub maker_token_account_wanted: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub taker: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = taker_token_account_offered.mint == sol_offer.mint_wanted
+            @ ErrorCode::WrongWantedMint,
+        constraint = taker_token_account_offered.owner == taker.key() @ ErrorCode::Unauthorized,
+    )]
+    pub taker_token_account_offered: Account<'info, TokenAccount>,
+
+    /// CHECK: `MintTvl` of the native mint, updated by `release_mint_tvl`
+    #[account(
+        mut,
+        seeds = [b"mint_tvl", token::spl_token::native_mint::ID.as_ref()],
+        bump
+    )]
+    pub mint_tvl: UncheckedAccount<'info>,
+
+    /// CHECK: Pause status PDAs of both mints; may not exist, see
+    /// `check_mints_unpaused`
+    #[account(seeds = [b"mint_status", token::spl_token::native_mint::ID.as_ref()], bump)]
+    pub mint_status_offered: UncheckedAccount<'info>,
+    /// CHECK: See `mint_status_offered`
+    #[account(seeds = [b"mint_status", sol_offer.mint_wanted.as_ref()], bump)]
+    pub mint_status_wanted: UncheckedAccount<'info>,
+
+    /// CHECK: Protocol config PDA; may not be initialized yet, see `load_config`
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer_id: u64)]
+pub struct CancelSolOffer<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"sol_offer",
+            maker.key().as_ref(),
+            &offer_id.to_le_bytes(),
+        ],
+        bump = sol_offer.bump,
+        constraint = sol_offer.offer_id == offer_id @ ErrorCode::InvalidOfferId,
+        close = maker,
+        has_one = maker @ ErrorCode::Unauthorized,
+    )]
+    pub sol_offer: Account<'info, SolOffer>,
+
+    #[account(mut)]
+    pub maker: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"user_profile", maker.key().as_ref()],
+        bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    /// CHECK: `MintTvl` of the native mint, updated by `release_mint_tvl`
+    #[account(
+        mut,
+        seeds = [b"mint_tvl", token::spl_token::native_mint::ID.as_ref()],
+        bump
+    )]
+    pub mint_tvl: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer_id: u64)]
+pub struct BookmarkOffer<'info> {
+    #[account(
+        seeds = [
+            b"offer",
+            offer.maker.as_ref(),
+            &offer.id_seed(),
+        ],
+        bump = offer.bump,
+        constraint = offer.offer_id == offer_id @ ErrorCode::InvalidOfferId,
+    )]
+    pub offer: Account<'info, Offer>,
+
+    pub user: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer_id: u64)]
+pub struct InspectOffer<'info> {
+    #[account(
+        seeds = [
+            b"offer",
+            maker.key().as_ref(),
+            &offer.id_seed(),
+        ],
+        bump = offer.bump,
+        constraint = offer.offer_id == offer_id @ ErrorCode::InvalidOfferId,
+        has_one = maker @ ErrorCode::Unauthorized,
+    )]
+    pub offer: Account<'info, Offer>,
+
+    #[account(
+        seeds = [
+            b"vault",
+            offer.vault_key_seed(offer.key()).as_ref(),
+            &offer.vault_id_seed(),
+            offer.mint_offered.as_ref(),
+        ],
+        bump = offer.vault_bump,
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    /// CHECK: Only used to derive the offer PDA, validated by has_one in offer
+    pub maker: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ValidateOffer<'info> {
+    pub offer: Account<'info, Offer>,
+
+    /// Checked against the offer by `validate_offer` itself
     pub vault: Account<'info, TokenAccount>,
 
-    /// CHECK: Maker will receive rent refund, validated by has_one in offer
-    #[account(mut)]
-    pub maker: UncheckedAccount<'info>,
+    /// CHECK: Checked against `offer.maker` by `validate_offer` itself
+    pub maker: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct Version {}
+
+#[derive(Accounts)]
+pub struct BatchInspect {}
+
+#[derive(Accounts)]
+#[instruction(offer_id: u64)]
+pub struct SnapshotOffer<'info> {
+    #[account(
+        seeds = [
+            b"offer",
+            offer.maker.as_ref(),
+            &offer.id_seed(),
+        ],
+        bump = offer.bump,
+        constraint = offer.offer_id == offer_id @ ErrorCode::InvalidOfferId,
+    )]
+    pub offer: Account<'info, Offer>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer_id: u64)]
+pub struct QuoteAccept<'info> {
+    #[account(
+        seeds = [
+            b"offer",
+            offer.maker.as_ref(),
+            &offer.id_seed(),
+        ],
+        bump = offer.bump,
+        constraint = offer.offer_id == offer_id @ ErrorCode::InvalidOfferId,
+    )]
+    pub offer: Account<'info, Offer>,
+
+    /// CHECK: Protocol config PDA; may not be initialized yet, see `load_config`
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+
+    /// Owner's holding of the rebate mint, to quote the fee rebate
+    pub maker_rebate_token_account: Option<Account<'info, TokenAccount>>,
+
+    /// CHECK: Pyth price account of an oracle offer, matched against
+    /// `offer.price_feed` and validated by `load_pyth_price`
+    pub price_feed: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: AMM pool of an AMM-priced offer, matched against
+    /// `offer.amm_pool` and validated by `load_pool_reserves`
+    pub amm_pool: Option<UncheckedAccount<'info>>,
+}
+
+// ============================================================================
+// Account Data Structures
+// ============================================================================
+
+#[account]
+pub struct UserProfile {
+    /// The user's wallet address
+    pub authority: Pubkey,    // 32 bytes
+    /// Counter for creating unique offer IDs
+    pub offer_count: u64,     // 8 bytes
+    /// Creation time of the user's latest offer, for rate limiting; never
+    /// moves backwards
+    pub last_offer_ts: i64,   // 8 bytes
+    /// Time of the user's latest cancel of a live offer, for the cooldown
+    pub last_cancel_ts: i64,  // 8 bytes
+    /// Offers the user currently owns, for the per-user cap
+    pub open_offers: u32,     // 4 bytes
+}
+
+impl UserProfile {
+    pub const SIZE: usize = 32 + 8 + 8 + 8 + 4;
+
+    /// Whether the profile was just created and has never been set up
+    pub fn is_fresh(&self) -> bool {
+        self.offer_count == 0 && self.authority == Pubkey::default()
+    }
+
+    /// Set up a fresh profile for `authority`, or check that an existing one
+    /// already belongs to it. The seeds imply as much; the explicit check
+    /// makes any reuse of another user's profile fail loudly.
+    pub fn claim(&mut self, authority: Pubkey) -> Result<()> {
+        if self.is_fresh() {
+            self.authority = authority;
+            msg!("Auto-initialized user profile for {}", authority);
+        }
+        require_keys_eq!(self.authority, authority, ErrorCode::AlreadyInitialized);
+        Ok(())
+    }
+
+    /// Stamp a new offer's creation time, refusing one earlier than the
+    /// previous offer's so the user's offer timeline stays ordered
+    pub fn record_offer_ts(&mut self, now: i64) -> Result<()> {
+        require!(now >= self.last_offer_ts, ErrorCode::ClockWentBackwards);
+        self.last_offer_ts = now;
+        Ok(())
+    }
+
+    /// Count one more open offer, refusing it past `max_open_offers`
+    /// (0 for no cap)
+    pub fn claim_offer_slot(&mut self, max_open_offers: u32) -> Result<()> {
+        require!(
+            self.remaining_offer_slots(max_open_offers) > 0,
+            ErrorCode::TooManyOpenOffers
+        );
+        self.open_offers += 1;
+        Ok(())
+    }
+
+    /// Offers the user may still open under `max_open_offers`; without a cap
+    /// the counter's own limit applies
+    pub fn remaining_offer_slots(&self, max_open_offers: u32) -> u32 {
+        let cap = if max_open_offers == 0 { u32::MAX } else { max_open_offers };
+        cap.saturating_sub(self.open_offers)
+    }
+
+    /// Free the slot of an offer that was filled, cancelled or handed over
+    pub fn release_offer_slot(&mut self) -> Result<()> {
+        self.open_offers = self
+            .open_offers
+            .checked_sub(1)
+            .ok_or(ErrorCode::CounterUnderflow)?;
+        Ok(())
+    }
+}
+
+#[account]
+pub struct Offer {
+    /// Unique offer ID from user's counter
+    pub offer_id: u64,        // 8 bytes
+    /// Offer creator's wallet
+    pub maker: Pubkey,        // 32 bytes
+    /// Token mint being offered
+    pub mint_offered: Pubkey, // 32 bytes
+    /// Token mint being requested
+    pub mint_wanted: Pubkey,  // 32 bytes
+    /// Amount of offered tokens
+    pub amount_offered: u64,  // 8 bytes
+    /// Amount of wanted tokens
+    pub amount_wanted: u64,   // 8 bytes
+    /// PDA bump for vault
+    pub vault_bump: u8,       // 1 byte
+    /// PDA bump for offer account
+    pub bump: u8,             // 1 byte
+    /// Creation timestamp
+    pub created_at: i64,      // 8 bytes
+    /// Caller-supplied PDA seed replacing the counter, if any
+    pub nonce: Option<[u8; 16]>, // 1 + 16 bytes
+    /// Unix timestamp after which the offer can no longer be accepted
+    pub expiry_ts: Option<i64>, // 1 + 8 bytes
+    /// Whether takers may fill only part of the offer
+    pub allow_partial: bool,  // 1 byte
+    /// Marketplace grouping tag, 0 for uncategorized
+    pub category: u8,         // 1 byte
+    /// Taker of the most recent fill, if any
+    pub taker: Option<Pubkey>, // 1 + 32 bytes
+    /// Timestamp of the most recent fill, 0 if never filled
+    pub accepted_at: i64,     // 8 bytes
+    /// Pyth price account the wanted amount floats with, if any
+    pub price_feed: Option<Pubkey>, // 1 + 32 bytes
+    /// Scale applied to the oracle price, in basis points
+    pub multiplier_bps: u16,  // 2 bytes
+    /// Taker holding a reservation, default when unreserved
+    pub reserved_by: Pubkey,  // 32 bytes
+    /// Timestamp the reservation lapses at
+    pub reserved_until: i64,  // 8 bytes
+    /// Decimals of the offered mint, so clients can read prices directly
+    pub decimals_offered: u8, // 1 byte
+    /// Decimals of the wanted mint
+    pub decimals_wanted: u8,  // 1 byte
+    /// Proceeds destination overriding the maker's own token account
+    pub maker_receive_account: Option<Pubkey>, // 1 + 32 bytes
+    /// Account layout version, 0 for offers predating versioning
+    pub version: u8,          // 1 byte
+    /// Unix timestamp the offer becomes takeable at, if scheduled
+    pub active_from_ts: Option<i64>, // 1 + 8 bytes
+    /// Wallet receiving `referrer_bps` of each fill's wanted amount
+    pub referrer: Option<Pubkey>, // 1 + 32 bytes
+    /// Referrer's share, in basis points
+    pub referrer_bps: u16,    // 2 bytes
+    /// Cap on partial fills, 0 for no limit
+    pub max_fills: u16,       // 2 bytes
+    /// Fills taken so far
+    pub fill_count: u16,      // 2 bytes
+    /// Takers' pending price suggestions, one per taker
+    pub counter_offers: Vec<CounterOffer>, // 4 + 40 bytes each, up to MAX_COUNTER_OFFERS
+    /// Only taker allowed to fill, set by `accept_counter`
+    pub locked_taker: Option<Pubkey>, // 1 + 32 bytes
+    /// Whether the vault is derived from the maker and offer ID; see `vault_key_seed`
+    pub maker_derived_vault: bool, // 1 byte
+    /// Remainder below which only whole fills are accepted, 0 to disable
+    pub dust_threshold: u64,  // 8 bytes
+    /// Quotes in other mints accepted instead of `mint_wanted`
+    pub alt_wanted: Vec<WantedQuote>, // 4 + 40 bytes each
+    /// Wallet receiving `royalty_bps` of each fill's wanted amount
+    pub royalty_recipient: Option<Pubkey>, // 1 + 32 bytes
+    /// Creator's royalty, in basis points
+    pub royalty_bps: u16,     // 2 bytes
+    /// Takers barred from the offer
+    pub blocked_takers: Vec<Pubkey>, // 4 + 32 bytes each
+    /// The maker may not cancel or modify the offer before it expires
+    pub sealed: bool,         // 1 byte
+    /// Slot at which the offer can no longer be accepted
+    pub expiry_slot: Option<u64>, // 1 + 8 bytes
+    /// Wallet that controls the offer and receives its proceeds and refunds;
+    /// starts as `maker`, which stays fixed as the PDA seed
+    pub current_owner: Pubkey, // 32 bytes
+    /// Escrow sits in the mint's shared vault, of which the offer may only
+    /// ever move its own `amount_offered`
+    pub omnibus_vault: bool,  // 1 byte
+    /// Completed swaps a taker needs to fill the offer
+    pub min_taker_swaps: u64, // 8 bytes
+    /// Only takers holding a signed `TakerAuthorization` may fill the offer
+    pub requires_authorization: bool, // 1 byte
+    /// Constant-product pool the wanted amount floats with, if any
+    pub amm_pool: Option<Pubkey>, // 1 + 32 bytes
+    /// Most takers `fill_queue` may hold, 0 when the offer has no queue
+    pub fill_queue_capacity: u8, // 1 byte
+    /// Takers lined up for the offer, served in order
+    pub fill_queue: Vec<QueuedTaker>, // 4 + 40 bytes each
+    /// How the price of an exact-out partial fill is rounded
+    pub rounding: RoundingMode, // 1 byte
+    /// Seconds after acceptance before the taker may claim the offered
+    /// tokens, 0 to pay them out at once
+    pub vesting_cliff_secs: i64, // 8 bytes
+    /// Recurring slot range the offer may be taken in, always open if unset
+    pub fill_window: Option<FillWindow>, // 1 + 24 bytes
+    /// Whether `dust_threshold` is in whole tokens of `mint_offered`
+    pub dust_in_whole_tokens: bool, // 1 byte
+    /// Acceptance suspended by the owner through `pause_offer`
+    pub is_paused: bool,      // 1 byte
+    /// Whether this is a regular offer or a taker's request for quote
+    pub request_type: RequestType, // 1 byte
+    /// Hash of the listing's off-chain metadata JSON, zeroed if none
+    pub metadata_hash: [u8; 32], // 32 bytes
+    /// Unix timestamp before which the maker may not withdraw the escrow
+    pub no_cancel_until: Option<i64>, // 1 + 8 bytes
+    /// Amount of `mint_offered` to ask when relisting the proceeds
+    pub auto_relist_amount_wanted: Option<u64>, // 1 + 8 bytes
+    /// Lamports held above rent, paid to the taker who completes the offer
+    pub taker_tip_lamports: u64, // 8 bytes
+    /// Partial fills must pay a multiple of this much `mint_wanted`, 0 for any
+    pub lot_size: u64, // 8 bytes
+    /// Most distinct takers that may fill the offer, 0 for no cap
+    pub max_acceptors: u8, // 1 byte
+    /// Takers that have filled the offer, tracked while it is capped
+    pub acceptors: Vec<Pubkey>, // 4 + 32 bytes each
+    /// Oldest, in slots, a `PriceAttestation` may be to fill the offer; 0
+    /// when fills need none
+    pub attestation_max_age_slots: u64, // 8 bytes
+    /// Whether the escrow is counted in the offered mint's `MintTvl`
+    pub tvl_tracked: bool, // 1 byte
+    /// Least an oracle or AMM reprice may ask for the remaining escrow, 0
+    /// for no floor
+    pub min_acceptable_wanted: u64, // 8 bytes
+    /// Mint takers must hold at least one token of to fill the offer
+    pub gate_mint: Option<Pubkey>, // 1 + 32 bytes
+    /// Taker rebate on each fill, in basis points of the offered amount
+    pub taker_rebate_bps: u16, // 2 bytes
+    /// Rebate pool left in the vault on top of `amount_offered`
+    pub taker_rebate_escrowed: u64, // 8 bytes
+    /// Weighted Pyth feeds the wanted amount floats with, empty unless this
+    /// is an index offer
+    pub index_components: Vec<IndexComponent>, // 4 + 34 bytes each, up to MAX_INDEX_COMPONENTS
+    /// Taker holding the offer through `commit_accept`, if any
+    pub committed_taker: Option<Pubkey>, // 1 + 32 bytes
+    /// Unix timestamp the commit lapses at
+    pub commit_expires_at: i64, // 8 bytes
+}
+
+impl Offer {
+    pub const SIZE: usize = 8 + 32 + 32 + 32 + 8 + 8 + 1 + 1 + 8 + (1 + 16) + (1 + 8) + 1 + 1
+        + (1 + 32) + 8 + (1 + 32) + 2 + 32 + 8 + 1 + 1 + (1 + 32) + 1 + (1 + 8)
+        + (1 + 32) + 2 + 2 + 2 + (4 + MAX_COUNTER_OFFERS * CounterOffer::SIZE)
+        + (1 + 32) + 1 + 8 + 4 + (1 + 32) + 2 + 4 + 1 + (1 + 8) + 32 + 1 + 8 + 1 + (1 + 32) + 1
+        + 4 + 1 + 8 + (1 + FillWindow::SIZE) + 1 + 1 + 1 + 32 + (1 + 8) + (1 + 8) + 8 + 8
+        + 1 + 4 + 8 + 1 + 8 + (1 + 32) + 2 + 8
+        + (4 + MAX_INDEX_COMPONENTS * IndexComponent::SIZE) + (1 + 32) + 8;
+
+    /// Account size for an offer listing `alt_quotes` alternative quotes,
+    /// blocking `blocked_takers` takers, queueing up to `fill_queue_len`
+    /// and tracking up to `max_acceptors` distinct takers
+    pub fn space(
+        alt_quotes: usize,
+        blocked_takers: usize,
+        fill_queue_len: usize,
+        max_acceptors: usize,
+    ) -> usize {
+        Self::SIZE
+            + alt_quotes * WantedQuote::SIZE
+            + blocked_takers * 32
+            + fill_queue_len * QueuedTaker::SIZE
+            + max_acceptors * 32
+    }
+
+    /// Seed that distinguishes this offer among its maker's offers: the
+    /// nonce for nonce-derived offers, otherwise the counter-based ID
+    pub fn id_seed(&self) -> Vec<u8> {
+        match self.nonce {
+            Some(nonce) => nonce.to_vec(),
+            None => self.offer_id.to_le_bytes().to_vec(),
+        }
+    }
+
+    pub fn vault_key_seed(&self, offer_key: Pubkey) -> Pubkey {
+        vault_key_seed(self.maker_derived_vault, self.omnibus_vault, offer_key, self.maker)
+    }
+
+    pub fn vault_id_seed(&self) -> Vec<u8> {
+        vault_id_seed(self.maker_derived_vault, self.omnibus_vault, &self.id_seed())
+    }
+
+    /// Offer address and bump `find_program_address` derives from the seeds
+    pub fn canonical_address(&self) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[b"offer", self.maker.as_ref(), &self.id_seed()], &crate::ID)
+    }
+
+    /// Vault address and bump `find_program_address` derives for the offer
+    /// at `offer_key`
+    pub fn canonical_vault(&self, offer_key: Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[
+                b"vault",
+                self.vault_key_seed(offer_key).as_ref(),
+                &self.vault_id_seed(),
+                self.mint_offered.as_ref(),
+            ],
+            &crate::ID,
+        )
+    }
+
+    /// Expired once either the timestamp or the slot deadline is reached
+    pub fn is_expired(&self, clock: &Clock) -> bool {
+        self.expiry_ts
+            .is_some_and(|expiry_ts| clock.unix_timestamp >= expiry_ts)
+            || self
+                .expiry_slot
+                .is_some_and(|expiry_slot| clock.slot >= expiry_slot)
+    }
+
+    /// Whether the maker is still bound by a seal, which lifts at expiry
+    pub fn is_sealed(&self, clock: &Clock) -> bool {
+        self.sealed && !self.is_expired(clock)
+    }
+
+    pub fn is_cancel_locked(&self, now: i64) -> bool {
+        self.no_cancel_until.is_some_and(|no_cancel_until| now < no_cancel_until)
+    }
+
+    pub fn is_blocked(&self, taker: &Pubkey) -> bool {
+        self.blocked_takers.contains(taker)
+    }
+
+    /// Reject a repriced ask below the maker's `min_acceptable_wanted`
+    pub fn check_maker_floor(&self) -> Result<()> {
+        require!(
+            self.amount_wanted >= self.min_acceptable_wanted,
+            ErrorCode::BelowMakerFloor
+        );
+        Ok(())
+    }
+
+    /// Count `taker` among the offer's acceptors, refusing a new one once
+    /// `max_acceptors` distinct takers have filled it
+    pub fn admit_acceptor(&mut self, taker: Pubkey) -> Result<()> {
+        if self.max_acceptors == 0 || self.acceptors.contains(&taker) {
+            return Ok(());
+        }
+        require!(
+            self.acceptors.len() < usize::from(self.max_acceptors),
+            ErrorCode::AcceptorCapReached
+        );
+        self.acceptors.push(taker);
+        Ok(())
+    }
+
+    /// Reprice the offer to `counter` and lock it to the proposing taker,
+    /// discarding every other pending counter
+    pub fn settle_counter(&mut self, counter: CounterOffer) {
+        self.amount_wanted = counter.amount_wanted;
+        self.locked_taker = Some(counter.taker);
+        self.counter_offers.clear();
+        // The agreed price must not float with the oracle or pool afterwards
+        self.price_feed = None;
+        self.amm_pool = None;
+        self.index_components.clear();
+
+        msg!(
+            "Offer {} repriced to {} for {}",
+            self.offer_id,
+            counter.amount_wanted,
+            counter.taker
+        );
+    }
+
+    pub fn is_reserved(&self, now: i64) -> bool {
+        self.reserved_by != Pubkey::default() && now < self.reserved_until
+    }
+
+    /// Whether a taker's `commit_accept` still holds the offer
+    pub fn is_committed(&self, now: i64) -> bool {
+        self.committed_taker.is_some() && now < self.commit_expires_at
+    }
+
+    /// Whether a reservation or a pending commit holds the offer, so its
+    /// maker may not change it under the taker
+    pub fn is_held(&self, now: i64) -> bool {
+        self.is_reserved(now) || self.is_committed(now)
+    }
+
+    /// `taker` must hold a commit on the offer that hasn't lapsed
+    pub fn check_commit(&self, taker: &Pubkey, now: i64) -> Result<()> {
+        require!(
+            self.committed_taker == Some(*taker),
+            ErrorCode::AcceptNotCommitted
+        );
+        require!(now < self.commit_expires_at, ErrorCode::CommitExpired);
+        Ok(())
+    }
+
+    /// Once no turn is running, reserve the offer for the first queued
+    /// taker still willing to wait, dropping those whose wait ran out
+    pub fn serve_fill_queue(&mut self, now: i64) -> Result<()> {
+        if self.fill_queue_capacity == 0 || self.is_reserved(now) {
+            return Ok(());
+        }
+
+        self.reserved_by = Pubkey::default();
+        self.reserved_until = 0;
+        while !self.fill_queue.is_empty() {
+            let next = self.fill_queue.remove(0);
+            if now < next.wait_until {
+                self.reserved_by = next.taker;
+                self.reserved_until = checked_add_ts(now, FILL_QUEUE_TURN_SECS)?;
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Amount wanted when paying in `mint`, if the offer accepts it
+    pub fn quote_for(&self, mint: &Pubkey) -> Option<u64> {
+        if *mint == self.mint_wanted {
+            return Some(self.amount_wanted);
+        }
+        self.alt_wanted
+            .iter()
+            .find(|quote| quote.mint == *mint)
+            .map(|quote| quote.amount)
+    }
+
+    /// Dust threshold in base units, scaling whole-token thresholds by
+    /// `decimals_offered`; `None` on overflow
+    pub fn dust_threshold_units(&self) -> Option<u64> {
+        if !self.dust_in_whole_tokens {
+            return Some(self.dust_threshold);
+        }
+        10u64
+            .checked_pow(self.decimals_offered as u32)?
+            .checked_mul(self.dust_threshold)
+    }
+
+    pub fn is_dust(&self) -> bool {
+        self.dust_threshold_units()
+            .is_none_or(|threshold| self.amount_offered < threshold)
+    }
+
+    pub fn is_active(&self, now: i64) -> bool {
+        self.active_from_ts.is_none_or(|active_from_ts| now >= active_from_ts)
+    }
+
+    pub fn in_fill_window(&self, slot: u64) -> bool {
+        self.fill_window.is_none_or(|window| window.contains(slot))
+    }
+
+    /// Whether the account was just created and no offer was ever recorded
+    /// in it
+    pub fn is_fresh(&self) -> bool {
+        self.maker == Pubkey::default() && self.amount_offered == 0
+    }
+
+    /// What the vault should hold for the offer: the offered amount plus
+    /// any taker rebate pool
+    pub fn amount_escrowed(&self) -> u64 {
+        self.amount_offered.saturating_add(self.taker_rebate_escrowed)
+    }
+}
+
+#[account]
+pub struct Config {
+    /// Authority allowed to update the config
+    pub admin: Pubkey,            // 32 bytes
+    /// Owner of the token accounts that collect protocol fees
+    pub treasury: Pubkey,         // 32 bytes
+    /// Maker-side fee withheld from the maker's proceeds, in basis points
+    pub protocol_fee_bps: u16,    // 2 bytes
+    /// Fee reduction for makers holding the rebate mint, in basis points
+    pub maker_rebate_bps: u16,    // 2 bytes
+    /// Mint whose holders qualify for the maker rebate
+    pub rebate_mint: Pubkey,      // 32 bytes
+    /// Minimum rebate-mint balance to qualify for the rebate
+    pub rebate_threshold: u64,    // 8 bytes
+    /// Smallest `amount_offered` accepted by `create_offer`
+    pub min_amount_offered: u64,  // 8 bytes
+    /// Smallest `amount_wanted` accepted by `create_offer`
+    pub min_amount_wanted: u64,   // 8 bytes
+    /// Oldest oracle price accepted, 0 for the default
+    pub max_oracle_staleness_secs: i64, // 8 bytes
+    /// Minimum gap between a maker's offer creations, 0 to disable
+    pub min_offer_interval_secs: i64, // 8 bytes
+    /// Maker-only cancellation window after expiry, 0 for the default
+    pub expiry_grace_secs: i64,   // 8 bytes
+    /// Minimum gap between a maker's cancels of live offers, 0 to disable
+    pub min_cancel_interval_secs: i64, // 8 bytes
+    /// Flat fee in lamports charged to the treasury per offer listed
+    pub listing_fee_lamports: u64, // 8 bytes
+    /// Whether only makers approved via `approve_maker` may list
+    pub maker_allowlist: bool,    // 1 byte
+    /// Most offers a user may have open at once, 0 for no cap
+    pub max_offers_per_user: u32, // 4 bytes
+    /// Taker-side fee charged on top of the price, in basis points
+    pub taker_fee_bps: u16,       // 2 bytes
+    /// Program owning the AMM pools offers may be priced from
+    pub amm_program: Pubkey,      // 32 bytes
+    /// Oldest AMM pool update accepted, in slots, 0 for the default
+    pub max_pool_staleness_slots: u64, // 8 bytes
+    /// Lamports per day of extension `extend_expiry` charges, 0 for free
+    pub extension_fee_lamports_per_day: u64, // 8 bytes
+    /// Mint fees are charged in instead of the traded tokens, default for
+    /// the bps fees on the trade
+    pub fee_mint: Pubkey,         // 32 bytes
+    /// Flat fee in `fee_mint` a taker pays per fill in fee-token mode
+    pub fee_token_amount: u64,    // 8 bytes
+    /// Lifetime given to offers created without an expiry, 0 for none
+    pub default_expiry_secs: i64, // 8 bytes
+    /// Set by `declare_emergency`: accepts are blocked and anyone may return
+    /// any offer's escrow to its owner through `emergency_reclaim`
+    pub emergency: bool,          // 1 byte
+    /// Whether only mints added via `add_allowed_mint` may be listed
+    pub mint_allowlist: bool,     // 1 byte
+    /// Volume discounts overriding `protocol_fee_bps`, by ascending threshold
+    pub fee_tiers: Vec<FeeTier>,  // 4 + 10 bytes each, up to MAX_FEE_TIERS
+    /// PDA bump for config account
+    pub bump: u8,                 // 1 byte
+    /// Admin nominated by `propose_admin`, awaiting `accept_admin`
+    pub pending_admin: Option<Pubkey>, // 1 + 32 bytes
+    /// Most escrow any one mint's `MintTvl` may count, 0 for no cap
+    pub max_mint_tvl: u64,        // 8 bytes
+    /// Offered amount above which offers need `commit_accept` before they
+    /// are filled, 0 to disable
+    pub high_value_threshold: u64, // 8 bytes
+    /// How long a commit holds an offer, 0 for the default
+    pub accept_commit_ttl_secs: i64, // 8 bytes
+    /// Whether takers pay a Token-2022 wanted mint's transfer fee on top, so
+    /// every recipient nets its full share; otherwise recipients bear it
+    pub gross_up_transfer_fees: bool, // 1 byte
+    /// Whether accepts load both mint accounts in full instead of only
+    /// matching their keys against the offer, at a cost in CUs
+    pub load_mint_accounts: bool, // 1 byte
+}
+
+impl Config {
+    pub const SIZE: usize = 32 + 32 + 2 + 2 + 32 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 8
+        + 1 + 4 + 2 + 32 + 8 + 8 + 32 + 8 + 8 + 1 + 1 + (4 + MAX_FEE_TIERS * FeeTier::SIZE) + 1
+        + (1 + 32) + 8 + 8 + 8 + 1 + 1;
+
+    /// Highest fee rate any fill may be charged, across all tiers
+    pub fn max_fee_bps(&self) -> u16 {
+        self.fee_tiers
+            .iter()
+            .map(|tier| tier.fee_bps)
+            .fold(self.protocol_fee_bps, u16::max)
+    }
+
+    /// Fee rate for a fill paying `amount_in`: that of the highest tier it
+    /// reaches, or the flat `protocol_fee_bps` below every tier
+    pub fn fee_bps_for(&self, amount_in: u64) -> u16 {
+        self.fee_tiers
+            .iter()
+            .rev()
+            .find(|tier| amount_in >= tier.threshold_amount)
+            .map_or(self.protocol_fee_bps, |tier| tier.fee_bps)
+    }
+}
+
+/// Per-maker allowlist entry consulted while `config.maker_allowlist` is on
+#[account]
+pub struct MakerStatus {
+    /// Maker's wallet
+    pub maker: Pubkey,        // 32 bytes
+    /// Whether the maker may list offers
+    pub approved: bool,       // 1 byte
+    /// PDA bump for the maker status account
+    pub bump: u8,             // 1 byte
+}
+
+impl MakerStatus {
+    pub const SIZE: usize = 32 + 1 + 1;
+}
+
+/// Per-mint allowlist entry consulted while `config.mint_allowlist` is on
+#[account]
+pub struct AllowedMint {
+    /// Mint that may be offered or wanted
+    pub mint: Pubkey,         // 32 bytes
+    /// PDA bump for the allowed mint account
+    pub bump: u8,             // 1 byte
+}
+
+impl AllowedMint {
+    pub const SIZE: usize = 32 + 1;
+}
+
+/// Per-mint trading switch set through `set_mint_paused`
+#[account]
+pub struct MintStatus {
+    /// Mint the status applies to
+    pub mint: Pubkey,         // 32 bytes
+    /// Whether offers in the mint are halted
+    pub paused: bool,         // 1 byte
+    /// PDA bump for the mint status account
+    pub bump: u8,             // 1 byte
+}
+
+impl MintStatus {
+    pub const SIZE: usize = 32 + 1 + 1;
+}
+
+/// Escrow of a mint locked across the offers counted in it, for dashboards
+/// to read without scanning every offer
+#[account]
+pub struct MintTvl {
+    /// Offered mint the total is kept for
+    pub mint: Pubkey,         // 32 bytes
+    /// Tokens of the mint currently escrowed by counted offers
+    pub total_locked: u64,    // 8 bytes
+    /// PDA bump for the mint TVL account
+    pub bump: u8,             // 1 byte
+}
+
+impl MintTvl {
+    pub const SIZE: usize = 32 + 8 + 1;
+}
+
+/// Track record of a taker, for offers gated on `min_taker_swaps`
+#[account]
+pub struct TakerStats {
+    /// Taker's wallet
+    pub taker: Pubkey,        // 32 bytes
+    /// Fills the taker has completed, partial ones included
+    pub completed_swaps: u64, // 8 bytes
+}
+
+impl TakerStats {
+    pub const SIZE: usize = 32 + 8;
+}
+
+/// Protocol-wide counters
+#[account]
+pub struct GlobalStats {
+    /// Fees withdrawn from the treasury, in raw units summed across mints
+    pub fees_withdrawn: u64, // 8 bytes
+    /// Number of fee withdrawals
+    pub withdrawals: u64,    // 8 bytes
+    pub bump: u8,            // 1 byte
+}
+
+impl GlobalStats {
+    pub const SIZE: usize = 8 + 8 + 1;
+}
+
+/// Loyalty points a taker has earned through `accept_with_loyalty`
+#[account]
+pub struct LoyaltyAccount {
+    /// Taker's wallet
+    pub taker: Pubkey, // 32 bytes
+    /// Protocol fees paid through loyalty accepts, in raw units summed across
+    /// mints
+    pub points: u64,   // 8 bytes
+    pub bump: u8,      // 1 byte
+}
+
+impl LoyaltyAccount {
+    pub const SIZE: usize = 32 + 8 + 1;
+}
+
+/// Holds a taker's deposit while they are in an offer's fill queue
+#[account]
+pub struct QueueTicket {
+    pub offer: Pubkey,        // 32 bytes
+    pub taker: Pubkey,        // 32 bytes
+    pub bump: u8,             // 1 byte
+}
+
+impl QueueTicket {
+    pub const SIZE: usize = 32 + 32 + 1;
+}
+
+/// Holds a taker's deposit while they hold a reservation on an offer
+#[account]
+pub struct ReservationDeposit {
+    pub offer: Pubkey,        // 32 bytes
+    pub taker: Pubkey,        // 32 bytes
+    /// Offer owner at reservation time, paid the deposit if it is forfeited
+    pub owner: Pubkey,        // 32 bytes
+    /// End of the reservation; accepting after it forfeits the deposit
+    pub settlement_deadline: i64, // 8 bytes
+    pub bump: u8,             // 1 byte
+}
+
+impl ReservationDeposit {
+    pub const SIZE: usize = 32 + 32 + 32 + 8 + 1;
+}
+
+/// Offered tokens a taker bought through `accept_with_vesting`, held in the
+/// claim's vesting vault until `unlock_ts`
+#[account]
+pub struct VestingClaim {
+    pub offer: Pubkey,        // 32 bytes
+    pub taker: Pubkey,        // 32 bytes
+    /// Offered mint the vault holds
+    pub mint: Pubkey,         // 32 bytes
+    pub amount: u64,          // 8 bytes
+    /// Unix timestamp from which `claim_vested` releases the tokens
+    pub unlock_ts: i64,       // 8 bytes
+    pub bump: u8,             // 1 byte
+    pub vault_bump: u8,       // 1 byte
+}
+
+impl VestingClaim {
+    pub const SIZE: usize = 32 + 32 + 32 + 8 + 8 + 1 + 1;
+}
+
+#[account]
+pub struct SellBundle {
+    /// Offer ID from the maker's counter, shared with regular offers
+    pub offer_id: u64,        // 8 bytes
+    /// Bundle creator's wallet
+    pub maker: Pubkey,        // 32 bytes
+    /// Token mint wanted for the whole basket
+    pub mint_wanted: Pubkey,  // 32 bytes
+    /// Amount of the wanted token
+    pub amount_wanted: u64,   // 8 bytes
+    /// PDA bump for the bundle account
+    pub bump: u8,             // 1 byte
+    /// Unix timestamp of bundle creation
+    pub created_at: i64,      // 8 bytes
+    /// Unix timestamp after which the bundle can no longer be accepted
+    pub expiry_ts: Option<i64>, // 1 + 8 bytes
+    /// Escrowed tokens, one vault each
+    pub items: Vec<BundleItem>, // 4 + n * BundleItem::SIZE bytes
+}
+
+impl SellBundle {
+    /// Account size for a basket of `items` tokens
+    pub fn space(items: usize) -> usize {
+        8 + 32 + 32 + 8 + 1 + 8 + (1 + 8) + 4 + items * BundleItem::SIZE
+    }
+}
+
+/// One escrowed token of a `SellBundle`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct BundleItem {
+    pub mint: Pubkey,         // 32 bytes
+    pub amount: u64,          // 8 bytes
+    pub vault_bump: u8,       // 1 byte
+}
+
+impl BundleItem {
+    pub const SIZE: usize = 32 + 8 + 1;
+}
+
+/// Offer filled from the maker's own token account through a delegate
+/// approval instead of an escrow vault
+#[account]
+pub struct DelegatedOffer {
+    /// Offer ID from the maker's counter, shared with regular offers
+    pub offer_id: u64,        // 8 bytes
+    /// Offer creator's wallet
+    pub maker: Pubkey,        // 32 bytes
+    /// Maker's token account the offered tokens are pulled from
+    pub maker_token_account: Pubkey, // 32 bytes
+    /// Token mint being offered
+    pub mint_offered: Pubkey, // 32 bytes
+    /// Token mint wanted in exchange
+    pub mint_wanted: Pubkey,  // 32 bytes
+    /// Amount of the offered token
+    pub amount_offered: u64,  // 8 bytes
+    /// Amount of the wanted token
+    pub amount_wanted: u64,   // 8 bytes
+    /// PDA bump for the delegated offer account
+    pub bump: u8,             // 1 byte
+    /// Unix timestamp of offer creation
+    pub created_at: i64,      // 8 bytes
+    /// Unix timestamp after which the offer can no longer be accepted
+    pub expiry_ts: Option<i64>, // 1 + 8 bytes
+}
+
+impl DelegatedOffer {
+    pub const SIZE: usize = 8 + 32 + 32 + 32 + 32 + 8 + 8 + 1 + 8 + (1 + 8);
+}
+
+/// Offer of plain SOL, escrowed as lamports on the offer PDA itself
+#[account]
+pub struct SolOffer {
+    /// Offer ID from the maker's counter, shared with regular offers
+    pub offer_id: u64,        // 8 bytes
+    /// Offer creator's wallet
+    pub maker: Pubkey,        // 32 bytes
+    /// Token mint wanted in exchange
+    pub mint_wanted: Pubkey,  // 32 bytes
+    /// Lamports escrowed on top of the account's rent
+    pub lamports_offered: u64, // 8 bytes
+    /// Amount of the wanted token
+    pub amount_wanted: u64,   // 8 bytes
+    /// PDA bump for the SOL offer account
+    pub bump: u8,             // 1 byte
+    /// Unix timestamp of offer creation
+    pub created_at: i64,      // 8 bytes
+    /// Unix timestamp after which the offer can no longer be accepted
+    pub expiry_ts: Option<i64>, // 1 + 8 bytes
+}
+
+impl SolOffer {
+    pub const SIZE: usize = 8 + 32 + 32 + 8 + 8 + 1 + 8 + (1 + 8);
+}
+
+/// Core fields of an offer, as returned by `batch_inspect`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct OfferSummary {
+    pub offer_id: u64,        // 8 bytes
+    pub amount_offered: u64,  // 8 bytes
+    pub amount_wanted: u64,   // 8 bytes
+    pub maker: Pubkey,        // 32 bytes
+}
+
+impl OfferSummary {
+    pub const SIZE: usize = 8 + 8 + 8 + 32;
+}
+
+/// Amounts a fill settled, set as return data by the accept instructions
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct FillResult {
+    /// Wanted tokens the taker paid, taker fee included
+    pub paid: u64,
+    /// Offered tokens the taker received, taker rebate included
+    pub received: u64,
+    /// Protocol fee out of the payment, maker and taker side together; 0 in
+    /// fee-token mode, whose flat fee is paid in `config.fee_mint`
+    pub fee: u64,
+}
+
+/// Room left on a user's offer counters, as returned by `get_offer_capacity`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct OfferCapacity {
+    pub open_offers: u32,
+    /// Offers the user may open before hitting the cap or counter limit
+    pub remaining_open_offers: u32,
+    /// Offer IDs left before `offer_count` is exhausted
+    pub remaining_offer_ids: u64,
+}
+
+/// Transfers a full accept would make, as returned by `quote_accept`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct AcceptQuote {
+    /// Price the taker pays in the wanted mint, before `taker_fee`
+    pub amount_in: u64,
+    /// Offered tokens the taker receives
+    pub amount_out: u64,
+    pub fee: u64,
+    pub taker_fee: u64,
+    pub referral: u64,
+    pub royalty: u64,
+    pub maker_proceeds: u64,
+    /// Rebate the maker pays the taker on top of `amount_out`
+    pub taker_rebate: u64,
+}
+
+/// Admin-settable fields of `Config`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct ConfigParams {
+    pub treasury: Pubkey,
+    pub protocol_fee_bps: u16,
+    pub maker_rebate_bps: u16,
+    pub rebate_mint: Pubkey,
+    pub rebate_threshold: u64,
+    pub min_amount_offered: u64,
+    pub min_amount_wanted: u64,
+    pub max_oracle_staleness_secs: i64,
+    pub min_offer_interval_secs: i64,
+    pub expiry_grace_secs: i64,
+    pub min_cancel_interval_secs: i64,
+    pub listing_fee_lamports: u64,
+    pub maker_allowlist: bool,
+    pub max_offers_per_user: u32,
+    pub taker_fee_bps: u16,
+    pub amm_program: Pubkey,
+    pub max_pool_staleness_slots: u64,
+    pub extension_fee_lamports_per_day: u64,
+    pub fee_mint: Pubkey,
+    pub fee_token_amount: u64,
+    pub default_expiry_secs: i64,
+    pub mint_allowlist: bool,
+    pub fee_tiers: Vec<FeeTier>,
+    pub max_mint_tvl: u64,
+    pub high_value_threshold: u64,
+    pub accept_commit_ttl_secs: i64,
+    pub gross_up_transfer_fees: bool,
+    pub load_mint_accounts: bool,
+}
+
+impl ConfigParams {
+    fn validate(&self) -> Result<()> {
+        require!(
+            self.protocol_fee_bps <= BPS_DENOMINATOR,
+            ErrorCode::InvalidFeeBps
+        );
+        require!(
+            self.maker_rebate_bps <= BPS_DENOMINATOR,
+            ErrorCode::InvalidFeeBps
+        );
+        require!(
+            self.taker_fee_bps <= BPS_DENOMINATOR,
+            ErrorCode::InvalidFeeBps
+        );
+        require!(
+            (0..=MAX_OFFER_LIFETIME_SECS).contains(&self.default_expiry_secs),
+            ErrorCode::InvalidExpiry
+        );
+        require!(
+            (0..=MAX_RESERVATION_SECS).contains(&self.accept_commit_ttl_secs),
+            ErrorCode::InvalidReservation
+        );
+        require!(
+            self.fee_tiers.len() <= MAX_FEE_TIERS,
+            ErrorCode::InvalidFeeTiers
+        );
+        require!(
+            self.fee_tiers.iter().all(|tier| tier.fee_bps <= BPS_DENOMINATOR),
+            ErrorCode::InvalidFeeBps
+        );
+        // Strictly ascending thresholds keep every tier's range distinct
+        require!(
+            self.fee_tiers
+                .windows(2)
+                .all(|pair| pair[0].threshold_amount < pair[1].threshold_amount),
+            ErrorCode::InvalidFeeTiers
+        );
+        Ok(())
+    }
+
+    fn apply(&self, config: &mut Config) {
+        config.treasury = self.treasury;
+        config.protocol_fee_bps = self.protocol_fee_bps;
+        config.maker_rebate_bps = self.maker_rebate_bps;
+        config.rebate_mint = self.rebate_mint;
+        config.rebate_threshold = self.rebate_threshold;
+        config.min_amount_offered = self.min_amount_offered;
+        config.min_amount_wanted = self.min_amount_wanted;
+        config.max_oracle_staleness_secs = self.max_oracle_staleness_secs;
+        config.min_offer_interval_secs = self.min_offer_interval_secs;
+        config.expiry_grace_secs = self.expiry_grace_secs;
+        config.min_cancel_interval_secs = self.min_cancel_interval_secs;
+        config.listing_fee_lamports = self.listing_fee_lamports;
+        config.maker_allowlist = self.maker_allowlist;
+        config.mint_allowlist = self.mint_allowlist;
+        config.max_offers_per_user = self.max_offers_per_user;
+        config.taker_fee_bps = self.taker_fee_bps;
+        config.amm_program = self.amm_program;
+        config.max_pool_staleness_slots = self.max_pool_staleness_slots;
+        config.extension_fee_lamports_per_day = self.extension_fee_lamports_per_day;
+        config.fee_mint = self.fee_mint;
+        config.fee_token_amount = self.fee_token_amount;
+        config.default_expiry_secs = self.default_expiry_secs;
+        config.fee_tiers = self.fee_tiers.clone();
+        config.max_mint_tvl = self.max_mint_tvl;
+        config.high_value_threshold = self.high_value_threshold;
+        config.accept_commit_ttl_secs = self.accept_commit_ttl_secs;
+        config.gross_up_transfer_fees = self.gross_up_transfer_fees;
+        config.load_mint_accounts = self.load_mint_accounts;
+    }
+}
+
+/// Optional settings a maker can attach when creating an offer
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct OfferOptions {
+    /// Unix timestamp after which the offer can no longer be accepted
+    pub expiry_ts: Option<i64>,
+    /// Whether takers may fill only part of the offer
+    pub allow_partial: bool,
+    /// Marketplace grouping tag, up to `MAX_OFFER_CATEGORY`
+    pub category: u8,
+    /// Token account of the wanted mint to receive proceeds instead of one
+    /// owned by the maker
+    pub maker_receive_account: Option<Pubkey>,
+    /// Price the maker means to list at, in wanted tokens per offered token
+    /// scaled by `PRICE_SCALE`; guards against fat-fingered amounts
+    pub expected_price: Option<u64>,
+    /// Allowed deviation from `expected_price`, in basis points
+    pub tolerance_bps: u16,
+    /// Unix timestamp before which the offer cannot be accepted
+    pub active_from_ts: Option<i64>,
+    /// Wallet paid a share of each fill's proceeds for referring the maker
+    pub referrer: Option<Pubkey>,
+    /// Referrer's share of the wanted amount, in basis points
+    pub referrer_bps: u16,
+    /// Most partial fills the offer accepts, 0 for no limit
+    pub max_fills: u16,
+    /// Derive the vault from `[b"vault", maker, offer_id, mint_offered]`
+    /// instead of the offer address
+    pub maker_derived_vault: bool,
+    /// Remainder below which the offer may only be taken whole, 0 to disable
+    pub dust_threshold: u64,
+    /// Original creator paid a royalty on each fill, e.g. of an NFT
+    pub royalty_recipient: Option<Pubkey>,
+    /// Creator's royalty on the wanted amount, in basis points
+    pub royalty_bps: u16,
+    /// Takers who may never fill or reserve the offer, up to
+    /// `MAX_BLOCKED_TAKERS`
+    pub blocked_takers: Vec<Pubkey>,
+    /// Bar the maker from cancelling or modifying the offer until it
+    /// expires; requires `expiry_ts` or `expiry_slot`
+    pub sealed: bool,
+    /// Slot from which the offer can no longer be accepted; with `expiry_ts`
+    /// also set, whichever comes first applies
+    pub expiry_slot: Option<u64>,
+    /// Escrow in the mint's shared vault `[b"vault", program_id,
+    /// OMNIBUS_VAULT_SEED, mint_offered]` instead of a vault of the offer's
+    /// own; takes precedence over `maker_derived_vault`
+    pub omnibus_vault: bool,
+    /// Completed swaps, per `TakerStats`, a taker needs to fill the offer;
+    /// 0 lets anyone fill it
+    pub min_taker_swaps: u64,
+    /// Only let takers holding a `TakerAuthorization` signed by the owner
+    /// fill the offer, through `accept_offer_authorized`
+    pub requires_authorization: bool,
+    /// Takers that may line up through `join_fill_queue`, up to
+    /// `MAX_FILL_QUEUE_LEN`; 0 leaves the offer open to anyone
+    pub fill_queue_len: u8,
+    /// How the price of an exact-out partial fill is rounded
+    pub rounding: RoundingMode,
+    /// Hold the offered tokens for this long after acceptance, up to
+    /// `MAX_ESCROW_AGE_SECS`; such offers are taken through
+    /// `accept_with_vesting`
+    pub vesting_cliff_secs: i64,
+    /// Only let the offer be taken in this recurring slot range, e.g. to
+    /// match the maker's operating hours
+    pub fill_window: Option<FillWindow>,
+    /// Express `dust_threshold` in whole tokens, so the same threshold
+    /// means the same amount whatever the offered mint's decimals
+    pub dust_in_whole_tokens: bool,
+    /// Hash of off-chain JSON describing the listing (name, description,
+    /// image), for frontends to verify what they fetch; zeroed if none. The
+    /// program never interprets it.
+    pub metadata_hash: [u8; 32],
+    /// Commit to not cancelling or withdrawing from the offer before this
+    /// Unix timestamp, at most `MAX_CANCEL_LOCK_SECS` ahead; unlike
+    /// `sealed` it needs no expiry
+    pub no_cancel_until: Option<i64>,
+    /// Relist the proceeds as a new offer wanting this much of the offered
+    /// mint back; such offers are only taken in full through
+    /// `accept_and_relist`
+    pub auto_relist_amount_wanted: Option<u64>,
+    /// Lamports the maker escrows on the offer account for whoever completes
+    /// the offer; refunded with the rent if the offer closes otherwise
+    pub taker_tip_lamports: u64,
+    /// Round lot, in `mint_wanted` base units, partial fills must pay a
+    /// multiple of; the final remainder is exempt. Partial offers only, 0 for
+    /// no lots.
+    pub lot_size: u64,
+    /// Stop new takers once this many distinct takers have filled the
+    /// offer, up to `MAX_ACCEPTORS`; those who already have may keep
+    /// filling. 0 for no cap.
+    pub max_acceptors: u8,
+    /// Only let the offer be filled against a `PriceAttestation` of its
+    /// current ask signed by the owner at most this many slots earlier,
+    /// through `accept_offer_attested`; 0 for no attestation
+    pub attestation_max_age_slots: u64,
+    /// For oracle and AMM offers, the least the whole escrow may be repriced
+    /// to; acceptances priced lower are refused, and the floor scales down
+    /// with partial fills. 0 for no floor.
+    pub min_acceptable_wanted: u64,
+    /// Only let takers holding at least one token of this mint, such as a
+    /// community's NFT collection, fill the offer; open to anyone if unset
+    pub gate_mint: Option<Pubkey>,
+    /// Extra share of each fill's offered amount paid to the taker, in basis
+    /// points, out of a rebate pool the maker escrows on top of the offer
+    pub taker_rebate_bps: u16,
+}
+
+impl OfferOptions {
+    pub fn vault_key_seed(&self, offer_key: Pubkey, maker: Pubkey) -> Pubkey {
+        vault_key_seed(self.maker_derived_vault, self.omnibus_vault, offer_key, maker)
+    }
+
+    pub fn vault_id_seed(&self, id_seed: &[u8]) -> Vec<u8> {
+        vault_id_seed(self.maker_derived_vault, self.omnibus_vault, id_seed)
+    }
+}
+
+/// Offer terms a maker signs off-chain for `create_offer_relayed`; the
+/// signed message is their Borsh encoding
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct RelayedOfferTerms {
+    /// The maker's next counter-based offer ID
+    pub offer_id: u64,
+    pub mint_offered: Pubkey,
+    pub mint_wanted: Pubkey,
+    pub amount_offered: u64,
+    pub amount_wanted: u64,
+    pub options: OfferOptions,
+}
+
+/// Permission an offer's owner signs off-chain for one taker to take the
+/// offer through `accept_offer_authorized`; the signed message is its Borsh
+/// encoding
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct TakerAuthorization {
+    pub offer: Pubkey,
+    pub taker: Pubkey,
+    /// Unix timestamp from which the authorization may be used
+    pub valid_from: i64,
+    /// Unix timestamp at which the authorization lapses
+    pub valid_until: i64,
+}
+
+/// Current ask an offer's owner signs off-chain, so takers cannot fill the
+/// offer at a stale price through `accept_offer_attested`; the signed message
+/// is its Borsh encoding
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct PriceAttestation {
+    pub offer: Pubkey,
+    /// Offer's `amount_wanted` the owner stands by
+    pub amount_wanted: u64,
+    /// Slot at which the owner signed
+    pub slot: u64,
+}
 
-    #[account(
-        mut,
-        constraint = maker_token_account_wanted.mint == offer.mint_wanted @ ErrorCode::InvalidMint,
-        constraint = maker_token_account_wanted.owner == maker.key() @ ErrorCode::Unauthorized,
-    )]
-    pub maker_token_account_wanted: Account<'info, TokenAccount>,
+/// Rounding applied to the price of a partial fill
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RoundingMode {
+    /// In the maker's favour, so no value leaks to takers
+    #[default]
+    Up,
+    /// In the taker's favour
+    Down,
+    /// To the closest unit, halves going up
+    Nearest,
+}
 
-    #[account(mut)]
-    pub taker: Signer<'info>,
+/// Which side of the market an offer account was opened from
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RequestType {
+    /// A maker escrows what they sell, see `create_offer`
+    #[default]
+    Offer,
+    /// A would-be taker escrows what they pay, see `create_request`
+    Request,
+}
 
-    #[account(
-        mut,
-        constraint = taker_token_account_wanted.mint == offer.mint_offered @ ErrorCode::InvalidMint,
-        constraint = taker_token_account_wanted.owner == taker.key() @ ErrorCode::Unauthorized,
-    )]
-    pub taker_token_account_wanted: Account<'info, TokenAccount>,
+/// Recurring slot range an offer may be taken in: the slots whose offset
+/// into each `period_slots`-long cycle falls in `[start, start + len)`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct FillWindow {
+    pub period_slots: u64,    // 8 bytes
+    pub start: u64,           // 8 bytes
+    pub len: u64,             // 8 bytes
+}
 
-    #[account(
-        mut,
-        constraint = taker_token_account_offered.mint == offer.mint_wanted @ ErrorCode::InvalidMint,
-        constraint = taker_token_account_offered.owner == taker.key() @ ErrorCode::Unauthorized,
-    )]
-    pub taker_token_account_offered: Account<'info, TokenAccount>,
+impl FillWindow {
+    pub const SIZE: usize = 8 + 8 + 8;
 
-    pub mint_offered: Account<'info, Mint>,
-    pub mint_wanted: Account<'info, Mint>,
+    pub fn is_valid(&self) -> bool {
+        self.len > 0
+            && self
+                .start
+                .checked_add(self.len)
+                .is_some_and(|end| end <= self.period_slots)
+    }
 
-    pub token_program: Program<'info, Token>,
+    pub fn contains(&self, slot: u64) -> bool {
+        let offset = slot % self.period_slots;
+        offset >= self.start && offset - self.start < self.len
+    }
 }
 
-#[derive(Accounts)]
-#[instruction(offer_id: u64)]
-pub struct CancelOffer<'info> {
-    #[account(
-        mut,
-        seeds = [
-            b"offer",
-            maker.key().as_ref(),
-            &offer_id.to_le_bytes(),
-        ],
-        bump = offer.bump,
-        close = maker,
-        has_one = maker @ ErrorCode::Unauthorized,
-    )]
-    pub offer: Account<'info, Offer>,
+/// Taker waiting in an offer's fill queue
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct QueuedTaker {
+    pub taker: Pubkey,        // 32 bytes
+    /// Unix timestamp after which the taker is skipped instead of served
+    pub wait_until: i64,      // 8 bytes
+}
 
-    #[account(
-        mut,
-        seeds = [
-            b"vault",
-            offer.key().as_ref(),
-            mint_offered.key().as_ref(),
-        ],
-        bump = offer.vault_bump,
-    )]
-    pub vault: Account<'info, TokenAccount>,
+impl QueuedTaker {
+    pub const SIZE: usize = 32 + 8;
+}
 
-    #[account(
-        mut,
-        constraint = maker_token_account.mint == offer.mint_offered @ ErrorCode::InvalidMint,
-        constraint = maker_token_account.owner == maker.key() @ ErrorCode::Unauthorized,
-    )]
-    pub maker_token_account: Account<'info, TokenAccount>,
+/// Price suggested by a prospective taker through `propose_counter`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct CounterOffer {
+    pub taker: Pubkey,        // 32 bytes
+    pub amount_wanted: u64,   // 8 bytes
+}
 
-    pub mint_offered: Account<'info, Mint>,
+impl CounterOffer {
+    pub const SIZE: usize = 32 + 8;
+}
 
-    #[account(mut)]
-    pub maker: Signer<'info>,
+/// One Pyth feed of an index offer and its share of the price
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct IndexComponent {
+    pub price_feed: Pubkey,   // 32 bytes
+    /// Share of the index, in basis points
+    pub weight_bps: u16,      // 2 bytes
+}
 
-    pub token_program: Program<'info, Token>,
+impl IndexComponent {
+    pub const SIZE: usize = 32 + 2;
+}
+
+/// A mint an offer accepts payment in and the amount it wants of it
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct WantedQuote {
+    pub mint: Pubkey,         // 32 bytes
+    pub amount: u64,          // 8 bytes
+}
+
+impl WantedQuote {
+    pub const SIZE: usize = 32 + 8;
+}
+
+/// Fee rate applied to fills paying at least `threshold_amount`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct FeeTier {
+    pub threshold_amount: u64, // 8 bytes
+    pub fee_bps: u16,         // 2 bytes
+}
+
+impl FeeTier {
+    pub const SIZE: usize = 8 + 2;
 }
 
 // ============================================================================
-// Account Data Structures
+// Events
 // ============================================================================
 
-#[account]
-pub struct UserProfile {
-    /// The user's wallet address
-    pub authority: Pubkey,    // 32 bytes
-    /// Counter for creating unique offer IDs
-    pub offer_count: u64,     // 8 bytes
+#[event]
+pub struct OfferCreated {
+    pub offer: Pubkey,
+    pub maker: Pubkey,
+    pub offer_id: u64,
+    pub mint_offered: Pubkey,
+    pub mint_wanted: Pubkey,
+    pub amount_offered: u64,
+    pub amount_wanted: u64,
+    pub category: u8,
+    pub decimals_offered: u8,
+    pub decimals_wanted: u8,
+    pub active_from_ts: Option<i64>,
+    pub metadata_hash: [u8; 32],
 }
 
-impl UserProfile {
-    pub const SIZE: usize = 32 + 8;
+#[event]
+pub struct OfferAccepted {
+    pub offer: Pubkey,
+    pub maker: Pubkey,
+    pub offer_id: u64,
+    pub taker: Pubkey,
+    pub amount_in: u64,
+    pub amount_out: u64,
+    /// Maker-side fee, withheld from the maker's proceeds
+    pub fee: u64,
+    /// Taker-side fee, paid on top of `amount_in`
+    pub taker_fee: u64,
+    /// Flat fee paid in `config.fee_mint`, 0 outside fee-token mode
+    pub fee_token_amount: u64,
+    pub accepted_at: i64,
+    /// Offered amount left after this fill, 0 once fully filled
+    pub remaining_offered: u64,
+    /// Wanted amount left after this fill, 0 once fully filled
+    pub remaining_wanted: u64,
 }
 
-#[account]
-pub struct Offer {
-    /// Unique offer ID from user's counter
-    pub offer_id: u64,        // 8 bytes
-    /// Offer creator's wallet
-    pub maker: Pubkey,        // 32 bytes
-    /// Token mint being offered
-    pub mint_offered: Pubkey, // 32 bytes
-    /// Token mint being requested
-    pub mint_wanted: Pubkey,  // 32 bytes
-    /// Amount of offered tokens
-    pub amount_offered: u64,  // 8 bytes
-    /// Amount of wanted tokens
-    pub amount_wanted: u64,   // 8 bytes
-    /// PDA bump for vault
-    pub vault_bump: u8,       // 1 byte
-    /// PDA bump for offer account
-    pub bump: u8,             // 1 byte
-    /// Creation timestamp
-    pub created_at: i64,      // 8 bytes
+#[event]
+pub struct OfferCancelled {
+    pub offer: Pubkey,
+    pub maker: Pubkey,
+    pub offer_id: u64,
+    pub amount_returned: u64,
 }
 
-impl Offer {
-    pub const SIZE: usize = 8 + 32 + 32 + 32 + 8 + 8 + 1 + 1 + 8;
+#[event]
+pub struct OfferWantedMintChanged {
+    pub offer: Pubkey,
+    pub maker: Pubkey,
+    pub offer_id: u64,
+    pub old_mint_wanted: Pubkey,
+    pub new_mint_wanted: Pubkey,
+    pub new_amount_wanted: u64,
+}
+
+#[event]
+pub struct OfferExtended {
+    pub offer: Pubkey,
+    pub maker: Pubkey,
+    pub offer_id: u64,
+    pub old_expiry_ts: i64,
+    pub new_expiry_ts: i64,
+    pub fee: u64,
+}
+
+#[event]
+pub struct OffersMatched {
+    pub offer_a: Pubkey,
+    pub offer_b: Pubkey,
+    pub amount_a: u64,
+    pub amount_b: u64,
+    pub keeper: Pubkey,
+    pub remaining_a: u64,
+    pub remaining_b: u64,
+}
+
+#[event]
+pub struct OfferOwnershipTransferred {
+    pub offer: Pubkey,
+    pub maker: Pubkey,
+    pub offer_id: u64,
+    pub old_owner: Pubkey,
+    pub new_owner: Pubkey,
+}
+
+#[event]
+pub struct UserOffersQueried {
+    pub user: Pubkey,
+    pub start_id: u64,
+    /// Offer PDAs for consecutive IDs from `start_id`
+    pub offers: Vec<Pubkey>,
+}
+
+#[event]
+pub struct OfferInspected {
+    pub offer: Pubkey,
+    pub offer_id: u64,
+    pub amount_offered: u64,
+    pub vault_amount: u64,
+    /// Whether the vault holds exactly the recorded escrow
+    pub balanced: bool,
+}
+
+#[event]
+pub struct QuoteComputed {
+    pub offer: Pubkey,
+    pub offer_id: u64,
+    pub amount_in: u64,
+    pub amount_out: u64,
+    pub fee: u64,
+    pub taker_fee: u64,
+    pub referral: u64,
+    pub royalty: u64,
+    pub maker_proceeds: u64,
+}
+
+#[event]
+pub struct FeesWithdrawn {
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub destination: Pubkey,
+    pub admin: Pubkey,
+    pub total_withdrawn: u64,
+}
+
+#[event]
+pub struct ValidationResult {
+    pub offer: Pubkey,
+    pub offer_id: u64,
+    /// Bitwise OR of the flags below for every invariant that failed, 0 when
+    /// the offer is healthy
+    pub failures: u16,
+}
+
+impl ValidationResult {
+    /// The vault isn't the one derived for the offer
+    pub const VAULT_ADDRESS: u16 = 1 << 0;
+    /// The vault holds another mint than the offered one
+    pub const VAULT_MINT: u16 = 1 << 1;
+    /// The vault isn't its own authority
+    pub const VAULT_AUTHORITY: u16 = 1 << 2;
+    /// The vault holds less than the recorded escrow
+    pub const VAULT_BALANCE: u16 = 1 << 3;
+    /// The stored offer bump isn't the canonical one
+    pub const OFFER_BUMP: u16 = 1 << 4;
+    /// The stored vault bump isn't the canonical one
+    pub const VAULT_BUMP: u16 = 1 << 5;
+    /// The offered or wanted amount is zero
+    pub const AMOUNTS: u16 = 1 << 6;
+    /// The maker account passed in isn't the offer's maker
+    pub const MAKER: u16 = 1 << 7;
+}
+
+#[event]
+pub struct OfferPauseChanged {
+    pub offer: Pubkey,
+    pub maker: Pubkey,
+    pub offer_id: u64,
+    pub paused: bool,
+}
+
+#[event]
+pub struct OfferBookmarked {
+    pub offer: Pubkey,
+    pub user: Pubkey,
+}
+
+#[event]
+pub struct BatchAccepted {
+    pub taker: Pubkey,
+    pub offers_filled: u8,
+    /// Sum of `amount_in` across the fills, in each offer's wanted mint
+    pub total_amount_in: u64,
+    /// Sum of `amount_out` across the fills, in each offer's offered mint
+    pub total_amount_out: u64,
 }
 
 // ============================================================================
@@ -427,4 +11672,370 @@ pub enum ErrorCode {
 
     #[msg("User profile must be initialized first")]
     UninitializedUserProfile,
+
+    #[msg("Cannot withdraw the entire escrow - use cancel_offer instead")]
+    CannotDrainOffer,
+
+    #[msg("Offer ID does not match the offer account")]
+    InvalidOfferId,
+
+    #[msg("Vault balance does not match the escrowed amount")]
+    VaultBalanceMismatch,
+
+    #[msg("Expiry must be in the future and within the maximum offer lifetime")]
+    InvalidExpiry,
+
+    #[msg("Offer has expired")]
+    OfferExpired,
+
+    #[msg("Fee basis points must not exceed 10000")]
+    InvalidFeeBps,
+
+    #[msg("Config account is not owned by this program")]
+    InvalidConfig,
+
+    #[msg("Treasury token account is required when a protocol fee applies")]
+    MissingTreasuryAccount,
+
+    #[msg("Offer does not allow partial fills")]
+    PartialFillNotAllowed,
+
+    #[msg("Amount is below the configured minimum")]
+    BelowMinimum,
+
+    #[msg("Offer category is out of range")]
+    InvalidCategory,
+
+    #[msg("Arithmetic overflow in amount calculation")]
+    MathOverflow,
+
+    #[msg("Maker's token account for the wanted mint is frozen")]
+    MakerAccountUnavailable,
+
+    #[msg("Oracle price is older than the allowed staleness")]
+    StaleOracle,
+
+    #[msg("Oracle price account is missing or invalid")]
+    InvalidOracle,
+
+    #[msg("Offered mint would leave the escrow vault frozen")]
+    FrozenMintUnsupported,
+
+    #[msg("Offer is reserved by another taker")]
+    OfferReserved,
+
+    #[msg("Reservation duration must be positive and within the maximum")]
+    InvalidReservation,
+
+    #[msg("Offer account still exists - use cancel_offer instead")]
+    OfferStillOpen,
+
+    #[msg("Offer created too soon after the previous one")]
+    RateLimited,
+
+    #[msg("Too many offers in one batch")]
+    BatchTooLarge,
+
+    #[msg("Offered mint does not match the offer")]
+    WrongOfferedMint,
+
+    #[msg("Wanted mint does not match the offer")]
+    WrongWantedMint,
+
+    #[msg("Only the maker may cancel during the grace period after expiry")]
+    GracePeriodActive,
+
+    #[msg("Missing or invalid maker signature")]
+    InvalidSignature,
+
+    #[msg("Offer price deviates too far from the expected price")]
+    PriceOutOfTolerance,
+
+    #[msg("Offer is not active yet")]
+    OfferNotYetActive,
+
+    #[msg("Treasury account must differ from the maker and taker accounts")]
+    InvalidFeeRoute,
+
+    #[msg("Referrer's associated token account is required")]
+    MissingReferrerAccount,
+
+    #[msg("Too many tokens in one bundle")]
+    BundleTooLarge,
+
+    #[msg("Accounts don't match the bundle's tokens")]
+    InvalidBundle,
+
+    #[msg("Offer has reached its maximum number of fills")]
+    MaxFillsReached,
+
+    #[msg("Memo is too long")]
+    MemoTooLong,
+
+    #[msg("Memo program account is required to attach a memo")]
+    MissingMemoProgram,
+
+    #[msg("Cancelled too soon after the previous cancel")]
+    CancelCooldown,
+
+    #[msg("No matching counter-offer for this taker")]
+    NoActiveCounter,
+
+    #[msg("Only dust remains; the whole remainder must be taken")]
+    DustRemainder,
+
+    #[msg("Offer's remainder is not below its dust threshold")]
+    OfferNotDust,
+
+    #[msg("Offer doesn't accept payment in this mint")]
+    MintNotAccepted,
+
+    #[msg("Quotes must be 1 to 4 distinct mints, led by mint_wanted, with non-zero amounts")]
+    InvalidQuotes,
+
+    #[msg("Fee tiers must be at most 4, with strictly ascending thresholds")]
+    InvalidFeeTiers,
+
+    #[msg("Maker is not approved to list offers")]
+    MakerNotApproved,
+
+    #[msg("Maker revoked the delegate approval or no longer holds the offered tokens")]
+    DelegateRevoked,
+
+    #[msg("Bundle accounts are not in the order of the stored items")]
+    BundleOrderMismatch,
+
+    #[msg("Royalty, referral and protocol fees exceed the wanted amount")]
+    FeesExceedTotal,
+
+    #[msg("Royalty recipient's token account is missing or incorrect")]
+    MissingRoyaltyAccount,
+
+    #[msg("Taker is blocked by the maker")]
+    TakerBlocked,
+
+    #[msg("Too many blocked takers")]
+    BlocklistTooLarge,
+
+    #[msg("Offer has not expired yet")]
+    NotExpiredYet,
+
+    #[msg("Offered and wanted mints must differ")]
+    SameMint,
+
+    #[msg("Clock is earlier than the user's previous offer")]
+    ClockWentBackwards,
+
+    #[msg("Offer no longer holds the expected amount")]
+    FillOrKillFailed,
+
+    #[msg("Offer is sealed until it expires")]
+    OfferSealed,
+
+    #[msg("User already has the maximum number of open offers")]
+    TooManyOpenOffers,
+
+    #[msg("Counter would drop below zero")]
+    CounterUnderflow,
+
+    #[msg("Taker has too few completed swaps for this offer")]
+    InsufficientReputation,
+
+    #[msg("Offer can only be taken with the maker's authorization")]
+    AuthorizationRequired,
+
+    #[msg("Authorization is for a different offer or taker")]
+    InvalidAuthorization,
+
+    #[msg("Authorization is outside its validity window")]
+    AuthorizationExpired,
+
+    #[msg("Offers do not mirror each other at compatible amounts")]
+    OffersNotCompatible,
+
+    #[msg("User profile still has open offers")]
+    OpenOffersRemaining,
+
+    #[msg("Maker profile still exists; use cancel_offer")]
+    ProfileStillOpen,
+
+    #[msg("AMM pool account is invalid")]
+    InvalidPool,
+
+    #[msg("AMM pool has no liquidity")]
+    EmptyPool,
+
+    #[msg("AMM pool reserves are too old")]
+    StalePool,
+
+    #[msg("Stored bump is not the canonical one")]
+    InvalidBump,
+
+    #[msg("Offer has no fill queue")]
+    FillQueueDisabled,
+
+    #[msg("Offer fill queue is full")]
+    FillQueueFull,
+
+    #[msg("Fill queue exceeds the maximum length")]
+    FillQueueTooLong,
+
+    #[msg("Offer is too recent for an emergency withdrawal")]
+    EscrowNotStale,
+
+    #[msg("User profile account does not match its authority")]
+    InvalidUserProfile,
+
+    #[msg("User profile already exists")]
+    ProfileAlreadyExists,
+
+    #[msg("Fee-token account required in fee-token mode")]
+    MissingFeeTokenAccount,
+
+    #[msg("Amount offered exceeds the mint supply")]
+    ExceedsMintSupply,
+
+    #[msg("Counter-offer does not beat the pending counters")]
+    CounterTooLow,
+
+    #[msg("Vesting offers are only taken through accept_with_vesting")]
+    VestingMismatch,
+
+    #[msg("Vesting claim or vault account missing or not the expected PDA")]
+    MissingVestingAccount,
+
+    #[msg("Vesting cliff not reached yet")]
+    CliffNotReached,
+
+    #[msg("Vesting cliff out of range")]
+    InvalidVestingCliff,
+
+    #[msg("Fill window must be non-empty and fit in its period")]
+    InvalidFillWindow,
+
+    #[msg("Offer cannot be taken outside its fill window")]
+    OutsideFillWindow,
+
+    #[msg("Offer is paused by its owner")]
+    OfferPausedByMaker,
+
+    #[msg("System program or sysvar account is not the canonical one")]
+    InvalidSysvar,
+
+    #[msg("Offer is not a request for quote")]
+    NotARequest,
+
+    #[msg("Reservation deposit is not forfeited before its settlement deadline")]
+    DepositNotForfeited,
+
+    #[msg("Accepts are suspended during a declared emergency")]
+    EmergencyDeclared,
+
+    #[msg("No emergency is declared")]
+    NoEmergency,
+
+    #[msg("The same token account was passed for both sides of the swap")]
+    DuplicateAccount,
+
+    #[msg("Cancel lock must end in the future and within the maximum")]
+    InvalidCancelLock,
+
+    #[msg("Maker committed to not cancelling the offer yet")]
+    CancelLocked,
+
+    #[msg("Receive account is not the beneficiary's token account for the offered mint")]
+    InvalidBeneficiary,
+
+    #[msg("Timestamp arithmetic overflowed")]
+    TimestampOverflow,
+
+    #[msg("Auto-relist offers are only taken in full through accept_and_relist")]
+    AutoRelistOffer,
+
+    #[msg("Offer has no auto-relist terms")]
+    NotAutoRelist,
+
+    #[msg("Mint is not on the allowlist")]
+    MintNotAllowed,
+
+    #[msg("Batch likely exceeds the compute budget; use smaller batches")]
+    ComputeBudgetRisk,
+
+    #[msg("Partial fills must pay a whole number of lots")]
+    InvalidLotSize,
+
+    #[msg("Offer already belongs to this maker")]
+    SameMaker,
+
+    #[msg("Acceptor cap exceeds the maximum")]
+    AcceptorCapTooLarge,
+
+    #[msg("Offer has reached its cap on distinct takers")]
+    AcceptorCapReached,
+
+    #[msg("Trading of this mint is paused")]
+    MintPaused,
+
+    #[msg("Offer can only be filled against a price attestation")]
+    AttestationRequired,
+
+    #[msg("Price attestation does not match the offer")]
+    InvalidAttestation,
+
+    #[msg("Price attestation is too old")]
+    StaleAttestation,
+
+    #[msg("Maker's wanted-mint account is not frozen")]
+    AccountNotFrozen,
+
+    #[msg("Price is below the maker's floor")]
+    BelowMakerFloor,
+
+    #[msg("Taker does not hold the offer's gate token")]
+    GateNotSatisfied,
+
+    #[msg("Offer still escrows a taker rebate")]
+    TakerRebateEscrowed,
+
+    #[msg("Account is already initialized")]
+    AlreadyInitialized,
+
+    #[msg("Offer snapshot exceeds the return data limit")]
+    SnapshotTooLarge,
+
+    #[msg("Offer would push the mint's locked value over the cap")]
+    TvlCapExceeded,
+
+    #[msg("Index weights must be nonzero and sum to 10000")]
+    InvalidIndexWeights,
+
+    #[msg("Index offers are taken through accept_index_offer")]
+    IndexFeedsRequired,
+
+    #[msg("Offer is not priced from an index")]
+    NotIndexOffer,
+
+    #[msg("Vault holds nothing beyond the offer's escrow")]
+    NoExcessFunds,
+
+    #[msg("Excess in a shared vault cannot be attributed to one offer")]
+    SharedVault,
+
+    #[msg("Offer must be committed to by this taker before it is filled")]
+    AcceptNotCommitted,
+
+    #[msg("The taker's commit on the offer has lapsed")]
+    CommitExpired,
+
+    #[msg("Offer has taken its most partial fills; only the remainder may be taken")]
+    FillCountExhausted,
+
+    #[msg("An offer already exists at this offer ID")]
+    SlotInUse,
+
+    #[msg("Offer already has the current layout version")]
+    OfferUpToDate,
+
+    #[msg("A Token-2022 wanted mint needs the Token-2022 program as wanted_token_program")]
+    MissingWantedTokenProgram,
 }